@@ -1,7 +1,6 @@
 use bevy::{app::Plugin,  prelude::*, tasks::{block_on, }};
-use bevy_rapier3d::prelude::Collider;
 
-use crate::{noise::perlin::Perlin, player::{camera_controller::CameraController,}, simulation::world::WorldState, terrain::{chunks::{Chunkbase, RenderDistance, RenderedChunks}, grid::{ChunkRadius, }}};
+use crate::{noise::{erosion::Erosion, heightmap_backend::HeightmapBackend}, player::{camera_controller::CameraController, player::SPAWN_POSITION}, simulation::world::WorldState, terrain::{biomes::BiomeMap, chunks::{Chunkbase, RenderDistance, RenderedChunks, CHUNK_CACHE_CAPACITY, CHUNK_HEIGHT, CHUNK_WIDTH}, grid::{ChunkRadius, }, edits::{TerrainEdits, TERRAIN_EDITS_PATH}, region::{RegionCache, REGION_DIRECTORY}, terrain_config::TerrainGenConfig, water::LakeFinder}};
 
 #[derive(Component)]
 pub struct DebugText;
@@ -15,19 +14,52 @@ pub struct Init;
 
 impl Plugin for Init {
     fn build(&self, app: &mut bevy::app::App) {
+        let config = TerrainGenConfig::load_or_default();
+
         app
             .insert_resource(ChunkRadius::default())
             .insert_resource(RenderDistance(16))
             .insert_resource(RenderedChunks::default())
-            .insert_resource(block_on(Perlin::new(1, 0.001)).unwrap())
+            .insert_resource(block_on(HeightmapBackend::new(&config)).unwrap())
+            .insert_resource(config)
             .insert_resource(WorldState::default())
             .add_systems(Startup, (init_resources, setup_scene))
             .add_systems(Update, update_compass);
     }
 }
 
-fn init_resources(mut commands: Commands, perlin: Res<Perlin>) {
-    let chunkbase: Chunkbase = Chunkbase::new(&perlin);
+fn init_resources(mut commands: Commands, backend: Res<HeightmapBackend>, config: Res<TerrainGenConfig>, mut meshes: ResMut<Assets<Mesh>>) {
+    let chunkbase: Chunkbase = match RegionCache::open(REGION_DIRECTORY, &config) {
+        Ok(cache) => Chunkbase::new(backend.clone(), CHUNK_CACHE_CAPACITY).with_region_cache(cache),
+        Err(e) => {
+            warn!("Running without the region cache, could not open {REGION_DIRECTORY}: {e}");
+            Chunkbase::new(backend.clone(), CHUNK_CACHE_CAPACITY)
+        }
+    };
+
+    let edits = if std::path::Path::new(TERRAIN_EDITS_PATH).exists() {
+        TerrainEdits::load(TERRAIN_EDITS_PATH).unwrap_or_else(|e| {
+            warn!("Starting without terrain edits, could not read {TERRAIN_EDITS_PATH}: {e}");
+            TerrainEdits::default()
+        })
+    } else {
+        TerrainEdits::default()
+    };
+    let mut chunkbase = chunkbase.with_edits(edits).with_biomes(BiomeMap::new(&config)).with_lakes(LakeFinder::new(&config));
+
+    if config.erosion.enabled {
+        chunkbase = chunkbase.with_erosion(Erosion::new(&config, &backend));
+    }
+
+    //Generate the ground under the player up front so it doesn't fall through before the first chunks stream in
+    let spawn_x = (SPAWN_POSITION.x / CHUNK_WIDTH as f32).floor() as i32;
+    let spawn_y = (SPAWN_POSITION.z / CHUNK_HEIGHT as f32).floor() as i32;
+    for y in spawn_y - 1..=spawn_y + 1 {
+        for x in spawn_x - 1..=spawn_x + 1 {
+            chunkbase.generate_blocking((x, y), &mut meshes);
+            chunkbase.generate_collider_blocking((x, y), config.colliders.near_step);
+        }
+    }
 
     //commands.spawn(Collider::cuboid(8192., 0., 8192.));
     commands.insert_resource(chunkbase);
@@ -76,7 +108,7 @@ fn update_compass(mut compass_query: Query<&mut Text, With<Compass>>, camera_que
     };
 
     compass.clear();
-    compass.push_str(format!("{yaw}").as_str()); 
+    compass.push_str(&yaw); 
 }
 
 
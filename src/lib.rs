@@ -1,6 +1,11 @@
+//Modules named like their parent, e.g. `player::player`, are how this crate is laid out
+#![allow(clippy::module_inception)]
+
 pub mod init;
 
 pub mod noise {
+    pub mod erosion;
+    pub mod heightmap_backend;
     pub mod perlin;
     pub mod perlin_cpu;
     pub mod poisson_disc;
@@ -34,8 +39,16 @@ pub mod terrain {
         }
     }
     pub mod grid;
+    pub mod biomes;
     pub mod chunks;
+    pub mod clipmap;
     pub mod collision;
+    pub mod edits;
+    pub mod material;
+    pub mod occlusion;
+    pub mod region;
+    pub mod terrain_config;
+    pub mod water;
 }
 
 pub mod simulation {
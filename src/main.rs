@@ -1,7 +1,7 @@
 use bevy::{diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin}, log::tracing_subscriber};
 use bevy::prelude::*;
-use bevy_rapier3d::{plugin::{NoUserData, RapierPhysicsPlugin}, prelude::{Collider, KinematicCharacterController}, render::RapierDebugRenderPlugin};
-use terrain::{init::{DebugText, Init}, noise::perlin::Perlin, player::{cursor::CursorPlugin, inventory::inventory::InventoryPlugin, player::{Player, PlayerPlugin}}, simulation::{sun::DaylightCyclePlugin, world::BallisticsPlugin}, terrain::{chunks::{Chunkbase, RenderDistance, RenderedChunks}, grid::{ChunkRadius, CurrentChunk, GridPlugin}, props::props::PropPlugin}};
+use bevy_rapier3d::{plugin::{NoUserData, RapierPhysicsPlugin}, prelude::KinematicCharacterController, render::RapierDebugRenderPlugin};
+use terrain::{init::{DebugText, Init}, player::{cursor::CursorPlugin, inventory::inventory::InventoryPlugin, player::{Player, PlayerPlugin}}, simulation::{sun::DaylightCyclePlugin, world::{BallisticsPlugin, WorldState}}, terrain::{chunks::{Chunkbase, RenderedChunks}, clipmap::ClipmapPlugin, edits::TerrainEditsPlugin, grid::{GridPlugin, StreamingQueue}, props::props::PropPlugin, water::WaterPlugin}};
 
 
 
@@ -14,6 +14,8 @@ fn main() {
         .add_plugins(Init)
         .add_plugins(PlayerPlugin)
         .add_plugins(GridPlugin)
+        .add_plugins(ClipmapPlugin)
+        .add_plugins(TerrainEditsPlugin)
         .add_plugins(BallisticsPlugin)
         .add_plugins(InventoryPlugin)
         .add_plugins(DaylightCyclePlugin)
@@ -22,6 +24,7 @@ fn main() {
         .add_plugins(RapierDebugRenderPlugin::default())
         .add_plugins(CursorPlugin)
         .add_plugins(PropPlugin)
+        .add_plugins(WaterPlugin)
         .add_systems(Update, debug)
         .run();
 }
@@ -29,6 +32,9 @@ fn main() {
 fn debug(
     player_query: Query<(&Player, &Transform, &KinematicCharacterController)>, 
     chunks: Res<RenderedChunks>,
+    queue: Res<StreamingQueue>,
+    chunkbase: Res<Chunkbase>,
+    world_state: Res<WorldState>,
     diagnostics: Res<DiagnosticsStore>,
     mut text_query: Query<&mut Text, With<DebugText>>,
 ) {
@@ -38,12 +44,21 @@ fn debug(
     let z = transform.translation.z;
 
     let mut text = text_query.single_mut().unwrap();
+    let ground = chunkbase.height_at(x, z).unwrap_or_default();
+    let slope = chunkbase.slope_at(x, z).unwrap_or_default().to_degrees();
+    let biome = chunkbase.biome_at(x, z).map(|biome| format!("{biome:?}")).unwrap_or_default();
+    let temperature = world_state.temperature();
+    let (loads, spawns, despawns, colliders) = queue.depth();
+    let generating = chunkbase.pending();
     let fps = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS).and_then(|d| d.average()).unwrap_or_default() as usize;
 
     text.clear();
     text.push_str(&format!("
         X: {x} Y: {y} Z: {z}\n
+        Ground: {ground:.1} Slope: {slope:.0}°\n
+        Biome: {biome} Temperature: {temperature:.1}°C\n
         FPS: {fps}
-        Current chunks: {:?}\n",
+        Current chunks: {:?}\n
+        Streaming: {loads} to load, {generating} generating, {spawns} to spawn, {despawns} to despawn, {colliders} colliders\n",
     chunks.0.len()));
 }
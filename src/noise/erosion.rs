@@ -0,0 +1,529 @@
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicI32, Ordering}, Arc, Mutex}};
+
+use bevy::tasks::block_on;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use wgpu::{util::{BufferInitDescriptor, DeviceExt}, wgt::PollType, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Device, PipelineCompilationOptions, Queue};
+
+use crate::{noise::heightmap_backend::HeightmapBackend, terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH}, terrain_config::{ErosionConfig, TerrainGenConfig}}};
+
+const DROPLET_WORKGROUP_SIZE: u32 = 64;
+const WORKGROUP_SIZE: u32 = 8;
+/// `dispatch_workgroups` is limited to 65535 groups per dimension.
+const MAX_BATCH_DROPLETS: u32 = 65535 * DROPLET_WORKGROUP_SIZE;
+
+/// Height changes are summed as integers in 1/65536ths of a metre, so the result doesn't
+/// depend on the order droplets run in.
+const FIXED_POINT: f32 = 65536.0;
+
+/// Eroded regions kept in memory so every chunk of a region doesn't erode it again.
+/// Raised on demand so the regions under the current render area always fit.
+const REGIONS_IN_MEMORY: usize = 16;
+
+/// A region's heights once eroded. The lock is async so chunks waiting for the region
+/// don't hold a thread of the task pool.
+type RegionSlot = Arc<futures_intrusive::sync::Mutex<Option<Arc<Vec<f32>>>>>;
+
+/// Regions eroded or being eroded with the tick they were last used at.
+struct RegionMemory {
+    slots: HashMap<(i32, i32), (RegionSlot, u64)>,
+    tick: u64,
+    capacity: usize,
+}
+
+/// Hydraulic and thermal erosion over heightmaps from `HeightmapBackend`. Runs as a
+/// compute shader on the device of a GPU backend and on rayon otherwise, both giving the
+/// same result for the same seed every time.
+pub struct Erosion {
+    config: ErosionConfig,
+    seed: u64,
+    backend: HeightmapBackend,
+    gpu: Option<ErosionGpu>,
+    regions: Mutex<RegionMemory>,
+}
+
+struct ErosionGpu {
+    device: Device,
+    queue: Queue,
+    bind_group_layout: BindGroupLayout,
+    droplets: ComputePipeline,
+    thermal: ComputePipeline,
+    apply: ComputePipeline,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    width: u32,
+    height: u32,
+    seed: u32,
+    droplet_offset: u32,
+    droplet_count: u32,
+    max_lifetime: u32,
+    inertia: f32,
+    sediment_capacity: f32,
+    min_capacity: f32,
+    deposition: f32,
+    erosion: f32,
+    evaporation: f32,
+    gravity: f32,
+    talus: f32,
+    thermal_rate: f32,
+    _padding: u32,
+}
+
+impl Erosion {
+    pub fn new(config: &TerrainGenConfig, backend: &HeightmapBackend) -> Self {
+        let gpu = match backend {
+            HeightmapBackend::Gpu(perlin) => {
+                let (device, queue) = perlin.device();
+                Some(ErosionGpu::new(device, queue))
+            }
+            HeightmapBackend::Cpu(_) => None,
+        };
+
+        Erosion {
+            config: config.erosion,
+            seed: config.seed,
+            backend: backend.clone(),
+            gpu,
+            regions: Mutex::new(RegionMemory { slots: HashMap::new(), tick: 0, capacity: REGIONS_IN_MEMORY }),
+        }
+    }
+
+    /// Grows the regions kept in memory so all regions under `chunks` fit.
+    pub fn reserve(&self, chunks: &HashSet<(i32, i32)>) {
+        let size = self.config.region_size as i32;
+        let regions: HashSet<(i32, i32)> = chunks.iter().map(|(x, y)| (x.div_euclid(size), y.div_euclid(size))).collect();
+        let mut memory = self.regions.lock().unwrap();
+        memory.capacity = memory.capacity.max(regions.len());
+    }
+
+    /// Erodes a whole `width` x `height` row-major heightmap in place, e.g. the full map.
+    pub fn erode_map(&self, heights: &mut [f32], width: usize, height: usize) -> anyhow::Result<()> {
+        block_on(self.erode(heights, width, height, pcg(self.seed as u32 ^ pcg((self.seed >> 32) as u32))))
+    }
+
+    /// Heights for one chunk plus its halo row and column, cut out of its eroded region.
+    pub async fn compute_chunk(&self, (x, y): (i32, i32)) -> anyhow::Result<Vec<f32>> {
+        let size = self.config.region_size as i32;
+        let (region_x, region_y) = (x.div_euclid(size), y.div_euclid(size));
+        let region = self.eroded_region((region_x, region_y)).await?;
+
+        let stride = size as usize * CHUNK_WIDTH + 1;
+        let left = (x - region_x * size) as usize * CHUNK_WIDTH;
+        let top = (y - region_y * size) as usize * CHUNK_HEIGHT;
+
+        Ok((top..=top + CHUNK_HEIGHT)
+            .flat_map(|row| region[row * stride + left..=row * stride + left + CHUNK_WIDTH].iter().copied())
+            .collect())
+    }
+
+    /// Heights of a region including the row and column shared with the next regions,
+    /// eroded once and then served from memory.
+    async fn eroded_region(&self, coordinates: (i32, i32)) -> anyhow::Result<Arc<Vec<f32>>> {
+        let slot = self.region_slot(coordinates);
+
+        //Chunks of a region being eroded await it here instead of eroding it too
+        let mut slot = slot.lock().await;
+        if let Some(heights) = &*slot {
+            return Ok(heights.clone());
+        }
+
+        let size = self.config.region_size as usize;
+        let (core_width, core_height) = (size * CHUNK_WIDTH, size * CHUNK_HEIGHT);
+        let padding = self.config.padding as usize;
+        let (width, height) = (core_width + 1 + 2 * padding, core_height + 1 + 2 * padding);
+        let origin = (
+            coordinates.0 * core_width as i32 - padding as i32,
+            coordinates.1 * core_height as i32 - padding as i32,
+        );
+
+        let raw = self.backend.compute_region(origin, (width as u32, height as u32)).await?;
+        let mut eroded = raw.clone();
+        self.erode(&mut eroded, width, height, region_seed(self.seed, coordinates)).await?;
+
+        //Samples on the border are left as generated, so they match the neighbouring region
+        let fade = self.config.border_fade.max(1) as f32;
+        let region: Vec<f32> = (0..=core_height)
+            .flat_map(|y| (0..=core_width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let index = (y + padding) * width + x + padding;
+                let border = x.min(core_width - x).min(y).min(core_height - y) as f32;
+                let t = (border / fade).min(1.0);
+                raw[index] + t * t * (3.0 - 2.0 * t) * (eroded[index] - raw[index])
+            })
+            .collect();
+
+        let region = Arc::new(region);
+        *slot = Some(region.clone());
+        Ok(region)
+    }
+
+    /// The slot of `coordinates`, adding it and forgetting the least recently used region
+    /// if needed. A forgotten region still being eroded finishes for the chunks awaiting it.
+    fn region_slot(&self, coordinates: (i32, i32)) -> RegionSlot {
+        let mut memory = self.regions.lock().unwrap();
+        memory.tick += 1;
+        let tick = memory.tick;
+
+        if let Some((slot, last_used)) = memory.slots.get_mut(&coordinates) {
+            *last_used = tick;
+            return slot.clone();
+        }
+
+        if memory.slots.len() >= memory.capacity {
+            let oldest = memory.slots.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(region, _)| *region);
+            if let Some(oldest) = oldest {
+                memory.slots.remove(&oldest);
+            }
+        }
+
+        let slot = Arc::new(futures_intrusive::sync::Mutex::new(None, false));
+        memory.slots.insert(coordinates, (slot.clone(), tick));
+        slot
+    }
+
+    async fn erode(&self, heights: &mut [f32], width: usize, height: usize, seed: u32) -> anyhow::Result<()> {
+        assert_eq!(heights.len(), width * height, "heightmap is not {width} x {height}");
+        let params = self.params(width, height, seed);
+
+        match &self.gpu {
+            Some(gpu) => gpu.erode(heights, params, &self.config).await,
+            None => {
+                erode_cpu(heights, params, &self.config);
+                Ok(())
+            }
+        }
+    }
+
+    fn params(&self, width: usize, height: usize, seed: u32) -> Params {
+        let hydraulic = self.config.hydraulic;
+        Params {
+            width: width as u32,
+            height: height as u32,
+            seed,
+            droplet_offset: 0,
+            droplet_count: 0,
+            max_lifetime: hydraulic.max_lifetime,
+            inertia: hydraulic.inertia,
+            sediment_capacity: hydraulic.sediment_capacity,
+            min_capacity: hydraulic.min_capacity,
+            deposition: hydraulic.deposition,
+            erosion: hydraulic.erosion,
+            evaporation: hydraulic.evaporation,
+            gravity: hydraulic.gravity,
+            talus: self.config.thermal.talus,
+            thermal_rate: self.config.thermal.rate,
+            _padding: 0,
+        }
+    }
+}
+
+/// `(offset, count)` of every droplet batch.
+fn batches(params: &Params, config: &ErosionConfig) -> Vec<(u32, u32)> {
+    let total = (config.hydraulic.droplets_per_sample * (params.width * params.height) as f32) as u32;
+    let batch = total.div_ceil(config.hydraulic.batches.max(1)).clamp(1, MAX_BATCH_DROPLETS);
+
+    (0..total).step_by(batch as usize).map(|offset| (offset, batch.min(total - offset))).collect()
+}
+
+fn region_seed(seed: u64, (x, y): (i32, i32)) -> u32 {
+    pcg(seed as u32 ^ pcg((seed >> 32) as u32 ^ pcg(x as u32 ^ pcg(y as u32))))
+}
+
+/// PCG hash, identical to `pcg` in `erosion.wgsl`.
+fn pcg(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Uniform in `[0, 1)`, identical to `random` in `erosion.wgsl`.
+fn random(value: u32) -> f32 {
+    (pcg(value) >> 8) as f32 / 16777216.0
+}
+
+fn fixed(amount: f32) -> i32 {
+    (amount * FIXED_POINT).round_ties_even() as i32
+}
+
+fn erode_cpu(heights: &mut [f32], mut params: Params, config: &ErosionConfig) {
+    let deltas: Vec<AtomicI32> = (0..heights.len()).map(|_| AtomicI32::new(0)).collect();
+
+    for (offset, count) in batches(&params, config) {
+        params.droplet_offset = offset;
+        params.droplet_count = count;
+
+        let frozen: &[f32] = heights;
+        (0..count).into_par_iter().for_each(|index| droplet(frozen, &deltas, &params, offset + index));
+        apply(heights, &deltas);
+    }
+
+    for _ in 0..config.thermal.iterations {
+        let frozen: &[f32] = heights;
+        (0..heights.len()).into_par_iter().for_each(|index| thermal(frozen, &deltas, &params, index));
+        apply(heights, &deltas);
+    }
+}
+
+fn apply(heights: &mut [f32], deltas: &[AtomicI32]) {
+    for (height, delta) in heights.iter_mut().zip(deltas) {
+        *height += delta.swap(0, Ordering::Relaxed) as f32 / FIXED_POINT;
+    }
+}
+
+/// Gradient and height, bilinearly interpolated.
+fn height_and_gradient(heights: &[f32], width: usize, (x, y): (f32, f32)) -> (f32, f32, f32) {
+    let (cell_x, cell_y) = (x as usize, y as usize);
+    let (offset_x, offset_y) = (x - cell_x as f32, y - cell_y as f32);
+
+    let index = cell_y * width + cell_x;
+    let (h00, h10, h01, h11) = (heights[index], heights[index + 1], heights[index + width], heights[index + width + 1]);
+
+    let gradient_x = (h10 - h00) * (1.0 - offset_y) + (h11 - h01) * offset_y;
+    let gradient_y = (h01 - h00) * (1.0 - offset_x) + (h11 - h10) * offset_x;
+    let value = h00 * (1.0 - offset_x) * (1.0 - offset_y) + h10 * offset_x * (1.0 - offset_y) + h01 * (1.0 - offset_x) * offset_y + h11 * offset_x * offset_y;
+
+    (gradient_x, gradient_y, value)
+}
+
+/// Spreads `amount` over the corners of a cell, negative to erode.
+fn deposit(deltas: &[AtomicI32], width: usize, (cell_x, cell_y): (usize, usize), (offset_x, offset_y): (f32, f32), amount: f32) {
+    let index = cell_y * width + cell_x;
+    deltas[index].fetch_add(fixed(amount * (1.0 - offset_x) * (1.0 - offset_y)), Ordering::Relaxed);
+    deltas[index + 1].fetch_add(fixed(amount * offset_x * (1.0 - offset_y)), Ordering::Relaxed);
+    deltas[index + width].fetch_add(fixed(amount * (1.0 - offset_x) * offset_y), Ordering::Relaxed);
+    deltas[index + width + 1].fetch_add(fixed(amount * offset_x * offset_y), Ordering::Relaxed);
+}
+
+/// One droplet of `droplets` in `erosion.wgsl`.
+fn droplet(heights: &[f32], deltas: &[AtomicI32], params: &Params, index: u32) {
+    let width = params.width as usize;
+    let limit = ((params.width - 1) as f32, (params.height - 1) as f32);
+    let mut position = (
+        random(params.seed ^ pcg(index.wrapping_mul(2))) * limit.0,
+        random(params.seed ^ pcg(index.wrapping_mul(2).wrapping_add(1))) * limit.1,
+    );
+    let mut direction = (0.0, 0.0);
+    let (mut speed, mut water, mut sediment) = (1.0_f32, 1.0_f32, 0.0_f32);
+
+    for _ in 0..params.max_lifetime {
+        let cell = (position.0 as usize, position.1 as usize);
+        let offset = (position.0 - cell.0 as f32, position.1 - cell.1 as f32);
+        let (gradient_x, gradient_y, here) = height_and_gradient(heights, width, position);
+
+        direction = (
+            direction.0 * params.inertia - gradient_x * (1.0 - params.inertia),
+            direction.1 * params.inertia - gradient_y * (1.0 - params.inertia),
+        );
+        let norm = (direction.0 * direction.0 + direction.1 * direction.1).sqrt();
+        if norm < 1e-6 {
+            break;
+        }
+        direction = (direction.0 / norm, direction.1 / norm);
+        position = (position.0 + direction.0, position.1 + direction.1);
+
+        if position.0 < 0.0 || position.1 < 0.0 || position.0 >= limit.0 || position.1 >= limit.1 {
+            break;
+        }
+
+        let delta_height = height_and_gradient(heights, width, position).2 - here;
+        let capacity = (-delta_height * speed * water * params.sediment_capacity).max(params.min_capacity);
+
+        if delta_height > 0.0 {
+            //The heights don't change under a droplet, so one that turned uphill would slosh
+            //in the same pit forever. It fills the pit and stops instead
+            deposit(deltas, width, cell, offset, delta_height.min(sediment));
+            break;
+        } else if sediment > capacity {
+            let amount = (sediment - capacity) * params.deposition;
+            sediment -= amount;
+            deposit(deltas, width, cell, offset, amount);
+        } else {
+            let amount = ((capacity - sediment) * params.erosion).min(-delta_height);
+            sediment += amount;
+            deposit(deltas, width, cell, offset, -amount);
+        }
+
+        speed = (speed * speed - delta_height * params.gravity).max(0.0).sqrt();
+        water *= 1.0 - params.evaporation;
+    }
+}
+
+/// Material exchanged with a neighbour `difference` metres higher, positive when it flows in.
+fn slump(difference: f32, params: &Params) -> f32 {
+    let excess = difference.abs() - params.talus;
+    if excess > 0.0 { difference.signum() * excess * params.thermal_rate * 0.5 } else { 0.0 }
+}
+
+/// One sample of `thermal` in `erosion.wgsl`.
+fn thermal(heights: &[f32], deltas: &[AtomicI32], params: &Params, index: usize) {
+    let width = params.width as usize;
+    let (x, y) = (index % width, index / width);
+    let here = heights[index];
+
+    let mut change = 0.0;
+    if x > 0 {
+        change += slump(heights[index - 1] - here, params);
+    }
+    if x + 1 < width {
+        change += slump(heights[index + 1] - here, params);
+    }
+    if y > 0 {
+        change += slump(heights[index - width] - here, params);
+    }
+    if y + 1 < params.height as usize {
+        change += slump(heights[index + width] - here, params);
+    }
+
+    deltas[index].store(fixed(change), Ordering::Relaxed);
+}
+
+impl ErosionGpu {
+    fn new(device: &Device, queue: &Queue) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("erosion"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("erosion.wgsl").into()),
+        });
+
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ErosionBindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1),
+                storage(2),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ErosionPipelineLayout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = |entry_point| device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some(entry_point),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        ErosionGpu {
+            device: device.clone(),
+            queue: queue.clone(),
+            droplets: pipeline("droplets"),
+            thermal: pipeline("thermal"),
+            apply: pipeline("apply"),
+            bind_group_layout,
+        }
+    }
+
+    async fn erode(&self, heights: &mut [f32], mut params: Params, config: &ErosionConfig) -> anyhow::Result<()> {
+        let buffer_size = std::mem::size_of_val(heights) as u64;
+
+        let height_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ErosionHeightBuffer"),
+            contents: bytemuck::cast_slice(heights),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        });
+
+        //Zeroed on creation
+        let delta_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("ErosionDeltaBuffer"),
+            size: buffer_size,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let grid = (params.width.div_ceil(WORKGROUP_SIZE), params.height.div_ceil(WORKGROUP_SIZE));
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("ErosionEncoder") });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+
+            //Every dispatch sees the heights the previous one left
+            for (offset, count) in batches(&params, config) {
+                params.droplet_offset = offset;
+                params.droplet_count = count;
+                compute_pass.set_bind_group(0, &self.bind_group(&params, &height_buffer, &delta_buffer), &[]);
+
+                compute_pass.set_pipeline(&self.droplets);
+                compute_pass.dispatch_workgroups(count.div_ceil(DROPLET_WORKGROUP_SIZE), 1, 1);
+                compute_pass.set_pipeline(&self.apply);
+                compute_pass.dispatch_workgroups(grid.0, grid.1, 1);
+            }
+
+            if config.thermal.iterations > 0 {
+                compute_pass.set_bind_group(0, &self.bind_group(&params, &height_buffer, &delta_buffer), &[]);
+            }
+            for _ in 0..config.thermal.iterations {
+                compute_pass.set_pipeline(&self.thermal);
+                compute_pass.dispatch_workgroups(grid.0, grid.1, 1);
+                compute_pass.set_pipeline(&self.apply);
+                compute_pass.dispatch_workgroups(grid.0, grid.1, 1);
+            }
+        }
+
+        let readback_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("ErosionReadbackBuffer"),
+            size: buffer_size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_buffer_to_buffer(&height_buffer, 0, &readback_buffer, 0, buffer_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+        self.device.poll(PollType::Wait)?;
+        receiver.receive().await.unwrap()?;
+
+        heights.copy_from_slice(bytemuck::cast_slice(&buffer_slice.get_mapped_range()));
+        readback_buffer.unmap();
+
+        Ok(())
+    }
+
+    fn bind_group(&self, params: &Params, height_buffer: &Buffer, delta_buffer: &Buffer) -> wgpu::BindGroup {
+        let params_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("ErosionParamsBuffer"),
+            contents: bytemuck::bytes_of(params),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ErosionBindGroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: height_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: delta_buffer.as_entire_binding() },
+            ],
+        })
+    }
+}
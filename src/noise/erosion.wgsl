@@ -0,0 +1,169 @@
+struct Params {
+    width: u32,
+    height: u32,
+    seed: u32,
+    droplet_offset: u32,
+    droplet_count: u32,
+    max_lifetime: u32,
+    inertia: f32,
+    sediment_capacity: f32,
+    min_capacity: f32,
+    deposition: f32,
+    erosion: f32,
+    evaporation: f32,
+    gravity: f32,
+    talus: f32,
+    thermal_rate: f32,
+    _padding: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> heights: array<f32>;
+// Height changes in fixed point, integer atomics add up the same whatever order droplets run in
+@group(0) @binding(2) var<storage, read_write> deltas: array<atomic<i32>>;
+
+const FIXED_POINT: f32 = 65536.0;
+
+// Identical to `pcg` in erosion.rs
+fn pcg(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Identical to `random` in erosion.rs, in [0, 1)
+fn random(value: u32) -> f32 {
+    return f32(pcg(value) >> 8u) / 16777216.0;
+}
+
+fn height(x: u32, y: u32) -> f32 {
+    return heights[y * params.width + x];
+}
+
+// Gradient in xy and height in z, bilinearly interpolated
+fn height_and_gradient(position: vec2<f32>) -> vec3<f32> {
+    let cell = vec2<u32>(position);
+    let offset = position - vec2<f32>(cell);
+
+    let h00 = height(cell.x, cell.y);
+    let h10 = height(cell.x + 1u, cell.y);
+    let h01 = height(cell.x, cell.y + 1u);
+    let h11 = height(cell.x + 1u, cell.y + 1u);
+
+    let gradient = vec2(
+        (h10 - h00) * (1.0 - offset.y) + (h11 - h01) * offset.y,
+        (h01 - h00) * (1.0 - offset.x) + (h11 - h10) * offset.x,
+    );
+    let value = h00 * (1.0 - offset.x) * (1.0 - offset.y) + h10 * offset.x * (1.0 - offset.y) + h01 * (1.0 - offset.x) * offset.y + h11 * offset.x * offset.y;
+
+    return vec3(gradient, value);
+}
+
+fn fixed(amount: f32) -> i32 {
+    return i32(round(amount * FIXED_POINT));
+}
+
+// Spreads `amount` over the corners of a cell, negative to erode
+fn deposit(cell: vec2<u32>, offset: vec2<f32>, amount: f32) {
+    let index = cell.y * params.width + cell.x;
+    atomicAdd(&deltas[index], fixed(amount * (1.0 - offset.x) * (1.0 - offset.y)));
+    atomicAdd(&deltas[index + 1u], fixed(amount * offset.x * (1.0 - offset.y)));
+    atomicAdd(&deltas[index + params.width], fixed(amount * (1.0 - offset.x) * offset.y));
+    atomicAdd(&deltas[index + params.width + 1u], fixed(amount * offset.x * offset.y));
+}
+
+// Identical to `droplet` in erosion.rs
+@compute @workgroup_size(64)
+fn droplets(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.droplet_count) {
+        return;
+    }
+
+    let index = params.droplet_offset + id.x;
+    let limit = vec2(f32(params.width - 1u), f32(params.height - 1u));
+    var position = vec2(random(params.seed ^ pcg(index * 2u)), random(params.seed ^ pcg(index * 2u + 1u))) * limit;
+    var direction = vec2(0.0);
+    var speed = 1.0;
+    var water = 1.0;
+    var sediment = 0.0;
+
+    for (var step = 0u; step < params.max_lifetime; step = step + 1u) {
+        let cell = vec2<u32>(position);
+        let offset = position - vec2<f32>(cell);
+        let here = height_and_gradient(position);
+
+        direction = direction * params.inertia - here.xy * (1.0 - params.inertia);
+        let norm = length(direction);
+        if (norm < 1e-6) {
+            break;
+        }
+        direction = direction / norm;
+        position = position + direction;
+
+        if (position.x < 0.0 || position.y < 0.0 || position.x >= limit.x || position.y >= limit.y) {
+            break;
+        }
+
+        let delta_height = height_and_gradient(position).z - here.z;
+        let capacity = max(-delta_height * speed * water * params.sediment_capacity, params.min_capacity);
+
+        if (delta_height > 0.0) {
+            // The heights don't change under a droplet, so one that turned uphill would slosh
+            // in the same pit forever. It fills the pit and stops instead
+            deposit(cell, offset, min(delta_height, sediment));
+            break;
+        } else if (sediment > capacity) {
+            let amount = (sediment - capacity) * params.deposition;
+            sediment = sediment - amount;
+            deposit(cell, offset, amount);
+        } else {
+            let amount = min((capacity - sediment) * params.erosion, -delta_height);
+            sediment = sediment + amount;
+            deposit(cell, offset, -amount);
+        }
+
+        speed = sqrt(max(speed * speed - delta_height * params.gravity, 0.0));
+        water = water * (1.0 - params.evaporation);
+    }
+}
+
+// Material exchanged with a neighbour `difference` metres higher, positive when it flows in
+fn slump(difference: f32) -> f32 {
+    let excess = abs(difference) - params.talus;
+    return select(0.0, sign(difference) * excess * params.thermal_rate * 0.5, excess > 0.0);
+}
+
+// Identical to `thermal` in erosion.rs
+@compute @workgroup_size(8, 8)
+fn thermal(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+
+    let here = height(id.x, id.y);
+    var change = 0.0;
+    if (id.x > 0u) {
+        change = change + slump(height(id.x - 1u, id.y) - here);
+    }
+    if (id.x + 1u < params.width) {
+        change = change + slump(height(id.x + 1u, id.y) - here);
+    }
+    if (id.y > 0u) {
+        change = change + slump(height(id.x, id.y - 1u) - here);
+    }
+    if (id.y + 1u < params.height) {
+        change = change + slump(height(id.x, id.y + 1u) - here);
+    }
+
+    atomicStore(&deltas[id.y * params.width + id.x], fixed(change));
+}
+
+@compute @workgroup_size(8, 8)
+fn apply(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+
+    let index = id.y * params.width + id.x;
+    heights[index] = heights[index] + f32(atomicExchange(&deltas[index], 0)) / FIXED_POINT;
+}
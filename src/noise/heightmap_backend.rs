@@ -0,0 +1,100 @@
+use bevy::ecs::resource::Resource;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{noise::{perlin::Perlin, perlin_cpu::PerlinCPU}, terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH, MAP_HEIGHT, MAP_WIDTH}, terrain_config::TerrainGenConfig}};
+
+/// Which heightmap generator to use. `Auto` prefers the GPU and falls back
+/// to the CPU when no adapter can be found (CI, headless servers).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackendKind {
+    #[default]
+    Auto,
+    Gpu,
+    Cpu,
+}
+
+impl BackendKind {
+    /// Reads `TERRAIN_BACKEND` (`auto`, `gpu` or `cpu`), if set.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("TERRAIN_BACKEND").map(|v| v.to_lowercase()).as_deref() {
+            Ok("gpu") => Some(BackendKind::Gpu),
+            Ok("cpu") => Some(BackendKind::Cpu),
+            Ok("auto") => Some(BackendKind::Auto),
+            Ok(other) => {
+                warn!("Unknown TERRAIN_BACKEND '{other}', ignoring");
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Resource)]
+pub enum HeightmapBackend {
+    Gpu(Perlin),
+    Cpu(Box<PerlinCPU>),
+}
+
+impl HeightmapBackend {
+    pub async fn new(config: &TerrainGenConfig) -> anyhow::Result<Self> {
+        match config.backend {
+            BackendKind::Gpu => Ok(HeightmapBackend::Gpu(Perlin::new(config).await?)),
+            BackendKind::Cpu => Ok(HeightmapBackend::Cpu(Box::new(PerlinCPU::from_config(config)))),
+            BackendKind::Auto => match Perlin::new(config).await {
+                Ok(perlin) => Ok(HeightmapBackend::Gpu(perlin)),
+                Err(e) => {
+                    warn!("No usable GPU adapter ({e}), falling back to CPU heightmap generation");
+                    Ok(HeightmapBackend::Cpu(Box::new(PerlinCPU::from_config(config))))
+                }
+            },
+        }
+    }
+
+    /// Generates the whole `MAP_WIDTH` x `MAP_HEIGHT` map in chunk-major order, regardless of backend.
+    pub async fn compute_from_fractal(&self) -> anyhow::Result<Vec<f32>> {
+        self.compute_chunks((0, 0), (MAP_WIDTH as u32, MAP_HEIGHT as u32)).await
+    }
+
+    /// Heights for the `size` samples starting at world sample `origin`, row by row.
+    pub async fn compute_region(&self, origin: (i32, i32), size: (u32, u32)) -> anyhow::Result<Vec<f32>> {
+        match self {
+            HeightmapBackend::Gpu(perlin) => perlin.compute_region(origin, size).await,
+            HeightmapBackend::Cpu(perlin) => Ok(perlin.compute_region(origin, size)),
+        }
+    }
+
+    /// Heights for a rectangle of `chunks` starting at chunk `origin`, in chunk-major order:
+    /// each chunk is a contiguous `CHUNK_WIDTH * CHUNK_HEIGHT` block, chunks row by row.
+    pub async fn compute_chunks(&self, origin: (i32, i32), chunks: (u32, u32)) -> anyhow::Result<Vec<f32>> {
+        let width = chunks.0 as usize * CHUNK_WIDTH;
+        let region = self.compute_region(
+            (origin.0 * CHUNK_WIDTH as i32, origin.1 * CHUNK_HEIGHT as i32),
+            (width as u32, chunks.1 * CHUNK_HEIGHT as u32),
+        ).await?;
+
+        let chunk_count = chunks.0 as usize * chunks.1 as usize;
+        let chunkmap = (0..chunk_count)
+            .flat_map(|chunk_index| {
+                let chunk_x = chunk_index % chunks.0 as usize;
+                let chunk_y = chunk_index / chunks.0 as usize;
+
+                (0..CHUNK_HEIGHT).flat_map(move |local_y| {
+                    let start = (chunk_y * CHUNK_HEIGHT + local_y) * width + chunk_x * CHUNK_WIDTH;
+                    start..start + CHUNK_WIDTH
+                })
+            })
+            .map(|index| region[index])
+            .collect();
+
+        Ok(chunkmap)
+    }
+
+    /// Heights for a single chunk plus its halo row and column, see `PerlinCPU::compute_chunk`.
+    pub async fn compute_chunk(&self, coordinates: (i32, i32)) -> anyhow::Result<Vec<f32>> {
+        match self {
+            HeightmapBackend::Gpu(perlin) => perlin.compute_chunk(coordinates).await,
+            HeightmapBackend::Cpu(perlin) => Ok(perlin.compute_chunk(coordinates)),
+        }
+    }
+}
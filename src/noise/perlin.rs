@@ -1,17 +1,28 @@
 use bevy::ecs::resource::Resource;
-use wgpu::{util::{BufferInitDescriptor, DeviceExt}, wgt::PollType, BindGroup, Buffer, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Device, DeviceDescriptor, PipelineCompilationOptions, Queue};
+use wgpu::{util::{BufferInitDescriptor, DeviceExt}, wgt::PollType, BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, ComputePipeline, ComputePipelineDescriptor, Device, DeviceDescriptor, PipelineCompilationOptions, Queue};
 use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 
-use crate::terrain::chunks::{CHUNK_HEIGHT, CHUNK_WIDTH, MAP_HEIGHT, MAP_WIDTH};
+use crate::terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH}, terrain_config::{DomainWarp, HeightCurve, NoiseMode, TerrainGenConfig}};
+
+const WORKGROUP_SIZE: u32 = 8;
 
 #[derive(Debug, Clone, Resource)]
 pub struct Perlin {
+    scale: f32,
+    octaves: u32,
+    lacunarity: f32,
+    persistence: f32,
+    noise_mode: NoiseMode,
+    warp: DomainWarp,
+    height_curve: HeightCurve,
+
     device: Device,
     queue: Queue,
-    output_buffer: Buffer,
+    seed_buffer: Buffer,
+    vector_buffer: Buffer,
 
+    bind_group_layout: BindGroupLayout,
     compute_pipeline: ComputePipeline,
-    bind_group: BindGroup
 }
 
 #[repr(C)]
@@ -19,8 +30,23 @@ pub struct Perlin {
 struct Data {
     width: u32,
     height: u32,
-    chunk_size: u32,
+    origin_x: i32,
+    origin_y: i32,
     scale: f32,
+    octaves: u32,
+    lacunarity: f32,
+    persistence: f32,
+    curve_offset: f32,
+    curve_exponent: u32,
+    curve_amplitude: f32,
+    noise_mode: u32,
+    ridge_gain: f32,
+    warp_strength: f32,
+    warp_frequency: f32,
+    blend_fbm: f32,
+    blend_ridged: f32,
+    blend_billow: f32,
+    _padding: [u32; 2],
 }
 
 #[repr(C)]
@@ -31,19 +57,19 @@ struct Vector {
 }
 
 impl Perlin {
-    pub async fn new(seed: u64, scale: f32) -> anyhow::Result<Self> {
+    pub async fn new(config: &TerrainGenConfig) -> anyhow::Result<Self> {
         let instance = wgpu::Instance::default();
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 compatible_surface: None,
                 force_fallback_adapter: false,
-            }).await.unwrap();
+            }).await?;
 
-        let (device, queue) = adapter.request_device(&DeviceDescriptor::default()).await.unwrap();
+        let (device, queue) = adapter.request_device(&DeviceDescriptor::default()).await?;
 
         let mut table_256: [u32; 256] = (0..=255).collect::<Vec<u32>>().try_into().unwrap();
-        let mut rng = StdRng::seed_from_u64(seed);
+        let mut rng = StdRng::seed_from_u64(config.seed);
         table_256.shuffle(&mut rng);
 
         let table_512: [u32; 512] = {
@@ -72,22 +98,6 @@ impl Perlin {
             Vector { x:  0.9238795,  y: -0.38268343},
         ]; 
 
-        let data = Data { 
-            width: (MAP_WIDTH * CHUNK_WIDTH) as u32, 
-            height: (MAP_HEIGHT * CHUNK_HEIGHT) as u32, 
-            chunk_size: CHUNK_HEIGHT as u32,
-            scale,
-        };
-
-        let vertex_count = (MAP_HEIGHT * MAP_WIDTH) as u64;
-        let buffer_size = vertex_count * CHUNK_WIDTH as u64 * CHUNK_HEIGHT as u64 * std::mem::size_of::<f32>() as u64;
-
-         let data_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("DataBuffer"),
-            contents: bytemuck::bytes_of(&data),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        }); 
-
         let seed_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("SeedBuffer"),
             contents: bytemuck::cast_slice(&table_512),
@@ -100,13 +110,6 @@ impl Perlin {
             usage: BufferUsages::STORAGE,
         });
 
-        let output_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("OutputBuffer"),
-            size: buffer_size, 
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("from_point"),
             source: wgpu::ShaderSource::Wgsl(include_str!("perlin.wgsl").into()),
@@ -158,17 +161,6 @@ impl Perlin {
             ],
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("BindGroup"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry { binding: 0, resource: data_buffer.as_entire_binding() },
-                wgpu::BindGroupEntry { binding: 1, resource: seed_buffer.as_entire_binding() },
-                wgpu::BindGroupEntry { binding: 2, resource: vector_buffer.as_entire_binding() },
-                wgpu::BindGroupEntry { binding: 3, resource: output_buffer.as_entire_binding() },
-            ],
-        });
-
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("PipelineLayout"),
             bind_group_layouts: &[&bind_group_layout],
@@ -185,30 +177,101 @@ impl Perlin {
         });
 
         Ok(Self {
+            scale: config.scale,
+            octaves: config.octaves,
+            lacunarity: config.lacunarity,
+            persistence: config.persistence,
+            noise_mode: config.noise_mode,
+            warp: config.warp,
+            height_curve: config.height_curve,
+
             device,
             queue,
-            output_buffer,
+            seed_buffer,
+            vector_buffer,
 
+            bind_group_layout,
             compute_pipeline,
-            bind_group,
         })
     }
 
-    pub async fn compute_from_fractal(&self, workgroups: (u32, u32, u32)) -> anyhow::Result<Vec<f32>> { self.dispatch(workgroups).await }
+    /// Device the noise runs on, shared with the other terrain compute passes.
+    pub fn device(&self) -> (&Device, &Queue) {
+        (&self.device, &self.queue)
+    }
+
+    /// Heights for the `size` samples starting at world sample `origin`, row by row.
+    /// Only the requested region is rendered and read back.
+    pub async fn compute_region(&self, origin: (i32, i32), size: (u32, u32)) -> anyhow::Result<Vec<f32>> {
+        let (noise_mode, ridge_gain, [blend_fbm, blend_ridged, blend_billow]) = self.noise_mode.to_uniform();
+        let data = Data {
+            width: size.0,
+            height: size.1,
+            origin_x: origin.0,
+            origin_y: origin.1,
+            scale: self.scale,
+            octaves: self.octaves,
+            lacunarity: self.lacunarity,
+            persistence: self.persistence,
+            curve_offset: self.height_curve.offset,
+            curve_exponent: self.height_curve.exponent,
+            curve_amplitude: self.height_curve.amplitude,
+            noise_mode,
+            ridge_gain,
+            warp_strength: self.warp.strength,
+            warp_frequency: self.warp.frequency,
+            blend_fbm,
+            blend_ridged,
+            blend_billow,
+            _padding: [0; 2],
+        };
 
-    async fn dispatch(&self, workgroups: (u32, u32, u32)) -> anyhow::Result<Vec<f32>> {
+        let buffer_size = size.0 as u64 * size.1 as u64 * std::mem::size_of::<f32>() as u64;
+
+        let data_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("DataBuffer"),
+            contents: bytemuck::bytes_of(&data),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let output_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("OutputBuffer"),
+            size: buffer_size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("BindGroup"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: data_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: self.seed_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: self.vector_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: output_buffer.as_entire_binding() },
+            ],
+        });
+
+        let workgroups = (size.0.div_ceil(WORKGROUP_SIZE), size.1.div_ceil(WORKGROUP_SIZE), 1);
+        self.dispatch(&bind_group, &output_buffer, buffer_size, workgroups).await
+    }
+
+    /// Heights for one chunk plus its halo row and column, see `PerlinCPU::compute_chunk`.
+    pub async fn compute_chunk(&self, (chunk_x, chunk_y): (i32, i32)) -> anyhow::Result<Vec<f32>> {
+        let origin = (chunk_x * CHUNK_WIDTH as i32, chunk_y * CHUNK_HEIGHT as i32);
+        self.compute_region(origin, (CHUNK_WIDTH as u32 + 1, CHUNK_HEIGHT as u32 + 1)).await
+    }
+
+    async fn dispatch(&self, bind_group: &wgpu::BindGroup, output_buffer: &Buffer, buffer_size: u64, workgroups: (u32, u32, u32)) -> anyhow::Result<Vec<f32>> {
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("ComputeEncoder") });
 
         {
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
             compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(0, bind_group, &[]);
             compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
         }
 
-        let vertex_count = (MAP_HEIGHT * MAP_WIDTH) as u64;
-        let buffer_size = vertex_count * CHUNK_WIDTH as u64 * CHUNK_HEIGHT as u64 * std::mem::size_of::<f32>() as u64;
-
         let readback_buffer = self.device.create_buffer(&BufferDescriptor {
             label: Some("ReadbackBuffer"),
             size: buffer_size,
@@ -217,11 +280,10 @@ impl Perlin {
         });
 
         encoder.copy_buffer_to_buffer(
-            &self.output_buffer, 0,
+            output_buffer, 0,
             &readback_buffer, 0,
             buffer_size 
         );
-
         self.queue.submit(Some(encoder.finish()));
 
         let buffer_slice = readback_buffer.slice(..);
@@ -1,4 +1,23 @@
-struct Data { width: u32, height: u32, size: u32, scale: f32 };
+struct Data {
+    width: u32,
+    height: u32,
+    origin_x: i32,
+    origin_y: i32,
+    scale: f32,
+    octaves: u32,
+    lacunarity: f32,
+    persistence: f32,
+    curve_offset: f32,
+    curve_exponent: u32,
+    curve_amplitude: f32,
+    noise_mode: u32,
+    ridge_gain: f32,
+    warp_strength: f32,
+    warp_frequency: f32,
+    blend_fbm: f32,
+    blend_ridged: f32,
+    blend_billow: f32,
+};
 struct Seed { data: array<u32> };
 struct Vectors { data: array<vec2<f32>> };
 struct Output { data: array<f32> };
@@ -12,10 +31,96 @@ fn lerp(a: f32, b: f32, t: f32) -> f32 {
     return a + (b - a) * t;
 }
 
+// Horner form of 6t^5 - 15t^4 + 10t^3, kept identical to `fade` in perlin_cpu.rs
 fn fade(num: f32) -> f32 {
-    return (6.0 * pow(num, 5.0)) -
-           (15.0 * pow(num, 4.0)) +
-           (10.0 * pow(num, 3.0));
+    return num * num * num * (num * (num * 6.0 - 15.0) + 10.0);
+}
+
+// Identical to `PerlinCPU::from_ridged`
+fn from_ridged(x: f32, y: f32, octaves: u32, lacunarity: f32, persistence: f32, gain: f32) -> f32 {
+    var total: f32 = 0.0;
+    var frequency: f32 = 1.0;
+    var amplitude: f32 = 1.0;
+    var max_amplitude: f32 = 0.0;
+    var weight: f32 = 1.0;
+
+    for (var i: u32 = 0u; i < octaves; i = i + 1u) {
+        var signal: f32 = 1.0 - abs(from_sample(x * frequency, y * frequency));
+        signal = signal * signal * weight;
+        weight = clamp(signal * gain, 0.0, 1.0);
+
+        total = total + signal * amplitude;
+        max_amplitude = max_amplitude + amplitude;
+        frequency = frequency * lacunarity;
+        amplitude = amplitude * persistence;
+    }
+
+    return total / max_amplitude * 2.0 - 1.0;
+}
+
+// Identical to `PerlinCPU::from_billow`
+fn from_billow(x: f32, y: f32, octaves: u32, lacunarity: f32, persistence: f32) -> f32 {
+    var total: f32 = 0.0;
+    var frequency: f32 = 1.0;
+    var amplitude: f32 = 1.0;
+    var max_amplitude: f32 = 0.0;
+
+    for (var i: u32 = 0u; i < octaves; i = i + 1u) {
+        total = total + (abs(from_sample(x * frequency, y * frequency)) * 2.0 - 1.0) * amplitude;
+        max_amplitude = max_amplitude + amplitude;
+        frequency = frequency * lacunarity;
+        amplitude = amplitude * persistence;
+    }
+
+    return total / max_amplitude;
+}
+
+// Identical to `PerlinCPU::from_mode`: optional domain warp, then the selected mode
+fn from_mode(x_in: f32, y_in: f32) -> f32 {
+    var x: f32 = x_in;
+    var y: f32 = y_in;
+
+    if (data.warp_strength != 0.0) {
+        let wx = x_in * data.warp_frequency;
+        let wy = y_in * data.warp_frequency;
+        x = x_in + data.warp_strength * from_fractal(wx, wy, data.octaves, data.lacunarity, data.persistence);
+        y = y_in + data.warp_strength * from_fractal(wx + 5.2, wy + 1.3, data.octaves, data.lacunarity, data.persistence);
+    }
+
+    switch data.noise_mode {
+        case 1u: {
+            return from_ridged(x, y, data.octaves, data.lacunarity, data.persistence, data.ridge_gain);
+        }
+        case 2u: {
+            return from_billow(x, y, data.octaves, data.lacunarity, data.persistence);
+        }
+        case 3u: {
+            var total: f32 = 0.0;
+            if (data.blend_fbm != 0.0) {
+                total += data.blend_fbm * from_fractal(x, y, data.octaves, data.lacunarity, data.persistence);
+            }
+            if (data.blend_ridged != 0.0) {
+                total += data.blend_ridged * from_ridged(x, y, data.octaves, data.lacunarity, data.persistence, data.ridge_gain);
+            }
+            if (data.blend_billow != 0.0) {
+                total += data.blend_billow * from_billow(x, y, data.octaves, data.lacunarity, data.persistence);
+            }
+            return total / max(data.blend_fbm + data.blend_ridged + data.blend_billow, 1.1920929e-7);
+        }
+        default: {
+            return from_fractal(x, y, data.octaves, data.lacunarity, data.persistence);
+        }
+    }
+}
+
+// Identical to `HeightCurve::apply`: (n + offset)^exponent * amplitude
+fn height_curve(noise: f32) -> f32 {
+    let base = noise + data.curve_offset;
+    var height: f32 = 1.0;
+    for (var i: u32 = 0u; i < data.curve_exponent; i = i + 1u) {
+        height = height * base;
+    }
+    return height * data.curve_amplitude;
 }
 
 fn from_point(x: i32, y: i32) -> vec2<f32> {
@@ -67,11 +172,14 @@ fn from_fractal(x: f32, y: f32, octaves: u32, lacunarity: f32, persistence: f32)
     return total / max_amplitude;
 }
 
+fn terrain_height(x: f32, y: f32) -> f32 {
+    return height_curve(from_mode(x * data.scale, y * data.scale));
+}
+
+// Renders a `width` x `height` region of samples starting at world sample
+// (origin_x, origin_y) into `output`, row by row.
 @compute @workgroup_size(8, 8)
 fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
-    let CHUNK_SIZE = data.size;
-    let CHUNK_AREA = CHUNK_SIZE * CHUNK_SIZE;
-
     let gx: u32 = gid.x;
     let gy: u32 = gid.y;
 
@@ -79,23 +187,8 @@ fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
         return;
     }
 
-    let chunks_x: u32 = data.width / CHUNK_SIZE;
-    let chunks_y: u32 = data.height / CHUNK_SIZE;
-
-    let chunk_x: u32 = gx / CHUNK_SIZE;
-    let chunk_y: u32 = gy / CHUNK_SIZE;
-    let local_x: u32 = gx - chunk_x * CHUNK_SIZE;
-    let local_y: u32 = gy - chunk_y * CHUNK_SIZE;
-
-    let chunk_index: u32 = chunk_y * chunks_x + chunk_x;
-
-    let within_chunk_index: u32 = local_y * CHUNK_SIZE + local_x;
-    let out_index: u32 = chunk_index * CHUNK_AREA + within_chunk_index;
-
-    let sx: f32 = f32(gx) * data.scale;
-    let sy: f32 = f32(gy) * data.scale;
-
-    let noise: f32 = from_fractal(sx, sy, 4u, 2.0, 0.5);
+    let wx: i32 = data.origin_x + i32(gx);
+    let wy: i32 = data.origin_y + i32(gy);
 
-    output.data[out_index] = noise;
+    output.data[gy * data.width + gx] = terrain_height(f32(wx), f32(wy));
 }
@@ -1,5 +1,8 @@
 use bevy::{ecs::resource::Resource, math::{ops::floor, Vec2}};
 use rand::{self, rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH, MAP_HEIGHT, MAP_WIDTH}, terrain_config::{DomainWarp, HeightCurve, NoiseMode, TerrainGenConfig}};
 
 const VECTORS: [Vec2; 16] = [
     Vec2 { x:  1.0,        y:  0.0       },
@@ -29,6 +32,10 @@ pub struct PerlinCPU {
     pub octaves: usize,
     pub lacunarity: f32,
     pub persistence: f32,
+
+    pub noise_mode: NoiseMode,
+    pub warp: DomainWarp,
+    pub height_curve: HeightCurve,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -65,12 +72,32 @@ impl PerlinCPU {
         table_512[..256].copy_from_slice(&table_256);
         table_512[256..].copy_from_slice(&table_256);
 
-        PerlinCPU { seed: table_512, scale, octaves, lacunarity, persistence }
+        PerlinCPU {
+            seed: table_512,
+            scale,
+            octaves,
+            lacunarity,
+            persistence,
+            noise_mode: NoiseMode::Fbm,
+            warp: DomainWarp::default(),
+            height_curve: HeightCurve::default(),
+        }
     }
 
-    pub fn from_point(&self, x: usize, y: usize) -> Vec2 {
-        let x_bwand = x & 255;
-        let y_bwand = y & 255;
+    pub fn from_config(config: &TerrainGenConfig) -> Self {
+        PerlinCPU {
+            noise_mode: config.noise_mode,
+            warp: config.warp,
+            height_curve: config.height_curve,
+            ..PerlinCPU::new(config.seed, config.scale, config.octaves as usize, config.lacunarity, config.persistence)
+        }
+    }
+
+    /// Gradient lookup. Coordinates wrap on their low 8 bits exactly like the
+    /// `u32(x) & 255u` in `perlin.wgsl`, so negative lattice points hash the same way.
+    pub fn from_point(&self, x: i32, y: i32) -> Vec2 {
+        let x_bwand = (x as u32 & 255) as usize;
+        let y_bwand = (y as u32 & 255) as usize;
 
         let hash = self.seed[(self.seed[x_bwand] as usize + y_bwand) & 255];
         let vector_index = hash % 16;
@@ -78,11 +105,9 @@ impl PerlinCPU {
         VECTORS[vector_index as usize]
     }
 
-    
-
     pub fn from_sample(&self, x: f32, y: f32) -> f32 {
-        let x0 = floor(x) as usize;
-        let y0 = floor(y) as usize;
+        let x0 = floor(x) as i32;
+        let y0 = floor(y) as i32;
 
         let g00 = self.from_point(x0, y0);
         let g10 = self.from_point(x0+1, y0);
@@ -126,14 +151,128 @@ impl PerlinCPU {
 
         total / max_amplitude
     }
+
+    /// Ridged multifractal: inverted `|noise|` octaves, squared and weighted by
+    /// the previous octave so detail gathers along the crests.
+    pub fn from_ridged(&self, x: f32, y: f32, gain: f32) -> f32 {
+        let mut total = 0.;
+        let mut frequency = 1.;
+        let mut amplitude = 1.;
+        let mut max_amplitude = 0.;
+        let mut weight = 1.;
+
+        for _ in 0..self.octaves {
+            let mut signal = 1. - self.from_sample(x * frequency, y * frequency).abs();
+            signal = signal * signal * weight;
+            weight = (signal * gain).clamp(0., 1.);
+
+            total += signal * amplitude;
+            max_amplitude += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+
+        total / max_amplitude * 2. - 1.
+    }
+
+    /// Billow: `|noise|` octaves remapped back to `[-1, 1]`.
+    pub fn from_billow(&self, x: f32, y: f32) -> f32 {
+        let mut total = 0.;
+        let mut frequency = 1.;
+        let mut amplitude = 1.;
+        let mut max_amplitude = 0.;
+
+        for _ in 0..self.octaves {
+            total += (self.from_sample(x * frequency, y * frequency).abs() * 2. - 1.) * amplitude;
+            max_amplitude += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+
+        total / max_amplitude
+    }
+
+    /// Evaluates the configured `noise_mode`, domain-warped when `warp.strength` is non-zero.
+    pub fn from_mode(&self, x: f32, y: f32) -> f32 {
+        let (x, y) = if self.warp.strength != 0. {
+            let wx = x * self.warp.frequency;
+            let wy = y * self.warp.frequency;
+            (
+                x + self.warp.strength * self.from_fractal(wx, wy),
+                y + self.warp.strength * self.from_fractal(wx + 5.2, wy + 1.3),
+            )
+        } else {
+            (x, y)
+        };
+
+        match self.noise_mode {
+            NoiseMode::Fbm => self.from_fractal(x, y),
+            NoiseMode::Ridged { gain } => self.from_ridged(x, y, gain),
+            NoiseMode::Billow => self.from_billow(x, y),
+            NoiseMode::Blend { fbm, ridged, billow, gain } => {
+                let mut total = 0.;
+                if fbm != 0. {
+                    total += fbm * self.from_fractal(x, y);
+                }
+                if ridged != 0. {
+                    total += ridged * self.from_ridged(x, y, gain);
+                }
+                if billow != 0. {
+                    total += billow * self.from_billow(x, y);
+                }
+                total / (fbm + ridged + billow).max(f32::EPSILON)
+            }
+        }
+    }
+
+    /// World-space terrain height at `(x, z)`. This is the CPU twin of
+    /// `terrain_height` in `perlin.wgsl`: scale, noise mode, then the height curve.
+    pub fn terrain_height(&self, x: f32, z: f32) -> f32 {
+        self.height_curve.apply(self.from_mode(x * self.scale, z * self.scale))
+    }
+
+    /// Heights for the `size` samples starting at world sample `origin`, row by row.
+    /// Mirrors `Perlin::compute_region`.
+    pub fn compute_region(&self, origin: (i32, i32), size: (u32, u32)) -> Vec<f32> {
+        (0..size.1)
+            .into_par_iter()
+            .flat_map_iter(|y| (0..size.0).map(move |x| {
+                self.terrain_height((origin.0 + x as i32) as f32, (origin.1 + y as i32) as f32)
+            })).collect()
+    }
+
+    /// Heights for one chunk plus its halo, `(CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1)`
+    /// samples row by row, starting at the chunk's world-space origin.
+    pub fn compute_chunk(&self, (chunk_x, chunk_y): (i32, i32)) -> Vec<f32> {
+        let origin = (chunk_x * CHUNK_WIDTH as i32, chunk_y * CHUNK_HEIGHT as i32);
+        self.compute_region(origin, (CHUNK_WIDTH as u32 + 1, CHUNK_HEIGHT as u32 + 1))
+    }
+
+    /// Fills the whole map in the same chunk-major layout `perlin.wgsl` writes:
+    /// every chunk is a contiguous `CHUNK_WIDTH * CHUNK_HEIGHT` block, row by row.
+    pub fn compute_from_fractal(&self) -> Vec<f32> {
+        (0..MAP_WIDTH * MAP_HEIGHT)
+            .into_par_iter()
+            .flat_map_iter(|chunk_index| {
+                let chunk_x = chunk_index % MAP_WIDTH;
+                let chunk_y = chunk_index / MAP_WIDTH;
+
+                (0..CHUNK_HEIGHT).flat_map(move |local_y| (0..CHUNK_WIDTH).map(move |local_x| {
+                    let gx = chunk_x * CHUNK_WIDTH + local_x;
+                    let gy = chunk_y * CHUNK_HEIGHT + local_y;
+
+                    self.terrain_height(gx as f32, gy as f32)
+                }))
+            }).collect()
+    }
 }
 
 pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
     a + (b - a) * t
 }
 
+/// `6t^5 - 15t^4 + 10t^3` in Horner form. `powf` lowers to exp2/log2 on most
+/// GPUs, so both paths use plain multiplications to stay in agreement.
 pub fn fade(num: f32) -> f32 {
-    6. * num.powf(5.) -
-    15. * num.powf(4.) +
-    10. * num.powf(3.)
+    num * num * num * (num * (num * 6. - 15.) + 10.)
 }
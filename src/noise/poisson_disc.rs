@@ -1,11 +1,17 @@
 use std::f32::consts::PI;
 use bevy::math::Vec2;
-use rand::prelude::*;
+use rand::{prelude::*, rngs::StdRng};
 
 pub struct PoissonDisc {
     grid: Vec<Vec<Option<usize>>>,
     points: Vec<Vec2>,
+    /// Distance each point keeps every other point at, parallel to `points`.
+    radii: Vec<f32>,
     radius: f32,
+    max_radius: f32,
+    radius_at: Option<Box<dyn Fn(Vec2) -> f32 + Send + Sync>>,
+    seed: Option<u64>,
+    wrapping: bool,
     cell_size: f32,
     sample_size: Vec2,
     samples_try: usize,
@@ -21,58 +27,83 @@ impl PoissonDisc {
         PoissonDisc {
             grid,
             points: Vec::new(),
+            radii: Vec::new(),
             radius,
+            max_radius: radius,
+            radius_at: None,
+            seed: None,
+            wrapping: false,
             cell_size,
             sample_size,
             samples_try,
         }
     }
 
+    /// Draws every `generate_points` from `seed`, so the same seed always gives the same points.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Spaces the points by `radius_at` of their position instead of one radius, for example
+    /// a noise field or the terrain height for denser trees in valleys. Two points stay the
+    /// larger of their radii apart. Radii are kept between the radius given to `new`, which
+    /// sizes the grid, and `max_radius`, which bounds the neighbours each candidate checks.
+    pub fn with_variable_radius(mut self, max_radius: f32, radius_at: impl Fn(Vec2) -> f32 + Send + Sync + 'static) -> Self {
+        self.max_radius = max_radius.max(self.radius);
+        self.radius_at = Some(Box::new(radius_at));
+        self
+    }
+
+    /// Treats the sample as a torus: points near one edge keep their distance to points near
+    /// the opposite edge, so copies of the sample laid side by side tile without clumping.
+    pub fn with_wrapping(mut self) -> Self {
+        self.wrapping = true;
+        self
+    }
+
     pub fn generate_points(&mut self) -> &Vec<Vec2> {
-        let mut rng = rand::rng();
+        match self.seed {
+            Some(seed) => self.generate_points_from(&mut StdRng::seed_from_u64(seed)),
+            None => self.generate_points_from(&mut rand::rng()),
+        }
+    }
 
+    fn generate_points_from(&mut self, rng: &mut impl Rng) -> &Vec<Vec2> {
         for col in &mut self.grid {
             for cell in col.iter_mut() {
                 *cell = None;
             }
         }
         self.points.clear();
+        self.radii.clear();
 
-        let initial = self.sample_size / 2.0;
-        self.points.push(initial);
-
-        let w = self.grid.len();
-        let h = self.grid[0].len();
-        let mut ix = (initial.x / self.cell_size).floor() as isize;
-        let mut iy = (initial.y / self.cell_size).floor() as isize;
-        ix = ix.clamp(0, (w as isize) - 1);
-        iy = iy.clamp(0, (h as isize) - 1);
-        self.grid[ix as usize][iy as usize] = Some(0);
+        //A fixed start would put a point in the same place in every sample
+        let initial = Vec2::new(rng.random_range(0.0..self.sample_size.x), rng.random_range(0.0..self.sample_size.y));
+        let initial_radius = self.radius_of(initial);
+        self.insert(initial, initial_radius);
 
         let mut spawn_points = Vec::new();
-        spawn_points.push(initial);
+        spawn_points.push(0);
 
         while !spawn_points.is_empty() {
             let spawn_index = rng.random_range(0..spawn_points.len());
-            let spawn_center = spawn_points[spawn_index];
+            let spawn = spawn_points[spawn_index];
+            let (spawn_center, spawn_radius) = (self.points[spawn], self.radii[spawn]);
             let mut accepted = false;
 
             for _ in 0..self.samples_try {
                 let angle = rng.random_range(0.0..(2.0 * PI));
-                let r = rng.random_range(self.radius..(2.0 * self.radius));
-                let candidate = spawn_center + Vec2::new(angle.cos(), angle.sin()) * r;
-
-                if self.is_valid(candidate) {
-                    let new_index = self.points.len();
-                    self.points.push(candidate);
-                    spawn_points.push(candidate);
-
-                    let mut cell_x = (candidate.x / self.cell_size).floor() as isize;
-                    let mut cell_y = (candidate.y / self.cell_size).floor() as isize;
-                    cell_x = cell_x.clamp(0, (w as isize) - 1);
-                    cell_y = cell_y.clamp(0, (h as isize) - 1);
+                let r = rng.random_range(spawn_radius..(2.0 * spawn_radius));
+                let mut candidate = spawn_center + Vec2::new(angle.cos(), angle.sin()) * r;
+                if self.wrapping {
+                    candidate = candidate.rem_euclid(self.sample_size);
+                }
 
-                    self.grid[cell_x as usize][cell_y as usize] = Some(new_index);
+                let radius = self.radius_of(candidate);
+                if self.is_valid(candidate, radius) {
+                    spawn_points.push(self.points.len());
+                    self.insert(candidate, radius);
                     accepted = true;
                     break;
                 }
@@ -86,7 +117,32 @@ impl PoissonDisc {
         &self.points
     }
 
-    fn is_valid(&self, candidate: Vec2) -> bool {
+    /// How far each point of the last `generate_points` keeps the others, in the same order.
+    pub fn radii(&self) -> &Vec<f32> {
+        &self.radii
+    }
+
+    fn radius_of(&self, point: Vec2) -> f32 {
+        match &self.radius_at {
+            Some(radius_at) => radius_at(point).clamp(self.radius, self.max_radius),
+            None => self.radius,
+        }
+    }
+
+    fn insert(&mut self, point: Vec2, radius: f32) {
+        let (cell_x, cell_y) = self.cell_of(point);
+        self.grid[cell_x as usize][cell_y as usize] = Some(self.points.len());
+        self.points.push(point);
+        self.radii.push(radius);
+    }
+
+    fn cell_of(&self, point: Vec2) -> (isize, isize) {
+        let cell_x = (point.x / self.cell_size).floor() as isize;
+        let cell_y = (point.y / self.cell_size).floor() as isize;
+        (cell_x.clamp(0, self.grid.len() as isize - 1), cell_y.clamp(0, self.grid[0].len() as isize - 1))
+    }
+
+    fn is_valid(&self, candidate: Vec2, radius: f32) -> bool {
         if !(candidate.x >= 0.0
             && candidate.x < self.sample_size.x
             && candidate.y >= 0.0
@@ -95,23 +151,24 @@ impl PoissonDisc {
             return false;
         }
 
-        let cell_x = (candidate.x / self.cell_size).floor() as isize;
-        let cell_y = (candidate.y / self.cell_size).floor() as isize;
-
+        let (cell_x, cell_y) = self.cell_of(candidate);
         let w = self.grid.len() as isize;
         let h = self.grid[0].len() as isize;
-
-        let start_x = (cell_x - 2).max(0) as usize;
-        let start_y = (cell_y - 2).max(0) as usize;
-        let end_x = (cell_x + 2).min(w - 1) as usize;
-        let end_y = (cell_y + 2).min(h - 1) as usize;
-
-        let rr = self.radius * self.radius;
-        for x in start_x..=end_x {
-            for y in start_y..=end_y {
-                if let Some(pi) = self.grid[x][y] {
-                    let dist2 = (candidate - self.points[pi]).length_squared();
-                    if dist2 < rr {
+        //Two cells either way with one radius, further when neighbours can be spaced wider.
+        //Wrapping one more, the last cell can be cut short by the edge
+        let reach = (self.max_radius / self.cell_size).ceil() as isize + self.wrapping as isize;
+
+        for x in cell_x - reach..=cell_x + reach {
+            for y in cell_y - reach..=cell_y + reach {
+                let (x, y) = match self.wrapping {
+                    true => (x.rem_euclid(w), y.rem_euclid(h)),
+                    false if x < 0 || y < 0 || x >= w || y >= h => continue,
+                    false => (x, y),
+                };
+
+                if let Some(pi) = self.grid[x as usize][y as usize] {
+                    let clear = radius.max(self.radii[pi]);
+                    if self.offset(candidate, self.points[pi]).length_squared() < clear * clear {
                         return false;
                     }
                 }
@@ -120,4 +177,13 @@ impl PoissonDisc {
 
         true
     }
+
+    /// Shortest offset from `b` to `a`, around the edges when wrapping.
+    fn offset(&self, a: Vec2, b: Vec2) -> Vec2 {
+        let offset = a - b;
+        match self.wrapping {
+            true => offset - self.sample_size * (offset / self.sample_size).round(),
+            false => offset,
+        }
+    }
 }
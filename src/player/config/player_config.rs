@@ -35,6 +35,11 @@ impl Default for PlayerConfig {
                 (InputBinding::MouseWheelUp, (DebugIncreaseRenderDistance, MonoStable)),
                 (InputBinding::MouseWheelDown, (DebugDecreaseRenderDistance, MonoStable)),
                 (InputBinding::Key(KeyX.into()), (DebugToggleFlight, MonoStable)),
+                (InputBinding::Key(KeyR.into()), (DebugRaiseTerrain, Momentary)),
+                (InputBinding::Key(KeyF.into()), (DebugLowerTerrain, Momentary)),
+                (InputBinding::Key(KeyG.into()), (DebugFlattenTerrain, Momentary)),
+                (InputBinding::Key(KeyB.into()), (DebugSmoothTerrain, Momentary)),
+                (InputBinding::Key(F5.into()), (DebugSaveTerrainEdits, MonoStable)),
 
             ].iter().cloned().collect(),
         }
@@ -81,6 +86,11 @@ pub enum PlayerAction {
     DebugIncreaseRenderDistance,
     DebugDecreaseRenderDistance,
     DebugToggleFlight,
+    DebugRaiseTerrain,
+    DebugLowerTerrain,
+    DebugFlattenTerrain,
+    DebugSmoothTerrain,
+    DebugSaveTerrainEdits,
 }
 
 
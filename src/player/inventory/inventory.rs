@@ -1,10 +1,10 @@
 use std::collections::HashMap;
 
-use bevy::{app::{Plugin, Startup}, color::palettes::css::{DARK_GRAY, LIGHT_GREY}, ecs::bundle::Bundle, prelude::*, ui::{Node, Val}, window::PrimaryWindow};
+use bevy::{app::{Plugin, Startup}, prelude::*, ui::{Node, Val}, window::PrimaryWindow};
 use uuid::Uuid;
-use tracing::{info, error};
+use tracing::error;
 
-use crate::player::{cursor::Cursor, inventory::{hud::load_hud, items::Item}, player::Player, player_state::ToggleInventory};
+use crate::player::{inventory::{hud::load_hud, items::Item}, player::Player, player_state::ToggleInventory};
 
 pub struct InventoryPlugin;
 
@@ -24,6 +24,8 @@ pub struct Inventory<'a> {
     pub logical: HashMap<Uuid, Item>
 }
 
+//Not wired up to the inventory UI yet
+#[allow(dead_code)]
 impl<'a> Inventory<'a> {
     fn new() -> Self {
         Inventory { 
@@ -34,13 +36,13 @@ impl<'a> Inventory<'a> {
         }
     }
 
-    fn insert(&self, item: Item, cell: (u8, u8)) {
+    fn insert(&self, _item: Item, cell: (u8, u8)) {
         if let Some(uuid) = &self.physical.get(&cell) {
            error!("Cell is occupied: {}", &self.logical.get(uuid).unwrap_or_default()); 
         }
     }
 
-    fn swap(&self, cell: (u8, u8), source_cell: (u8, u8)) {
+    fn swap(&self, _cell: (u8, u8), _source_cell: (u8, u8)) {
 
     }
 }
@@ -81,8 +83,8 @@ fn toggle_inventory(
                         BackgroundColor(Color::srgba_u8(20, 20, 20, 180))
                     ))
                     .with_children(|grid| {
-                        for y in 0..size.1 {
-                            for x in 0..size.0 {
+                        for _ in 0..size.1 {
+                            for _ in 0..size.0 {
                                 grid.spawn((
                                     Node {
                                         width: Val::Px(50.0),
@@ -116,7 +118,7 @@ fn hover(
     mut slot_query: Query<(&Interaction, &mut BackgroundColor), With<Hoverable>>,
 ) {
     let window = window_query.single().unwrap();
-    if let Some(cursor_pos) = window.cursor_position() {
+    if window.cursor_position().is_some() {
         for (interaction, mut background_color) in slot_query.iter_mut() {
             match interaction {
                 Interaction::Hovered => *background_color = Color::srgba(0.5, 0.5, 1.0, 0.8).into(),
@@ -1,6 +1,8 @@
 use std::fmt::Display;
 
+#[derive(Default)]
 pub enum Item {
+    #[default]
     UnknownItem,
     MeleeWeapon(MeleeWeaponItem),
     RangedWeapon(RangedWeaponItem),
@@ -8,8 +10,16 @@ pub enum Item {
     //Resource(ResourceItem),
 }
 
-impl Display for Item { fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "Item: {}", &self) } }
-impl Default for Item { fn default() -> Self { Item::UnknownItem } }
+impl Display for Item {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Item::UnknownItem => "unknown",
+            Item::MeleeWeapon(_) => "melee weapon",
+            Item::RangedWeapon(_) => "ranged weapon",
+        };
+        write!(f, "Item: {kind}")
+    }
+}
 impl Default for &Item { fn default() -> Self { &Item::UnknownItem } }
 
 pub struct MeleeWeaponItem {
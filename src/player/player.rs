@@ -4,6 +4,12 @@ use bevy_rapier3d::prelude::*;
 use crate::{player::{camera_controller::{update_camera_controller, CameraController}, config::player_config::PlayerConfig, player_attack::debug_shoot_bullet, player_input::{apply_player_movement, handle_player_input}, player_state::PlayerState}, terrain::grid::CurrentChunk};
 pub struct PlayerPlugin;
 
+pub const SPAWN_POSITION: Vec3 = Vec3::new(2080., 70., 2080.);
+
+/// Half the length of the straight part of the player's capsule, and its radius.
+pub const CAPSULE_HALF_HEIGHT: f32 = 0.9;
+pub const CAPSULE_RADIUS: f32 = 0.25;
+
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app
@@ -68,8 +74,8 @@ fn spawn_player(mut commands: Commands) {
 
     let player_entity = commands.spawn((
         Player::default(),
-        Transform::from_xyz(2080., 70., 2080.),
-        Collider::capsule_y(0.9, 0.25),
+        Transform::from_translation(SPAWN_POSITION),
+        Collider::capsule_y(CAPSULE_HALF_HEIGHT, CAPSULE_RADIUS),
         RigidBody::KinematicPositionBased,
         KinematicCharacterController {
             up: Vec3::Y,
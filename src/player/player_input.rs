@@ -1,19 +1,24 @@
-use bevy::{input::mouse::MouseWheel, prelude::*, reflect::Enum, window::PrimaryWindow};
+use bevy::{input::mouse::MouseWheel, prelude::*, window::PrimaryWindow};
 use bevy_rapier3d::prelude::{KinematicCharacterController, KinematicCharacterControllerOutput};
 
-use crate::{init::Physics, player::{camera_controller::CameraController, config::player_config::{InputBinding, PlayerAction::{self, *}, PressKind}, cursor::Cursor, player::Player, player_attack::DebugShootEvent, player_state::ToggleInventory}, simulation::world::{WorldState, GRAVITY}, terrain::chunks::RenderDistance};
+use crate::{init::Physics, player::{camera_controller::CameraController, config::player_config::{InputBinding, PlayerAction::{self, *}, PressKind}, cursor::Cursor, player::Player, player_attack::DebugShootEvent, player_state::ToggleInventory}, simulation::world::GRAVITY, terrain::{chunks::RenderDistance, edits::{BrushKind, DebugSaveTerrainEditsEvent, DebugTerraformEvent}}};
+
+/// Water deeper than this over the feet lifts the player off the ground to swim.
+const SWIM_DEPTH: f32 = 1.3;
+/// Fraction of the speed left wading waist deep and swimming.
+const WATER_SPEED: f32 = 0.5;
+/// Vertical speed swimming up or down, and how quickly the player floats back to `SWIM_DEPTH`.
+const SWIM_VERTICAL_SPEED: f32 = 2.0;
+const BUOYANCY: f32 = 2.0;
 
 pub fn handle_player_input(
     mut player_query: Query<(&mut Player, &Transform)>, 
     camera_query: Query<&CameraController>,
-    keys: Res<ButtonInput<KeyCode>>,
-    mouse_buttons: Res<ButtonInput<MouseButton>>,
-    mut scroll_events: EventReader<MouseWheel>,
+    (keys, mouse_buttons, mut scroll_events): (Res<ButtonInput<KeyCode>>, Res<ButtonInput<MouseButton>>, EventReader<MouseWheel>),
     mut render_distance: ResMut<RenderDistance>,
-    mut debug_shoot: EventWriter<DebugShootEvent>,
+    (mut debug_shoot, mut debug_terraform, mut debug_save_edits): (EventWriter<DebugShootEvent>, EventWriter<DebugTerraformEvent>, EventWriter<DebugSaveTerrainEditsEvent>),
     mut toggle_inventory: EventWriter<ToggleInventory>,
-    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
-    mut cursor: ResMut<Cursor>,
+    (mut window_query, mut cursor): (Query<&mut Window, With<PrimaryWindow>>, ResMut<Cursor>),
 ) {
     let (mut player, transform) = player_query.single_mut().unwrap();
     let mut window = window_query.single_mut().unwrap();
@@ -52,6 +57,12 @@ pub fn handle_player_input(
 
             DebugShootBullet => { let _ = debug_shoot.write(DebugShootEvent((*transform, forwards))); }, 
 
+            DebugRaiseTerrain => { let _ = debug_terraform.write(DebugTerraformEvent((*transform, forwards, BrushKind::Raise))); },
+            DebugLowerTerrain => { let _ = debug_terraform.write(DebugTerraformEvent((*transform, forwards, BrushKind::Lower))); },
+            DebugFlattenTerrain => { let _ = debug_terraform.write(DebugTerraformEvent((*transform, forwards, BrushKind::Flatten))); },
+            DebugSmoothTerrain => { let _ = debug_terraform.write(DebugTerraformEvent((*transform, forwards, BrushKind::Smooth))); },
+            DebugSaveTerrainEdits => { let _ = debug_save_edits.write(DebugSaveTerrainEditsEvent); },
+
             DebugIncreaseRenderDistance => render_distance.0 += 1,
             DebugDecreaseRenderDistance => { render_distance.0 = render_distance.0.saturating_sub(1) },
 
@@ -79,14 +90,10 @@ pub fn handle_player_input(
     }
 
     for ev in scroll_events.read() {
-        if ev.y > 0.0 {
-            if let Some((action, PressKind::MonoStable)) = keymap.get(&InputBinding::MouseWheelUp) {
-                apply_action(*action);
-            }
-        } else if ev.y < 0.0 {
-            if let Some((action, PressKind::MonoStable)) = keymap.get(&InputBinding::MouseWheelDown) {
-                apply_action(*action);
-            }
+        if ev.y > 0.0 && let Some((action, PressKind::MonoStable)) = keymap.get(&InputBinding::MouseWheelUp) {
+            apply_action(*action);
+        } else if ev.y < 0.0 && let Some((action, PressKind::MonoStable)) = keymap.get(&InputBinding::MouseWheelDown) {
+            apply_action(*action);
         }
     }
 }
@@ -99,7 +106,6 @@ pub fn apply_player_movement(
         &mut KinematicCharacterController,
         Option<&KinematicCharacterControllerOutput>
     )>,
-    world_state: Res<WorldState>,
     mut text_query: Query<&mut Text, With<Physics>>
 ) {
     let camera = camera_query.single().unwrap();
@@ -117,8 +123,12 @@ pub fn apply_player_movement(
 
         //movement.y += player.direction.y;
 
+        //Slows down the deeper the water gets, up to swimming
+        let wading = (player.state.water_depth / SWIM_DEPTH).clamp(0.0, 1.0);
+        let water_drag = 1.0 - wading * (1.0 - WATER_SPEED);
+
         if movement.length_squared() > 0.0 {
-            movement = movement.normalize() * player.speed * player.speed_multiplier;
+            movement = movement.normalize() * player.speed * player.speed_multiplier * water_drag;
             player.momentum.x = movement.x;
             player.momentum.z = movement.z;
         }
@@ -128,10 +138,21 @@ pub fn apply_player_movement(
             if player.direction.y != 0.0 {
                 player.momentum.y += player.direction.y * delta;
             }
+        } else if player.state.water_depth > SWIM_DEPTH * 0.8 {
+            //Floats until the water is `SWIM_DEPTH` over the feet, the fly keys swim up and down
+            grounded = "Swimming";
+            let float = ((player.state.water_depth - SWIM_DEPTH) * BUOYANCY).clamp(-SWIM_VERTICAL_SPEED, SWIM_VERTICAL_SPEED);
+            player.momentum.y = float + player.direction.y * SWIM_VERTICAL_SPEED;
+
+            if player.direction.x == 0.0 && player.direction.z == 0.0 {
+                player.momentum.x = 0.0;
+                player.momentum.z = 0.0;
+            }
         } else if let Some(output) = output {
-            grounded = match output.grounded {
-                true => "Grounded",
-                false => "Not grounded"
+            grounded = match (output.grounded, player.state.water_depth > 0.0) {
+                (true, true) => "Wading",
+                (true, false) => "Grounded",
+                (false, _) => "Not grounded"
             };
             if !output.grounded { 
                 player.momentum.y -= GRAVITY * delta; 
@@ -1,11 +1,11 @@
-use bevy::ecs::event::{Event, EventWriter};
-
-use crate::player::player::Player;
+use bevy::ecs::event::Event;
 
 pub struct PlayerState {
     pub is_pressing_movement_key: bool,
     pub debug_flying: bool,
     pub inventory_open: bool,
+    /// Metres of water above the player's feet, zero on dry land.
+    pub water_depth: f32,
 }
 
 impl Default for PlayerState {
@@ -14,6 +14,7 @@ impl Default for PlayerState {
             is_pressing_movement_key: false, 
             debug_flying: false,
             inventory_open: true,
+            water_depth: 0.0,
         }
     }
 }
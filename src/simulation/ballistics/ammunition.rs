@@ -10,7 +10,10 @@ pub struct Bullet {
 
     mass: f32,
     magnus: f32,
+    //Not part of the drag model yet
+    #[allow(dead_code)]
     diameter: f32,
+    #[allow(dead_code)]
     ballistic_coefficient: f32,
     drag_coefficient: f32,
     cross_section: f32,
@@ -1,6 +1,6 @@
-use bevy::{color::palettes::css::{ALICE_BLUE, WHITE}, pbr::light_consts::lux::{FULL_DAYLIGHT, FULL_MOON_NIGHT}, prelude::*};
+use bevy::{color::palettes::css::{ALICE_BLUE, WHITE}, pbr::light_consts::lux::{FULL_DAYLIGHT, FULL_MOON_NIGHT}, prelude::*, render::view::RenderLayers};
 
-use crate::simulation::world::WorldState;
+use crate::{simulation::world::WorldState, terrain::occlusion::OCCLUDED_LAYER};
 
 pub struct DaylightCyclePlugin;
 
@@ -16,14 +16,16 @@ impl Plugin for DaylightCyclePlugin {
 pub struct Sun {
     pub transform: Transform,
     pub light: DirectionalLight,
+    /// Lights what the camera sees and what the terrain hides from it, for its shadows.
+    pub layers: RenderLayers,
     sun_comp: SunComp
 }
 
 #[derive(Component)]
 pub struct SunComp;
 
-impl Sun {
-    pub fn new() -> Self {
+impl Default for Sun {
+    fn default() -> Self {
         Sun { 
             transform: Transform::from_xyz(0., 0., 0.),
             light: DirectionalLight { 
@@ -31,6 +33,7 @@ impl Sun {
                 illuminance: FULL_DAYLIGHT, 
                 ..Default::default()
             },
+            layers: RenderLayers::from_layers(&[0, OCCLUDED_LAYER]),
             sun_comp: SunComp
         }
     }
@@ -40,14 +43,15 @@ impl Sun {
 pub struct Moon {
     pub transform: Transform,
     pub light: DirectionalLight,
+    pub layers: RenderLayers,
     moon_comp: MoonComp
 }
 
 #[derive(Component)]
 pub struct MoonComp;
 
-impl Moon {
-    pub fn new() -> Self {
+impl Default for Moon {
+    fn default() -> Self {
         Moon {
             transform: Transform::from_xyz(0., 0., 0.),
             light: DirectionalLight {
@@ -55,22 +59,26 @@ impl Moon {
                 illuminance: FULL_MOON_NIGHT,
                 ..Default::default()
             },
+            layers: RenderLayers::from_layers(&[0, OCCLUDED_LAYER]),
             moon_comp: MoonComp
         }
     }
 }
 
 fn spawn_sun_and_moon(mut commands: Commands) {
-    commands.spawn(Sun::new()); 
-    commands.spawn(Moon::new()); 
+    commands.spawn(Sun::default()); 
+    commands.spawn(Moon::default()); 
 }
 
+/// Light and rotation of the sun, then of the moon.
+type CelestialQueries<'w, 's> = ParamSet<'w, 's, (
+    Query<'static, 'static, (&'static mut DirectionalLight, &'static mut Transform), With<SunComp>>,
+    Query<'static, 'static, (&'static mut DirectionalLight, &'static mut Transform), With<MoonComp>>,
+)>;
+
 fn cycle_daylight(
     world_state: ResMut<WorldState>, 
-    mut celestial_query: ParamSet<(
-        Query<(&mut DirectionalLight, &mut Transform), With<SunComp>>,
-        Query<(&mut DirectionalLight, &mut Transform), With<MoonComp>>,
-    )>,
+    mut celestial_query: CelestialQueries,
 ) {
     let hour = world_state.get_hour();
     let th = ((*hour + 5.0) * 15.0).to_radians();
@@ -107,15 +115,15 @@ fn kelvin_to_rgb(k: f32) -> (u8, u8, u8) {
         red = 255.0;
     } else {
         red = temp - 60.0;
-        red = 329.698727446 * red.powf(-0.1332047592);
+        red = 329.69873 * red.powf(-0.13320476);
         red = red.clamp(0.0, 255.0);
     }
 
     if temp <= 66.0 {
-        green = 99.4708025861 * temp.ln() - 161.1195681661;
+        green = 99.4708 * temp.ln() - 161.11957;
     } else {
         green = temp - 60.0;
-        green = 288.1221695283 * green.powf(-0.0755148492);
+        green = 288.12217 * green.powf(-0.075514849);
     }
     green = green.clamp(0.0, 255.0);
 
@@ -125,7 +133,7 @@ fn kelvin_to_rgb(k: f32) -> (u8, u8, u8) {
         blue = 0.0;
     } else {
         blue = temp - 10.0;
-        blue = 138.5177312231 * blue.ln() - 305.0447927307;
+        blue = 138.51773 * blue.ln() - 305.0448;
     }
     blue = blue.clamp(0.0, 255.0);
 
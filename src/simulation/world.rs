@@ -1,8 +1,9 @@
 use bevy::prelude::*;
 use bevy::{app::{Plugin, Update}, ecs::system::{Query, Res}, time::Time};
 
-use crate::player::player_attack::DebugShootEvent;
+use crate::player::{player::Player, player_attack::DebugShootEvent};
 use crate::simulation::ballistics::ammunition::{Ballistics, Bullet};
+use crate::terrain::chunks::Chunkbase;
 
 pub const GRAVITY: f32 = 9.81;
 const AIR_PRESSURE: f32 = 101325_f32;
@@ -14,7 +15,7 @@ impl Plugin for BallisticsPlugin {
     fn build(&self, app: &mut bevy::app::App) {
         app
             .add_event::<DebugShootEvent>()
-            .add_systems(Update, (step_time, step_projectiles));
+            .add_systems(Update, (step_time, update_temperature, step_projectiles));
     }
 }
 
@@ -47,6 +48,11 @@ impl WorldState {
     pub fn second_passed(&self) -> bool {
         self.second_passed
     }
+
+    /// Air temperature in degrees Celsius where the player stands.
+    pub fn temperature(&self) -> f32 {
+        self.temperature
+    }
 }
 
 fn step_time(mut world_state: ResMut<WorldState>, time: Res<Time>) {
@@ -57,14 +63,34 @@ fn step_time(mut world_state: ResMut<WorldState>, time: Res<Time>) {
     }
 }
 
+/// Takes the temperature from the climate of the biome under the player, colder higher up.
+fn update_temperature(mut world_state: ResMut<WorldState>, chunkbase: Res<Chunkbase>, player_query: Query<&Transform, With<Player>>) {
+    let Ok(transform) = player_query.single() else { return };
+    let Some(biomes) = chunkbase.biome_map() else { return };
+    let (x, z) = (transform.translation.x, transform.translation.z);
+    let Some(ground) = chunkbase.height_at(x, z) else { return };
+
+    world_state.temperature = biomes.climate_at(x, z, ground).temperature;
+}
+
 fn step_projectiles(
     time: Res<Time>,
-    mut projectiles: Query<(&mut Bullet, &mut Transform)>
+    chunkbase: Res<Chunkbase>,
+    mut commands: Commands,
+    mut projectiles: Query<(Entity, &mut Bullet, &mut Transform)>
 ) {
     let delta_time = time.delta_secs();
 
-    for (mut ballistics, mut transform) in projectiles.iter_mut() {
+    for (entity, mut ballistics, mut transform) in projectiles.iter_mut() {
+        let previous = ballistics.position;
         ballistics.step(delta_time); 
         transform.translation = ballistics.position;
+
+        //Fast bullets can skip through a heightfield between physics steps, so test the whole path
+        let travel = ballistics.position - previous;
+        if let Some(hit) = chunkbase.raycast_terrain(previous, travel, travel.length()) {
+            debug!("Bullet hit the terrain at {}", hit.point);
+            commands.entity(entity).despawn();
+        }
     }
 }
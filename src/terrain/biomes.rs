@@ -0,0 +1,193 @@
+use bevy::{color::{Color, ColorToComponents, LinearRgba}, math::Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{noise::perlin_cpu::PerlinCPU, terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH}, terrain_config::{BiomeConfig, TerrainGenConfig}}};
+
+pub const BIOME_COUNT: usize = 5;
+
+/// Offsets the world seed for each climate channel so they don't repeat the terrain noise.
+const TEMPERATURE_SEED: u64 = 0x7e3d;
+const MOISTURE_SEED: u64 = 0x30a1;
+
+/// Fractal noise rarely leaves about ±0.33, this stretches it over the whole climate range.
+const CLIMATE_CONTRAST: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    Grassland,
+    Forest,
+    Desert,
+    Tundra,
+    /// Bare rock above `BiomeConfig::rock_height`, whatever the climate.
+    Rock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Climate {
+    /// Degrees Celsius at ground level, altitude included.
+    pub temperature: f32,
+    /// From 0, arid, to 1, wet.
+    pub moisture: f32,
+}
+
+/// How much each `Biome` contributes to one heightmap sample, indexed like `Biome::ALL`
+/// and quantised so they add up to about 255.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BiomeWeights(pub [u8; BIOME_COUNT]);
+
+/// Temperature and moisture noise over the world and the biomes they make.
+#[derive(Debug, Clone)]
+pub struct BiomeMap {
+    config: BiomeConfig,
+    sea_level: f32,
+    temperature: PerlinCPU,
+    moisture: PerlinCPU,
+}
+
+impl Biome {
+    pub const ALL: [Biome; BIOME_COUNT] = [Biome::Grassland, Biome::Forest, Biome::Desert, Biome::Tundra, Biome::Rock];
+
+    /// Temperature and moisture the biome is most typical of, `None` for the ones set by altitude.
+    fn typical_climate(self) -> Option<Climate> {
+        match self {
+            Biome::Grassland => Some(Climate { temperature: 15.0, moisture: 0.4 }),
+            Biome::Forest => Some(Climate { temperature: 12.0, moisture: 0.75 }),
+            Biome::Desert => Some(Climate { temperature: 27.0, moisture: 0.15 }),
+            Biome::Tundra => Some(Climate { temperature: -2.0, moisture: 0.5 }),
+            Biome::Rock => None,
+        }
+    }
+
+    pub fn colour(self) -> Color {
+        match self {
+            Biome::Grassland => Color::srgb_u8(96, 150, 56),
+            Biome::Forest => Color::srgb_u8(34, 100, 34),
+            Biome::Desert => Color::srgb_u8(210, 185, 120),
+            Biome::Tundra => Color::srgb_u8(205, 210, 215),
+            Biome::Rock => Color::srgb_u8(120, 115, 110),
+        }
+    }
+
+    /// Scale of the relief above sea level, deserts are flattened and rock is sharpened.
+    fn relief(self) -> f32 {
+        match self {
+            Biome::Grassland => 0.9,
+            Biome::Forest => 1.0,
+            Biome::Desert => 0.6,
+            Biome::Tundra => 1.0,
+            Biome::Rock => 1.15,
+        }
+    }
+}
+
+impl BiomeWeights {
+    pub fn only(biome: Biome) -> Self {
+        let mut weights = [0; BIOME_COUNT];
+        weights[biome as usize] = u8::MAX;
+        BiomeWeights(weights)
+    }
+
+    fn quantise(weights: [f32; BIOME_COUNT]) -> Self {
+        BiomeWeights(weights.map(|weight| (weight * u8::MAX as f32).round() as u8))
+    }
+
+    /// The biome with the largest weight.
+    pub fn dominant(&self) -> Biome {
+        let (index, _) = self.0.iter().enumerate().max_by_key(|(index, weight)| (**weight, std::cmp::Reverse(*index))).unwrap();
+        Biome::ALL[index]
+    }
+
+    /// Weights of the first four biomes in `[0, 1]`, the rock weight being whatever is left.
+    /// Fits the RGBA vertex colour and textures the terrain material reads them from.
+    pub fn splat(&self) -> [f32; 4] {
+        [0, 1, 2, 3].map(|index| self.0[index] as f32 / u8::MAX as f32)
+    }
+
+    /// Biome colours blended by weight, in linear space.
+    pub fn colour(&self) -> LinearRgba {
+        let total = self.0.iter().map(|weight| *weight as f32).sum::<f32>().max(1.0);
+        let rgb = Biome::ALL.iter().zip(self.0).fold([0.0; 3], |rgb, (biome, weight)| {
+            let colour = biome.colour().to_linear().to_f32_array();
+            let t = weight as f32 / total;
+            [rgb[0] + colour[0] * t, rgb[1] + colour[1] * t, rgb[2] + colour[2] * t]
+        });
+        LinearRgba::rgb(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+impl Default for BiomeWeights {
+    fn default() -> Self {
+        BiomeWeights::only(Biome::Grassland)
+    }
+}
+
+impl BiomeMap {
+    pub fn new(config: &TerrainGenConfig) -> Self {
+        let biomes = config.biomes;
+        let channel = |seed| PerlinCPU::new(seed, biomes.climate_scale, biomes.climate_octaves as usize, 2.0, 0.5);
+
+        BiomeMap {
+            config: biomes,
+            sea_level: config.sea_level,
+            temperature: channel(config.seed ^ TEMPERATURE_SEED),
+            moisture: channel(config.seed ^ MOISTURE_SEED),
+        }
+    }
+
+    /// Climate at world position `(x, z)` on ground `height` metres high.
+    pub fn climate_at(&self, x: f32, z: f32, height: f32) -> Climate {
+        let scale = self.config.climate_scale;
+        let temperature = (self.temperature.from_fractal(x * scale, z * scale) * CLIMATE_CONTRAST + 0.5).clamp(0.0, 1.0);
+        let moisture = (self.moisture.from_fractal(x * scale, z * scale) * CLIMATE_CONTRAST + 0.5).clamp(0.0, 1.0);
+
+        Climate {
+            temperature: self.config.min_temperature + temperature * (self.config.max_temperature - self.config.min_temperature)
+                - (height - self.sea_level).max(0.0) * self.config.lapse_rate,
+            moisture,
+        }
+    }
+
+    /// Weight of every biome at `(x, z)` for ground `height` metres high, summing to one.
+    /// They change smoothly with position so biome borders blend instead of cutting.
+    pub fn weights_at(&self, x: f32, z: f32, height: f32) -> [f32; BIOME_COUNT] {
+        let climate = self.climate_at(x, z, height);
+        let span = self.config.max_temperature - self.config.min_temperature;
+        let sharpness = self.config.blend.max(f32::EPSILON).powi(2);
+
+        let mut weights = Biome::ALL.map(|biome| match biome.typical_climate() {
+            Some(typical) => {
+                let distance = Vec2::new((climate.temperature - typical.temperature) / span, climate.moisture - typical.moisture);
+                (-distance.length_squared() / sharpness).exp()
+            }
+            None => 0.0,
+        });
+
+        let total = weights.iter().sum::<f32>().max(f32::EPSILON);
+        let rock = smoothstep(self.config.rock_height - self.config.rock_blend, self.config.rock_height + self.config.rock_blend, height);
+        for (biome, weight) in Biome::ALL.iter().zip(&mut weights) {
+            *weight = if *biome == Biome::Rock { rock } else { *weight / total * (1.0 - rock) };
+        }
+        weights
+    }
+
+    /// Reshapes the heights of a chunk, halo included, by the relief of the biomes they fall
+    /// in, and returns their weights.
+    pub fn shape_chunk(&self, (chunk_x, chunk_y): (i32, i32), heights: &mut [f32]) -> Vec<BiomeWeights> {
+        let origin = (chunk_x * CHUNK_WIDTH as i32, chunk_y * CHUNK_HEIGHT as i32);
+
+        heights.iter_mut().enumerate().map(|(index, height)| {
+            let (x, z) = (origin.0 + (index % (CHUNK_WIDTH + 1)) as i32, origin.1 + (index / (CHUNK_WIDTH + 1)) as i32);
+            let weights = self.weights_at(x as f32, z as f32, *height);
+
+            let relief: f32 = Biome::ALL.iter().zip(weights).map(|(biome, weight)| biome.relief() * weight).sum();
+            *height = self.sea_level + (*height - self.sea_level) * relief;
+
+            BiomeWeights::quantise(weights)
+        }).collect()
+    }
+}
+
+fn smoothstep(low: f32, high: f32, value: f32) -> f32 {
+    let t = ((value - low) / (high - low)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,19 +1,58 @@
-use std::{collections::HashMap};
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::Arc};
 
 use bevy::prelude::*;
-use bevy::{asset::RenderAssetUsages, ecs::{entity::Entity, resource::Resource}, render::mesh::{Indices, Mesh, PrimitiveTopology}, tasks::block_on};
+use bevy::{asset::RenderAssetUsages, ecs::{entity::Entity, resource::Resource}, render::mesh::{Indices, Mesh, PrimitiveTopology, VertexAttributeValues}, tasks::{block_on, poll_once, AsyncComputeTaskPool, Task}};
 use bevy_rapier3d::prelude::Collider;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tracing::{error, warn};
 
-use crate::noise::{perlin::Perlin};
+use crate::{noise::{erosion::Erosion, heightmap_backend::HeightmapBackend}, terrain::{biomes::{Biome, BiomeMap, BiomeWeights}, edits::TerrainEdits, region::RegionCache, water::{Lake, LakeFinder, LAKE_PADDING}}};
 
 pub const MAP_WIDTH: usize = 32;
 pub const MAP_HEIGHT: usize = 32;
 pub const CHUNK_HEIGHT: usize = 128;
 pub const CHUNK_WIDTH: usize = 128;
 
-#[derive(Resource, Debug)]
-pub struct Chunkbase(HashMap<(i32, i32), Chunk>);
+/// Extra depth below the lowest edge sample that skirts hang down to.
+const SKIRT_MARGIN: f32 = 1.0;
+
+/// Heightfields are centred on their origin, chunk meshes start at their corner.
+pub const HEIGHTFIELD_OFFSET: Vec3 = Vec3::new(CHUNK_WIDTH as f32 / 2.0, 0.0, CHUNK_HEIGHT as f32 / 2.0);
+
+/// Sample step of the mesh of each LOD, `Chunk::mesh` to `Chunk::mesh_4`.
+pub const LOD_STEPS: [usize; 3] = [1, 2, 4];
+
+/// Chunks kept in memory before the least recently used ones are evicted.
+/// Raised on demand so the current render area always fits.
+pub const CHUNK_CACHE_CAPACITY: usize = 1024;
+
+/// Lazily generated, unbounded chunk store. Chunks are generated on the
+/// `AsyncComputeTaskPool` the first time they are requested and evicted
+/// least-recently-used first once `capacity` is exceeded. Terrain edits are layered
+/// over the generated heights and survive eviction.
+#[derive(Resource)]
+pub struct Chunkbase {
+    backend: HeightmapBackend,
+    erosion: Option<Arc<Erosion>>,
+    biomes: Option<Arc<BiomeMap>>,
+    lakes: Option<Arc<LakeFinder>>,
+    cache: Option<Arc<RegionCache>>,
+    edits: TerrainEdits,
+    chunks: HashMap<(i32, i32), (Chunk, u64)>,
+    pending: HashMap<(i32, i32), Task<anyhow::Result<GeneratedChunk>>>,
+    pending_colliders: HashMap<((i32, i32), usize), Task<Collider>>,
+    rebuilds: HashMap<(i32, i32), Task<GeneratedChunk>>,
+    /// Chunks edited while generating or rebuilding, redone once that finishes.
+    dirty: HashSet<(i32, i32)>,
+    /// Chunks whose lakes are flooded again over their neighbours once all of them are loaded.
+    unflooded: HashSet<(i32, i32)>,
+    pending_lakes: HashMap<(i32, i32), Task<Vec<Lake>>>,
+    lru: BTreeMap<u64, (i32, i32)>,
+    tick: u64,
+    capacity: usize,
+    /// Bumped whenever a chunk, collider or rebuild lands or a chunk is evicted.
+    changes: u64,
+}
 
 #[derive(Resource, Default)]
 pub struct RenderedChunks(pub HashMap<(i32, i32), Entity>);
@@ -23,192 +62,706 @@ pub struct RenderDistance(pub u32);
 
 
 impl Chunkbase { 
-    pub fn new(perlin: &Perlin) -> Self { 
-        let workgroup_size_x = 8;
-        let workgroup_size_y = 8;
+    pub fn new(backend: HeightmapBackend, capacity: usize) -> Self { 
+        Chunkbase {
+            backend,
+            erosion: None,
+            biomes: None,
+            lakes: None,
+            cache: None,
+            edits: TerrainEdits::default(),
+            chunks: HashMap::new(),
+            pending: HashMap::new(),
+            pending_colliders: HashMap::new(),
+            rebuilds: HashMap::new(),
+            dirty: HashSet::new(),
+            unflooded: HashSet::new(),
+            pending_lakes: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            capacity,
+            changes: 0,
+        }
+    }
 
-        let total_width  = MAP_WIDTH * CHUNK_WIDTH;
-        let total_height = MAP_HEIGHT * CHUNK_HEIGHT;
+    /// Erodes the noise before meshing it.
+    pub fn with_erosion(mut self, erosion: Erosion) -> Self {
+        self.erosion = Some(Arc::new(erosion));
+        self
+    }
 
-        let workgroups_x = (total_width  + workgroup_size_x - 1) / workgroup_size_x;
-        let workgroups_y = (total_height + workgroup_size_y - 1) / workgroup_size_y;
+    /// Reshapes and colours the terrain by biome. Without a map every chunk is grassland.
+    pub fn with_biomes(mut self, biomes: BiomeMap) -> Self {
+        self.biomes = Some(Arc::new(biomes));
+        self
+    }
 
-        let heightmap = block_on(perlin.compute_from_fractal((workgroups_x as u32, workgroups_y as u32, 1))).unwrap();
-        let chunkmap: &[[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT]; 16384] =  unsafe { &*(heightmap.as_ptr() as *const [[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT]; 16384]) };
+    pub fn biome_map(&self) -> Option<&BiomeMap> {
+        self.biomes.as_deref()
+    }
 
-        let chunks: HashMap<(i32, i32), Chunk> = (0..MAP_HEIGHT)
-            .into_par_iter()
-            .flat_map_iter(|chunk_y| (0..MAP_WIDTH).map(move |chunk_x| (chunk_x, chunk_y)))
-            .map(|(x, y)| {
-                let slice: &[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT] = &chunkmap[x + y * MAP_WIDTH];
-
-                let mut halo: [f32; CHUNK_WIDTH + CHUNK_HEIGHT + 1] = [0.0; CHUNK_WIDTH + CHUNK_HEIGHT + 1];
-                if x < MAP_WIDTH - 1 && y < MAP_HEIGHT - 1 {
-                    //Init a halo array that holds
-                    //[x; 32] for horizontal haloing
-                    //[y; 32] for vertical haloing
-                    //[z] for bottom-right corner haloing
-
-                    //When y = 33
-                    //[x; 32] = 
-                    halo[0..CHUNK_WIDTH].copy_from_slice(&chunkmap[x + (y + 1) * MAP_WIDTH][0]);
-
-                    // When x = 33
-                    //[y; 32] =
-                    for cy in 0..CHUNK_HEIGHT {
-                        halo[CHUNK_WIDTH + cy] = chunkmap[(x + 1) + y * MAP_WIDTH][cy][0];
-                    }
-
-                    //When x, y = 33
-                    //[z] = curr x + 1, curr y + 1
-                    halo[CHUNK_HEIGHT + CHUNK_WIDTH] = chunkmap[(x + 1) + ((y + 1) * MAP_WIDTH)][0][0]; 
-                }
+    /// Dominant biome of the sample nearest to `(x, z)`, `None` if its chunk isn't loaded.
+    pub fn biome_at(&self, x: f32, z: f32) -> Option<Biome> {
+        let (sample_x, sample_z) = (x.round() as i32, z.round() as i32);
+        let (width, height) = (CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
+        let chunk = self.get_chunk(&(sample_x.div_euclid(width), sample_z.div_euclid(height)))?;
+        let index = sample_z.rem_euclid(height) as usize * (CHUNK_WIDTH + 1) + sample_x.rem_euclid(width) as usize;
+        Some(chunk.biomes[index].dominant())
+    }
 
-                let mut chunk_data = ChunkData::new(slice, &halo, 1);
-                let mut chunk_data_2 = ChunkData::new(slice, &halo, 2);
-                let mut chunk_data_4 = ChunkData::new(slice, &halo, 4);
-
-                let mesh = chunk_data.into_mesh_with_normals();
-                let mesh_2 = chunk_data_2.into_mesh_with_normals();
-                let mesh_4 = chunk_data_4.into_mesh_with_normals();
-
-                let flat_slice = slice.iter().flat_map(|row| row.iter().copied()).collect();
-                let collider = generate_heightfield(flat_slice, 16);
-                let chunk = Chunk { 
-                    transform: Transform::from_xyz((x * CHUNK_WIDTH) as f32, 0., (y * CHUNK_HEIGHT) as f32), 
-                    collider: collider,
-                    mesh,
-                    mesh_2,
-                    mesh_4
-                };
-
-                ((x as i32, y as i32), chunk)
-            }).collect();
-             
-        Chunkbase(chunks)
+    /// Looks for lakes in every chunk generated or rebuilt from now on.
+    pub fn with_lakes(mut self, lakes: LakeFinder) -> Self {
+        self.lakes = Some(Arc::new(lakes));
+        self
+    }
+
+    /// Loads chunks from `cache` when it has them and stores every chunk generated from noise.
+    pub fn with_region_cache(mut self, cache: RegionCache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Starts from previously saved edits. Only chunks generated afterwards pick them up.
+    pub fn with_edits(mut self, edits: TerrainEdits) -> Self {
+        self.edits = edits;
+        self
+    }
+
+    pub fn edits(&self) -> &TerrainEdits {
+        &self.edits
     }
 
     pub fn get_chunk(&self, coordinates: &(i32, i32)) -> Option<&Chunk> {
-        self.0.get(coordinates)
+        self.chunks.get(coordinates).map(|(chunk, _)| chunk)
+    }
+
+    /// Marks a chunk as in use, queueing its generation if it is neither loaded nor pending.
+    pub fn request(&mut self, coordinates: (i32, i32)) {
+        if self.chunks.contains_key(&coordinates) {
+            self.touch(coordinates);
+        } else if !self.pending.contains_key(&coordinates) {
+            let backend = self.backend.clone();
+            let erosion = self.erosion.clone();
+            let biomes = self.biomes.clone();
+            let lakes = self.lakes.clone();
+            let cache = self.cache.clone();
+            let edits = self.edits.chunk_deltas(coordinates);
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let generated = Chunk::generate(&backend, erosion.as_deref(), biomes.as_deref(), cache.as_deref(), &edits, coordinates).await?;
+                Ok(generated.with_lakes(lakes.as_deref()))
+            });
+            self.pending.insert(coordinates, task);
+        }
+    }
+
+    /// Drops the generation and collider tasks of chunks no longer `wanted`, e.g. ones the
+    /// player moved away from before they finished.
+    pub fn cancel_unwanted(&mut self, wanted: impl Fn(&(i32, i32)) -> bool) {
+        let dirty = &mut self.dirty;
+        self.pending.retain(|coordinates, _| {
+            let keep = wanted(coordinates);
+            if !keep {
+                dirty.remove(coordinates);
+            }
+            keep
+        });
+        self.pending_colliders.retain(|(coordinates, _), _| wanted(coordinates));
+    }
+
+    /// Generates a chunk on the calling thread, e.g. under the player before the first frame.
+    pub fn generate_blocking(&mut self, coordinates: (i32, i32), meshes: &mut Assets<Mesh>) {
+        if self.chunks.contains_key(&coordinates) {
+            return;
+        }
+
+        self.pending.remove(&coordinates);
+        self.dirty.remove(&coordinates);
+        let edits = self.edits.chunk_deltas(coordinates);
+        match block_on(Chunk::generate(&self.backend, self.erosion.as_deref(), self.biomes.as_deref(), self.cache.as_deref(), &edits, coordinates)) {
+            Ok(generated) => self.insert(coordinates, generated.with_lakes(self.lakes.as_deref()).into_chunk(meshes)),
+            Err(e) => error!("Failed to generate chunk {coordinates:?}: {e}"),
+        }
+    }
+
+    /// Queues a heightfield collider sampling every `step`-th height of a loaded chunk.
+    pub fn request_collider(&mut self, coordinates: (i32, i32), step: usize) {
+        let Some((chunk, _)) = self.chunks.get(&coordinates) else { return };
+        if chunk.colliders.contains_key(&step) || self.pending_colliders.contains_key(&(coordinates, step)) {
+            return;
+        }
+
+        let heights = chunk.heights.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move { generate_heightfield(&heights, step) });
+        self.pending_colliders.insert((coordinates, step), task);
+    }
+
+    /// Builds a collider on the calling thread, see `generate_blocking`.
+    pub fn generate_collider_blocking(&mut self, coordinates: (i32, i32), step: usize) {
+        self.pending_colliders.remove(&(coordinates, step));
+        if let Some((chunk, _)) = self.chunks.get_mut(&coordinates) {
+            let collider = generate_heightfield(&chunk.heights, step);
+            chunk.colliders.entry(step).or_insert(collider);
+            self.changes += 1;
+        }
+    }
+
+    /// Adds `(world sample, height delta)` pairs to the edit layer and to every loaded chunk
+    /// holding those samples, halos included, then rebuilds the chunks that changed.
+    pub fn edit_heights(&mut self, changes: &[((i32, i32), f32)]) {
+        let (width, height) = (CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
+        let mut touched = HashSet::new();
+
+        for &((x, z), delta) in changes {
+            self.edits.add((x, z), delta);
+
+            let owner = (x.div_euclid(width), z.div_euclid(height));
+            let (local_x, local_z) = (x.rem_euclid(width) as usize, z.rem_euclid(height) as usize);
+
+            //Samples on the first row or column are also the halo of the chunks before
+            let mut holders = vec![(owner, local_x, local_z)];
+            if local_x == 0 {
+                holders.push(((owner.0 - 1, owner.1), CHUNK_WIDTH, local_z));
+            }
+            if local_z == 0 {
+                holders.push(((owner.0, owner.1 - 1), local_x, CHUNK_HEIGHT));
+            }
+            if local_x == 0 && local_z == 0 {
+                holders.push(((owner.0 - 1, owner.1 - 1), CHUNK_WIDTH, CHUNK_HEIGHT));
+            }
+
+            for (coordinates, x, y) in holders {
+                if let Some((chunk, _)) = self.chunks.get_mut(&coordinates) {
+                    chunk.heights[y * (CHUNK_WIDTH + 1) + x] += delta;
+                    touched.insert(coordinates);
+                } else if self.pending.contains_key(&coordinates) {
+                    touched.insert(coordinates);
+                }
+            }
+        }
+
+        for coordinates in touched {
+            self.rebuild(coordinates);
+        }
+    }
+
+    /// Rebuilds the meshes and colliders of a chunk from its current heights, or marks it to
+    /// be redone once the generation or rebuild already running for it finishes.
+    fn rebuild(&mut self, coordinates: (i32, i32)) {
+        if self.pending.contains_key(&coordinates) || self.rebuilds.contains_key(&coordinates) {
+            self.dirty.insert(coordinates);
+            return;
+        }
+        let Some((chunk, _)) = self.chunks.get(&coordinates) else { return };
+
+        //Colliders still building were sampled before the edit
+        let mut steps: Vec<usize> = chunk.colliders.keys().copied().collect();
+        self.pending_colliders.retain(|(pending, step), _| {
+            let stale = *pending == coordinates;
+            if stale {
+                steps.push(*step);
+            }
+            !stale
+        });
+
+        let (heights, biomes, lakes) = (chunk.heights.clone(), chunk.biomes.clone(), self.lakes.clone());
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            Chunk::from_heights_and_biomes(coordinates, &heights, biomes).with_colliders(&steps).with_lakes(lakes.as_deref())
+        });
+        self.rebuilds.insert(coordinates, task);
+    }
+
+    /// Moves finished generation tasks into the store, uploading their meshes once,
+    /// swaps in rebuilt chunks and evicts anything over capacity.
+    pub fn poll_tasks(&mut self, meshes: &mut Assets<Mesh>) {
+        let finished: Vec<(i32, i32)> = self.pending.iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(coordinates, _)| *coordinates)
+            .collect();
+
+        for coordinates in finished {
+            let Some(mut task) = self.pending.remove(&coordinates) else { continue };
+            match block_on(poll_once(&mut task)) {
+                //Edited while generating, generate again with the new edits
+                Some(Ok(_)) if self.dirty.remove(&coordinates) => self.request(coordinates),
+                Some(Ok(generated)) => self.insert(coordinates, generated.into_chunk(meshes)),
+                Some(Err(e)) => error!("Failed to generate chunk {coordinates:?}: {e}"),
+                None => { self.pending.insert(coordinates, task); }
+            }
+        }
+
+        let finished: Vec<((i32, i32), usize)> = self.pending_colliders.iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(key, _)| *key)
+            .collect();
+
+        for (coordinates, step) in finished {
+            let Some(mut task) = self.pending_colliders.remove(&(coordinates, step)) else { continue };
+            match block_on(poll_once(&mut task)) {
+                //The chunk may have been evicted while its collider was building
+                Some(collider) => if let Some((chunk, _)) = self.chunks.get_mut(&coordinates) {
+                    chunk.colliders.insert(step, collider);
+                    self.changes += 1;
+                },
+                None => { self.pending_colliders.insert((coordinates, step), task); }
+            }
+        }
+
+        let finished: Vec<(i32, i32)> = self.rebuilds.iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(coordinates, _)| *coordinates)
+            .collect();
+
+        for coordinates in finished {
+            let Some(mut task) = self.rebuilds.remove(&coordinates) else { continue };
+            let Some(rebuilt) = block_on(poll_once(&mut task)) else {
+                self.rebuilds.insert(coordinates, task);
+                continue;
+            };
+
+            if let Some((chunk, _)) = self.chunks.get_mut(&coordinates) {
+                //Same handles, so every entity showing the chunk picks up the new meshes
+                meshes.insert(&chunk.mesh, rebuilt.mesh);
+                meshes.insert(&chunk.mesh_2, rebuilt.mesh_2);
+                meshes.insert(&chunk.mesh_4, rebuilt.mesh_4);
+                chunk.colliders.extend(rebuilt.colliders);
+                chunk.lakes = rebuilt.lakes;
+                chunk.lod_errors = rebuilt.lod_errors;
+                chunk.height_range = rebuilt.height_range;
+                chunk.revision += 1;
+                chunk.lake_revision += 1;
+                self.changes += 1;
+                self.flood_around(coordinates);
+            }
+
+            if self.dirty.remove(&coordinates) {
+                self.rebuild(coordinates);
+            }
+        }
+
+        self.poll_lakes();
+        self.evict(meshes);
+    }
+
+    /// Swaps in lakes flooded over a neighbourhood and starts flooding every chunk whose
+    /// neighbours have all loaded since.
+    fn poll_lakes(&mut self) {
+        let finished: Vec<(i32, i32)> = self.pending_lakes.iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(coordinates, _)| *coordinates)
+            .collect();
+
+        for coordinates in finished {
+            let Some(mut task) = self.pending_lakes.remove(&coordinates) else { continue };
+            match block_on(poll_once(&mut task)) {
+                Some(lakes) => if let Some((chunk, _)) = self.chunks.get_mut(&coordinates) {
+                    chunk.lakes = lakes;
+                    chunk.lake_revision += 1;
+                    self.changes += 1;
+                },
+                None => { self.pending_lakes.insert(coordinates, task); }
+            }
+        }
+
+        let Some(finder) = self.lakes.clone() else { return };
+        let ready: Vec<((i32, i32), Vec<f32>)> = self.unflooded.iter()
+            .filter(|coordinates| !self.pending_lakes.contains_key(coordinates))
+            .filter_map(|coordinates| Some((*coordinates, self.padded_heights(*coordinates)?)))
+            .collect();
+
+        for (coordinates, heights) in ready {
+            self.unflooded.remove(&coordinates);
+            let finder = finder.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move { finder.find_padded(&heights, LAKE_PADDING) });
+            self.pending_lakes.insert(coordinates, task);
+        }
+    }
+
+    /// Queues a chunk and its neighbours to have their lakes flooded again, e.g. after an edit.
+    fn flood_around(&mut self, (x, y): (i32, i32)) {
+        for neighbour in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))) {
+            if self.chunks.contains_key(&neighbour) {
+                self.unflooded.insert(neighbour);
+            }
+        }
+    }
+
+    /// Heights of a chunk with `LAKE_PADDING` samples of its neighbours around it, `None`
+    /// until all of them are loaded.
+    fn padded_heights(&self, (x, y): (i32, i32)) -> Option<Vec<f32>> {
+        let neighbours: Vec<&Chunk> = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .map(|neighbour| self.get_chunk(&neighbour))
+            .collect::<Option<_>>()?;
+
+        let (width, height, padding) = (CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32, LAKE_PADDING as i32);
+        Some((-padding..=height + padding)
+            .flat_map(|sample_y| (-padding..=width + padding).map(move |sample_x| (sample_x, sample_y)))
+            .map(|(sample_x, sample_y)| {
+                let chunk = neighbours[((sample_y.div_euclid(height) + 1) * 3 + sample_x.div_euclid(width) + 1) as usize];
+                chunk.heights[sample_y.rem_euclid(height) as usize * (CHUNK_WIDTH + 1) + sample_x.rem_euclid(width) as usize]
+            })
+            .collect())
     }
 
+    /// Grows the capacity so at least `chunks` can stay resident, along with the eroded
+    /// regions under them.
+    pub fn reserve(&mut self, chunks: &HashSet<(i32, i32)>) {
+        self.capacity = self.capacity.max(chunks.len());
+        if let Some(erosion) = &self.erosion {
+            erosion.reserve(chunks);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Changes whenever what is loaded changes, so systems can skip frames where nothing did.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// Adds a built chunk as the most recently used, replacing whatever was loaded there.
+    pub fn insert(&mut self, coordinates: (i32, i32), chunk: Chunk) {
+        self.tick += 1;
+        self.changes += 1;
+        if self.lakes.is_some() {
+            self.unflooded.insert(coordinates);
+        }
+        if let Some((_, last_used)) = self.chunks.insert(coordinates, (chunk, self.tick)) {
+            self.lru.remove(&last_used);
+        }
+        self.lru.insert(self.tick, coordinates);
+    }
+
+    fn touch(&mut self, coordinates: (i32, i32)) {
+        self.tick += 1;
+        if let Some((_, last_used)) = self.chunks.get_mut(&coordinates) {
+            self.lru.remove(last_used);
+            *last_used = self.tick;
+            self.lru.insert(self.tick, coordinates);
+        }
+    }
+
+    fn evict(&mut self, meshes: &mut Assets<Mesh>) {
+        while self.chunks.len() > self.capacity {
+            let Some((_, coordinates)) = self.lru.pop_first() else { break };
+            if let Some((chunk, _)) = self.chunks.remove(&coordinates) {
+                chunk.unload(meshes);
+                self.changes += 1;
+                self.rebuilds.remove(&coordinates);
+                self.dirty.remove(&coordinates);
+                self.unflooded.remove(&coordinates);
+                self.pending_lakes.remove(&coordinates);
+            }
+        }
+    }
 }
 
-fn generate_heightfield(heightmap: Vec<f32>, lod: usize) -> Collider {
-    let mut heightfield = Vec::with_capacity(CHUNK_HEIGHT / lod + CHUNK_WIDTH / lod);
-    for y in (0..CHUNK_HEIGHT).step_by(lod) {
-        for x in (0..CHUNK_WIDTH).step_by(lod) {
-            heightfield.push((heightmap[y * CHUNK_WIDTH + x] + 1.0).powi(4) * 30.0);
+/// Heightfield over a whole chunk from `Chunk::heights`, sampling every `step`-th height.
+/// Parry stores heights column-major with rows along z and columns along x, and splits
+/// each cell along the same diagonal as `ChunkData`, so the collider matches the mesh of
+/// the same LOD triangle for triangle once offset by `HEIGHTFIELD_OFFSET`.
+pub fn generate_heightfield(heights: &[f32], step: usize) -> Collider {
+    assert!(step.is_power_of_two() && step <= CHUNK_WIDTH.min(CHUNK_HEIGHT), "unsupported collider step {step}");
+
+    let mut heightfield = Vec::with_capacity((CHUNK_HEIGHT / step + 1) * (CHUNK_WIDTH / step + 1));
+    for x in (0..=CHUNK_WIDTH).step_by(step) {
+        for y in (0..=CHUNK_HEIGHT).step_by(step) {
+            heightfield.push(heights[y * (CHUNK_WIDTH + 1) + x]);
         }
     }
 
-    Collider::heightfield(heightfield, CHUNK_HEIGHT / lod, CHUNK_WIDTH/ lod, Vec3::new(CHUNK_HEIGHT as f32, 1.0, CHUNK_WIDTH as f32))
+    Collider::heightfield(heightfield, CHUNK_HEIGHT / step + 1, CHUNK_WIDTH / step + 1, Vec3::new(CHUNK_WIDTH as f32, 1.0, CHUNK_HEIGHT as f32))
 }
 
 pub struct ChunkData {
     pub  vertex_buffer: Vec<[f32; 3]>,
+    pub  uv_buffer: Vec<[f32; 2]>,
     pub  index_buffer: Vec<u32>,
+    /// Surface vertex each skirt vertex was copied from, in order, for the vertices
+    /// appended after the grid.
+    pub  skirt_sources: Vec<u32>,
 }
 
 #[derive(/*Bundle,*/ Debug)]
 pub struct Chunk {
     pub transform: Transform,
-    pub collider: Collider,
+    /// Full resolution heights including the halo row and column, row by row.
+    pub heights: Vec<f32>,
+    /// Biome weights of every height, laid out the same way.
+    pub biomes: Vec<BiomeWeights>,
+    /// Lakes over the chunk, found in the chunk alone until its neighbours load and then
+    /// flooded over them too.
+    pub lakes: Vec<Lake>,
+    /// Furthest any height strays from the mesh of each of `LOD_STEPS`, in metres.
+    pub lod_errors: [f32; 3],
+    /// Lowest and highest height, halo included.
+    pub height_range: (f32, f32),
+    /// Heightfields built so far, keyed by sample step.
+    pub colliders: HashMap<usize, Collider>,
+    /// Bumped every time an edit has replaced the meshes, colliders and lakes.
+    pub revision: u32,
+    /// Bumped every time `lakes` is replaced.
+    pub lake_revision: u32,
+    /// Shared by every entity showing this chunk, removed from `Assets<Mesh>` on eviction.
+    pub mesh: Handle<Mesh>,
+    pub mesh_2: Handle<Mesh>,
+    pub mesh_4: Handle<Mesh>,
+}
+
+/// A chunk built off the main thread whose meshes are not in `Assets<Mesh>` yet.
+#[derive(Debug)]
+pub struct GeneratedChunk {
+    pub transform: Transform,
+    pub heights: Vec<f32>,
+    pub biomes: Vec<BiomeWeights>,
+    pub lakes: Vec<Lake>,
+    pub lod_errors: [f32; 3],
+    pub height_range: (f32, f32),
+    pub colliders: HashMap<usize, Collider>,
     pub mesh: Mesh,
     pub mesh_2: Mesh,
     pub mesh_4: Mesh,
 }
 
 impl Chunk {
-    //pub fn new(x: usize, y: usize, mesh: Mesh) -> Self { Chunk { x, y, mesh } }
-    //pub fn get_mesh(&self) -> &Mesh { &self.mesh }
+    /// Generates a chunk, shapes it by biome and adds `edits`, `(index, delta)` pairs from
+    /// `TerrainEdits::chunk_deltas`. The region cache only ever holds the unshaped heights.
+    pub async fn generate(backend: &HeightmapBackend, erosion: Option<&Erosion>, biomes: Option<&BiomeMap>, cache: Option<&RegionCache>, edits: &[(usize, f32)], coordinates: (i32, i32)) -> anyhow::Result<GeneratedChunk> {
+        let mut heights = Chunk::generate_heights(backend, erosion, cache, coordinates).await?;
+        let weights = match biomes {
+            Some(biomes) => biomes.shape_chunk(coordinates, &mut heights),
+            None => vec![BiomeWeights::default(); heights.len()],
+        };
+        for &(index, delta) in edits {
+            heights[index] += delta;
+        }
+        Ok(Chunk::from_heights_and_biomes(coordinates, &heights, weights))
+    }
+
+    async fn generate_heights(backend: &HeightmapBackend, erosion: Option<&Erosion>, cache: Option<&RegionCache>, coordinates: (i32, i32)) -> anyhow::Result<Vec<f32>> {
+        let compute = async {
+            match erosion {
+                Some(erosion) => erosion.compute_chunk(coordinates).await,
+                None => backend.compute_chunk(coordinates).await,
+            }
+        };
+        let Some(cache) = cache else {
+            return compute.await;
+        };
+
+        match cache.load(coordinates) {
+            Ok(Some(heights)) => return Ok(heights),
+            Ok(None) => {}
+            Err(e) => warn!("Could not read chunk {coordinates:?} from the region cache: {e}"),
+        }
+
+        let heights = compute.await?;
+        Ok(cache.store(coordinates, &heights).unwrap_or_else(|e| {
+            warn!("Could not write chunk {coordinates:?} to the region cache: {e}");
+            heights
+        }))
+    }
+
+    /// Where the entity holding one of `colliders` goes so it lines up with the mesh.
+    pub fn collider_transform(&self) -> Transform {
+        self.transform * Transform::from_translation(HEIGHTFIELD_OFFSET)
+    }
+
+    /// Frees the mesh assets of every LOD.
+    pub fn unload(self, meshes: &mut Assets<Mesh>) {
+        for mesh in [self.mesh, self.mesh_2, self.mesh_4] {
+            meshes.remove(&mesh);
+        }
+    }
+
+    /// Builds a chunk from `(CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1)` row-major heights,
+    /// the last row and column being the halo shared with the neighbouring chunks.
+    pub fn from_heights(coordinates: (i32, i32), heights: &[f32]) -> GeneratedChunk {
+        Chunk::from_heights_and_biomes(coordinates, heights, vec![BiomeWeights::default(); heights.len()])
+    }
+
+    /// Like `from_heights`, colouring the vertices by `biomes`, one per height.
+    pub fn from_heights_and_biomes((x, y): (i32, i32), heights: &[f32], biomes: Vec<BiomeWeights>) -> GeneratedChunk {
+        let (slice, halo) = split_halo(heights);
+
+        let mut chunk_data = ChunkData::new(&slice, &halo, 1);
+        let mut chunk_data_2 = ChunkData::new(&slice, &halo, 2);
+        let mut chunk_data_4 = ChunkData::new(&slice, &halo, 4);
+
+        let mesh = with_biome_weights(chunk_data.into_mesh_with_normals(), &biomes);
+        let mesh_2 = with_biome_weights(chunk_data_2.into_mesh_with_normals(), &biomes);
+        let mesh_4 = with_biome_weights(chunk_data_4.into_mesh_with_normals(), &biomes);
+
+        GeneratedChunk { 
+            transform: Transform::from_xyz((x * CHUNK_WIDTH as i32) as f32, 0., (y * CHUNK_HEIGHT as i32) as f32), 
+            heights: heights.to_vec(),
+            biomes,
+            lakes: Vec::new(),
+            lod_errors: LOD_STEPS.map(|step| lod_error(heights, step)),
+            height_range: heights.iter().fold((f32::MAX, f32::MIN), |(low, high), &height| (low.min(height), high.max(height))),
+            colliders: HashMap::new(),
+            mesh,
+            mesh_2,
+            mesh_4
+        }
+    }
+}
+
+impl GeneratedChunk {
+    /// Also builds the heightfield for each of `steps`.
+    pub fn with_colliders(mut self, steps: &[usize]) -> Self {
+        for &step in steps {
+            self.colliders.insert(step, generate_heightfield(&self.heights, step));
+        }
+        self
+    }
+
+    /// Also finds the lakes in the heights, the chunk keeps none without a finder.
+    pub fn with_lakes(mut self, finder: Option<&LakeFinder>) -> Self {
+        if let Some(finder) = finder {
+            self.lakes = finder.find(&self.heights);
+        }
+        self
+    }
+
+    pub fn into_chunk(self, meshes: &mut Assets<Mesh>) -> Chunk {
+        Chunk {
+            transform: self.transform,
+            heights: self.heights,
+            biomes: self.biomes,
+            lakes: self.lakes,
+            lod_errors: self.lod_errors,
+            height_range: self.height_range,
+            colliders: self.colliders,
+            revision: 0,
+            lake_revision: 0,
+            mesh: meshes.add(self.mesh),
+            mesh_2: meshes.add(self.mesh_2),
+            mesh_4: meshes.add(self.mesh_4),
+        }
+    }
+}
+
+/// Splits `(CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1)` row-major heights into the chunk's own
+/// heights and the halo `ChunkData::new` takes.
+fn split_halo(heights: &[f32]) -> (Box<[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT]>, [f32; CHUNK_WIDTH + CHUNK_HEIGHT + 1]) {
+    let stride = CHUNK_WIDTH + 1;
+
+    let rows: Vec<[f32; CHUNK_WIDTH]> = heights
+        .chunks_exact(stride)
+        .take(CHUNK_HEIGHT)
+        .map(|row| row[..CHUNK_WIDTH].try_into().unwrap())
+        .collect();
+
+    //[x; CHUNK_WIDTH] bottom halo row, [y; CHUNK_HEIGHT] right halo column, [z] corner
+    let mut halo: [f32; CHUNK_WIDTH + CHUNK_HEIGHT + 1] = [0.0; CHUNK_WIDTH + CHUNK_HEIGHT + 1];
+    halo[0..CHUNK_WIDTH].copy_from_slice(&heights[CHUNK_HEIGHT * stride..CHUNK_HEIGHT * stride + CHUNK_WIDTH]);
+    for cy in 0..CHUNK_HEIGHT {
+        halo[CHUNK_WIDTH + cy] = heights[cy * stride + CHUNK_WIDTH];
+    }
+    halo[CHUNK_HEIGHT + CHUNK_WIDTH] = heights[CHUNK_HEIGHT * stride + CHUNK_WIDTH];
+
+    (rows.into_boxed_slice().try_into().unwrap(), halo)
 }
 
 impl ChunkData {
-    pub fn new(heightmap: &[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT], halo: &[f32; CHUNK_HEIGHT + CHUNK_WIDTH + 1], lod: usize) -> Self {
-        match lod {
-            1 => {
-                let vertex_buffer: Vec<[f32; 3]> = (0..=CHUNK_HEIGHT)
-                    .into_par_iter()
-                    .flat_map_iter(|y| (0..=CHUNK_WIDTH).map(move |x| (x, y)))
-                    .map(|(x, y)| {
-                        [
-                            x as f32,
-                            match(y, x) {
-                                (CHUNK_HEIGHT, CHUNK_WIDTH) => (halo[CHUNK_HEIGHT + CHUNK_WIDTH] + 1.0).powi(4) * 30.,
-                                (_, CHUNK_WIDTH) => (halo[y + CHUNK_WIDTH] + 1.0).powi(4) * 30.,
-                                (CHUNK_HEIGHT, _) => (halo[x] + 1.0).powi(4) * 30.,
-                                _ => (heightmap[y][x] + 1.0).powi(4) * 30.,
-                            },
-                            y as f32
-                        ]
-                    }).collect();
-
-                let index_buffer: Vec<u32> = (0..CHUNK_HEIGHT)
-                    .into_par_iter()
-                    .flat_map_iter(|y| { 
-                        (0..CHUNK_WIDTH).flat_map(move |x| {
-                            let stride = (CHUNK_WIDTH + 1) as u32;
-                            let i0 = x as u32 + y as u32 * stride;
-                            let i1 = i0 + 1;
-                            let i2 = i0 + stride;
-                            let i3 = i2 + 1;
-
-                            [i0, i3, i1, i0, i2, i3]
-                        })
-                    }).collect();
-
-                ChunkData { vertex_buffer, index_buffer }
-            },
-            lod => {
-                let mut vertex_buffer: Vec<[f32; 3]> = Vec::new();
-                let mut index_buffer: Vec<u32> = Vec::new();
-
-                let lod_height = CHUNK_HEIGHT / lod;
-                let lod_width = CHUNK_WIDTH / lod;
-
-                for y in 0..=lod_height {
-                    for x in 0..=lod_width {
-                        vertex_buffer.push([
-                            (x * lod) as f32,
-                            match(y, x) {
-                                _ if y == lod_height && x == lod_width => (halo[CHUNK_HEIGHT + CHUNK_WIDTH] + 1.0).powi(4) * 30.,
-                                _ if x == lod_width => (halo[y * lod + CHUNK_WIDTH] + 1.0).powi(4) * 30.,
-                                _ if y == lod_height => (halo[x * lod] + 1.0).powi(4) * 30.,
-                                _ => (heightmap[y.saturating_sub(1) * lod][x.saturating_sub(1) * lod] + 1.0).powi(4) * 30.
-                            },
-                            (y * lod) as f32
-                        ]);
-                    }
-                }
+    /// `new` from heights laid out like `Chunk::from_heights` takes them.
+    pub fn from_heights(heights: &[f32], lod: usize) -> Self {
+        let (heightmap, halo) = split_halo(heights);
+        ChunkData::new(&heightmap, &halo, lod)
+    }
 
-                //info!("Vertex buffer length: {}", vertex_buffer.len());
-                //std::thread::sleep(Duration::new(4, 0));
+    /// Builds the grid mesh for a chunk at any power-of-two `lod`, sampling every
+    /// `lod`-th height. The last row and column come from the halo so neighbouring
+    /// chunks at the same LOD share their edge vertices exactly.
+    pub fn new(heightmap: &[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT], halo: &[f32; CHUNK_HEIGHT + CHUNK_WIDTH + 1], lod: usize) -> Self {
+        assert!(lod.is_power_of_two() && lod <= CHUNK_WIDTH.min(CHUNK_HEIGHT), "unsupported LOD {lod}");
 
-                for y in 0..lod_height as u32 {
-                    for x in 0..lod_width as u32 {
-                        let stride = lod_width as u32;
-                        let i0 = x + y * stride;
-                        let i1 = i0 + 1;
-                        let i2 = i0 + stride;
-                        let i3 = i2 + 1;
+        let columns = CHUNK_WIDTH / lod + 1;
+        let rows = CHUNK_HEIGHT / lod + 1;
 
+        let (vertex_buffer, uv_buffer): (Vec<[f32; 3]>, Vec<[f32; 2]>) = (0..rows)
+            .into_par_iter()
+            .flat_map_iter(|y| (0..columns).map(move |x| (x * lod, y * lod)))
+            .map(|(x, y)| (
+                [x as f32, sample(heightmap, halo, x, y), y as f32],
+                [x as f32 / CHUNK_WIDTH as f32, y as f32 / CHUNK_HEIGHT as f32],
+            ))
+            .unzip();
+
+        let stride = columns as u32;
+        let index_buffer: Vec<u32> = (0..rows as u32 - 1)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                (0..stride - 1).flat_map(move |x| {
+                    let i0 = x + y * stride;
+                    let i1 = i0 + 1;
+                    let i2 = i0 + stride;
+                    let i3 = i2 + 1;
+
+                    //Counter-clockwise seen from above, split along i1-i2 like Parry's heightfield cells
+                    [i0, i2, i1, i2, i3, i1]
+                })
+            }).collect();
 
-                        index_buffer.push(i0);
-                        index_buffer.push(i3);
-                        index_buffer.push(i1);
+        let mut chunk_data = ChunkData { vertex_buffer, uv_buffer, index_buffer, skirt_sources: Vec::new() };
+        chunk_data.add_skirts(heightmap, halo, lod);
+        chunk_data
+    }
 
-                        index_buffer.push(i0);
-                        index_buffer.push(i2);
-                        index_buffer.push(i3);
-                    }
-                }
+    /// Hangs a vertical skirt below each border of the grid. A coarser neighbour skips
+    /// some of our edge samples, and the skirt covers the gap that opens up. That keeps
+    /// the terrain watertight whatever LOD each neighbour is at.
+    fn add_skirts(&mut self, heightmap: &[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT], halo: &[f32; CHUNK_HEIGHT + CHUNK_WIDTH + 1], lod: usize) {
+        let columns = CHUNK_WIDTH / lod + 1;
+        let rows = CHUNK_HEIGHT / lod + 1;
+
+        //Walk the border counter-clockwise seen from above so every skirt faces outwards
+        let edges: [Vec<(usize, usize)>; 4] = [
+            (0..columns).map(|x| (x, 0)).collect(),
+            (0..rows).map(|y| (columns - 1, y)).collect(),
+            (0..columns).rev().map(|x| (x, rows - 1)).collect(),
+            (0..rows).rev().map(|y| (0, y)).collect(),
+        ];
+
+        for edge in edges {
+            //No neighbour's edge can dip below the lowest full resolution sample on this edge
+            let (start, end) = (edge[0], edge[edge.len() - 1]);
+            let lowest = if start.1 == end.1 {
+                (0..=CHUNK_WIDTH).map(|x| sample(heightmap, halo, x, start.1 * lod)).fold(f32::INFINITY, f32::min)
+            } else {
+                (0..=CHUNK_HEIGHT).map(|y| sample(heightmap, halo, start.0 * lod, y)).fold(f32::INFINITY, f32::min)
+            };
+            let bottom = lowest - SKIRT_MARGIN;
+
+            let base = self.vertex_buffer.len() as u32;
+            for &(x, y) in &edge {
+                let source = (y * columns + x) as u32;
+                let [px, py, pz] = self.vertex_buffer[source as usize];
+
+                let uv = self.uv_buffer[source as usize];
+
+                self.vertex_buffer.push([px, py, pz]);
+                self.vertex_buffer.push([px, bottom, pz]);
+                self.uv_buffer.extend([uv, uv]);
+                self.skirt_sources.extend([source, source]);
+            }
 
-                //let expected_len = lod_height * lod_width * 6;
-                //info!("Index buffer length: {}, Expected length: {}", index_buffer.len(), expected_len);
-                //std::thread::sleep(Duration::new(4, 0));
+            for i in 0..edge.len() as u32 - 1 {
+                let top = base + i * 2;
+                let next_top = top + 2;
 
-                ChunkData { vertex_buffer, index_buffer, }
+                self.index_buffer.extend([top, next_top, top + 1, next_top, next_top + 1, top + 1]);
             }
         }
     }
@@ -217,6 +770,7 @@ impl ChunkData {
         Mesh::new(PrimitiveTopology::TriangleList,
             RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD)
             .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.vertex_buffer.clone())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uv_buffer.clone())
             .with_inserted_indices(Indices::U32(self.index_buffer.clone()))
     }
 
@@ -224,8 +778,70 @@ impl ChunkData {
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList,
             RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD)
             .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.vertex_buffer.clone())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uv_buffer.clone())
             .with_inserted_indices(Indices::U32(self.index_buffer.clone()));
         mesh.compute_smooth_normals();
+
+        //Skirts borrow the normal of the edge they hang from so they shade like the surface
+        if let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) {
+            let surface_vertices = normals.len() - self.skirt_sources.len();
+            for (i, source) in self.skirt_sources.iter().enumerate() {
+                normals[surface_vertices + i] = normals[*source as usize];
+            }
+        }
+
         mesh
     }
 }
+
+/// Largest vertical distance between a height and the mesh sampling every `step`-th one,
+/// interpolated over the same triangles `ChunkData` splits each cell into.
+fn lod_error(heights: &[f32], step: usize) -> f32 {
+    let stride = CHUNK_WIDTH + 1;
+    let height = |x: usize, y: usize| heights[y * stride + x];
+    let mut error: f32 = 0.0;
+
+    for cell_y in (0..CHUNK_HEIGHT).step_by(step) {
+        for cell_x in (0..CHUNK_WIDTH).step_by(step) {
+            let h0 = height(cell_x, cell_y);
+            let h1 = height(cell_x + step, cell_y);
+            let h2 = height(cell_x, cell_y + step);
+            let h3 = height(cell_x + step, cell_y + step);
+
+            for y in cell_y..=cell_y + step {
+                for x in cell_x..=cell_x + step {
+                    let (u, v) = ((x - cell_x) as f32 / step as f32, (y - cell_y) as f32 / step as f32);
+                    //Split along the h1-h2 diagonal
+                    let mesh = if u + v <= 1.0 {
+                        h0 + u * (h1 - h0) + v * (h2 - h0)
+                    } else {
+                        h3 + (1.0 - u) * (h2 - h3) + (1.0 - v) * (h1 - h3)
+                    };
+                    error = error.max((height(x, y) - mesh).abs());
+                }
+            }
+        }
+    }
+    error
+}
+
+/// Stores the biome weights of the sample under each vertex as its colour for the
+/// `TerrainMaterial`, skirts included since they keep the x and z of the edge they hang from.
+fn with_biome_weights(mesh: Mesh, biomes: &[BiomeWeights]) -> Mesh {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { return mesh };
+    let weights: Vec<[f32; 4]> = positions.iter()
+        .map(|&[x, _, z]| biomes[z as usize * (CHUNK_WIDTH + 1) + x as usize].splat())
+        .collect();
+
+    mesh.with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, weights)
+}
+
+/// Full resolution height at grid position `(x, y)`, reading the halo past the last row or column.
+fn sample(heightmap: &[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT], halo: &[f32; CHUNK_HEIGHT + CHUNK_WIDTH + 1], x: usize, y: usize) -> f32 {
+    match (y, x) {
+        (CHUNK_HEIGHT, CHUNK_WIDTH) => halo[CHUNK_HEIGHT + CHUNK_WIDTH],
+        (_, CHUNK_WIDTH) => halo[y + CHUNK_WIDTH],
+        (CHUNK_HEIGHT, _) => halo[x],
+        _ => heightmap[y][x],
+    }
+}
@@ -0,0 +1,475 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use bevy::prelude::*;
+use bevy::{asset::{embedded_asset, RenderAssetUsages}, pbr::{ExtendedMaterial, MaterialExtension, NotShadowCaster}, render::{mesh::{Indices, PrimitiveTopology}, render_asset::RenderAssets, render_resource::{AsBindGroup, Extent3d, Origin3d, ShaderRef, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDimension, TextureFormat}, renderer::RenderQueue, texture::GpuImage, view::NoFrustumCulling, ExtractSchedule, MainWorld, Render, RenderApp, RenderSet}};
+
+use crate::{player::player::Player, terrain::{chunks::{Chunkbase, RenderDistance, CHUNK_HEIGHT, CHUNK_WIDTH}, material::{SplatParams, TerrainMaterialPlugin, TerrainSplat}}};
+
+const SHADER_PATH: &str = "embedded://terrain/terrain/clipmap.wgsl";
+
+/// Multiplies the terrain textures, the `debug` feature tints the rings red like LOD 4 chunks.
+#[cfg(feature = "debug")]
+const RING_TINT: Color = Color::srgb(180.0 / 255.0, 20.0 / 255.0, 20.0 / 255.0);
+#[cfg(not(feature = "debug"))]
+const RING_TINT: Color = Color::WHITE;
+
+/// Height written to texels whose chunk hasn't been generated yet, the shader discards those.
+const UNLOADED: f32 = -1.0e30;
+
+pub type ClipmapMaterial = ExtendedMaterial<StandardMaterial, ClipmapExtension>;
+
+/// Renders the far LOD ring as nested ring meshes centred on the player instead of one
+/// entity per chunk. The rings are displaced in the vertex shader from a heightmap
+/// texture filled from `Chunkbase`, and textured like the chunks from a biome weight texture beside it, so the draw calls only grow with the log of the radius.
+pub struct ClipmapPlugin;
+
+#[derive(Resource, Clone)]
+pub struct ClipmapSettings {
+    /// Render the far ring through the clipmap. When false `load_map` spawns LOD 4 chunk entities.
+    pub enabled: bool,
+    /// Cells from the centre to the edge of each ring, must be even.
+    pub half_cells: u32,
+    /// Heightmap samples between two texels, also the cell size of the finest ring.
+    pub texel_spacing: u32,
+}
+
+impl Default for ClipmapSettings {
+    fn default() -> Self {
+        ClipmapSettings { enabled: true, half_cells: 64, texel_spacing: 4 }
+    }
+}
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct ClipmapExtension {
+    #[uniform(100)]
+    pub params: ClipmapParams,
+    #[texture(101, sample_type = "float", filterable = false)]
+    pub heightmap: Handle<Image>,
+    /// `BiomeWeights::splat` of each heightmap texel.
+    #[texture(102, sample_type = "float", filterable = false)]
+    pub biomes: Handle<Image>,
+    #[uniform(103)]
+    pub splat: SplatParams,
+    #[texture(104, dimension = "2d_array")]
+    #[sampler(105)]
+    pub layers: Handle<Image>,
+}
+
+pub use params::ClipmapParams;
+
+mod params {
+    //encase's ShaderType derive emits an unused `check` fn per field
+    #![allow(dead_code)]
+
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(ShaderType, Reflect, Debug, Clone, Copy, PartialEq)]
+    pub struct ClipmapParams {
+        pub player_chunk: IVec2,
+        /// Chunks closer than this are meshes and get discarded.
+        pub inner_radius: i32,
+        pub outer_radius: i32,
+        pub cell_size: f32,
+        pub half_cells: f32,
+        pub texel_size: f32,
+        pub texture_size: i32,
+        pub chunk_size: Vec2,
+    }
+}
+
+#[derive(Component)]
+struct ClipmapRing;
+
+#[derive(Resource, Default)]
+struct Clipmap {
+    heightmap: Handle<Image>,
+    biomes: Handle<Image>,
+    rings: Vec<(Entity, Handle<ClipmapMaterial>)>,
+    texture_size: u32,
+    render_distance: u32,
+    /// Samples from the centre to the edge of the area kept in the texture.
+    window: i32,
+    /// World sample the rings are centred on, a multiple of the coarsest cell.
+    centre: Option<(i32, i32)>,
+    player_chunk: Option<(i32, i32)>,
+    /// Chunks copied into the texture and the revision they were at.
+    written: HashMap<(i32, i32), u32>,
+    /// What the heightmap and biome textures hold, written here first and copied to the GPU
+    /// a rectangle at a time.
+    heightmap_data: Vec<u8>,
+    biome_data: Vec<u8>,
+}
+
+/// Texel rectangles of the clipmap textures written since they were last copied to the GPU.
+/// Copying only these keeps Bevy from uploading both whole textures on every write.
+#[derive(Resource, Default)]
+struct ClipmapWrites {
+    /// Heightmap and biome textures the rectangles belong to.
+    textures: Option<(AssetId<Image>, AssetId<Image>)>,
+    rects: Vec<TexelRect>,
+}
+
+/// Texels `x..x + width` of the rows `y..y + height` of both textures, row by row.
+struct TexelRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    heightmap: Vec<u8>,
+    biomes: Vec<u8>,
+}
+
+impl MaterialExtension for ClipmapExtension {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+}
+
+impl Plugin for ClipmapPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "clipmap.wgsl");
+        if !app.is_plugin_added::<TerrainMaterialPlugin>() {
+            app.add_plugins(TerrainMaterialPlugin);
+        }
+
+        app
+            .init_resource::<ClipmapSettings>()
+            .init_resource::<Clipmap>()
+            .init_resource::<ClipmapWrites>()
+            .add_plugins(MaterialPlugin::<ClipmapMaterial>::default())
+            .add_systems(Update, (
+                rebuild_clipmap.run_if(resource_changed::<RenderDistance>.or(resource_changed::<ClipmapSettings>)),
+                update_clipmap.run_if(|settings: Res<ClipmapSettings>| settings.enabled),
+            ).chain())
+        ;
+
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<ClipmapWrites>()
+                .add_systems(ExtractSchedule, extract_clipmap_writes)
+                .add_systems(Render, write_clipmap_texels.in_set(RenderSet::PrepareResources));
+        }
+    }
+}
+
+impl Clipmap {
+    /// Copies the texels of columns `(x, width)` and rows `(y, height)` out of both textures.
+    fn rect(&self, (x, width): (i32, i32), (y, height): (i32, i32)) -> TexelRect {
+        let texture_size = self.texture_size as i32;
+        let copy = |data: &[u8]| (y..y + height)
+            .flat_map(|row| {
+                let start = (row * texture_size + x) as usize * 4;
+                data[start..start + width as usize * 4].iter().copied()
+            })
+            .collect();
+
+        TexelRect {
+            x: x as u32,
+            y: y as u32,
+            width: width as u32,
+            height: height as u32,
+            heightmap: copy(&self.heightmap_data),
+            biomes: copy(&self.biome_data),
+        }
+    }
+}
+
+impl ClipmapSettings {
+    /// Number of rings needed for the coarsest one to reach `window` samples from the centre.
+    fn levels(&self, window: i32) -> u32 {
+        let finest = (self.half_cells * self.texel_spacing) as i32;
+        let mut levels = 1;
+        while finest << (levels - 1) < window {
+            levels += 1;
+        }
+        levels
+    }
+}
+
+/// Recreates the rings and heightmap and biome textures so they cover the current render
+/// distance, or drops them when the clipmap is turned off.
+fn rebuild_clipmap(
+    mut commands: Commands,
+    (settings, splat): (Res<ClipmapSettings>, Res<TerrainSplat>),
+    (render_distance, mut writes): (Res<RenderDistance>, ResMut<ClipmapWrites>),
+    mut clipmap: ResMut<Clipmap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<ClipmapMaterial>>,
+) {
+    for (entity, material) in clipmap.rings.drain(..) {
+        commands.entity(entity).despawn();
+        materials.remove(&material);
+    }
+    images.remove(&clipmap.heightmap);
+    images.remove(&clipmap.biomes);
+    *writes = ClipmapWrites::default();
+    if !settings.enabled {
+        *clipmap = Clipmap::default();
+        return;
+    }
+
+    //One chunk of margin past the render circle, plus the snapping of the centre
+    let window = (render_distance.0 as i32 + 2) * CHUNK_WIDTH.max(CHUNK_HEIGHT) as i32;
+    let levels = settings.levels(window);
+    let texture_size = 2 * (window as u32 / settings.texel_spacing) + 1;
+    let texels = (texture_size * texture_size) as usize;
+    let heightmap_data = UNLOADED.to_le_bytes().repeat(texels);
+    let biome_data = vec![0; texels * 4];
+
+    //Only ever written through `ClipmapWrites` from here on, so the render world keeps them
+    let extent = Extent3d { width: texture_size, height: texture_size, depth_or_array_layers: 1 };
+    let heightmap = images.add(Image::new(extent, TextureDimension::D2, heightmap_data.clone(), TextureFormat::R32Float, RenderAssetUsages::RENDER_WORLD));
+    let biomes = images.add(Image::new(extent, TextureDimension::D2, biome_data.clone(), TextureFormat::Rgba8Unorm, RenderAssetUsages::RENDER_WORLD));
+    writes.textures = Some((heightmap.id(), biomes.id()));
+
+    let mut rings = Vec::with_capacity(levels as usize);
+    for level in 0..levels {
+        let cell_size = (settings.texel_spacing << level) as f32;
+        let material = materials.add(ClipmapMaterial {
+            base: StandardMaterial { base_color: RING_TINT, perceptual_roughness: 0.9, ..default() },
+            extension: ClipmapExtension {
+                params: ClipmapParams {
+                    player_chunk: IVec2::ZERO,
+                    inner_radius: 0,
+                    outer_radius: render_distance.0 as i32,
+                    cell_size,
+                    half_cells: settings.half_cells as f32,
+                    texel_size: settings.texel_spacing as f32,
+                    texture_size: texture_size as i32,
+                    chunk_size: Vec2::new(CHUNK_WIDTH as f32, CHUNK_HEIGHT as f32),
+                },
+                heightmap: heightmap.clone(),
+                biomes: biomes.clone(),
+                splat: splat.splat,
+                layers: splat.layers.clone(),
+            },
+        });
+
+        let entity = commands.spawn((
+            Mesh3d(meshes.add(ring_mesh(settings.half_cells, cell_size, level > 0))),
+            MeshMaterial3d(material.clone()),
+            Transform::default(),
+            //The mesh is flat until the vertex shader displaces it, so its bounds are meaningless
+            NoFrustumCulling,
+            NotShadowCaster,
+            ClipmapRing,
+        )).id();
+
+        rings.push((entity, material));
+    }
+
+    *clipmap = Clipmap {
+        heightmap,
+        biomes,
+        rings,
+        texture_size,
+        render_distance: render_distance.0,
+        window,
+        heightmap_data,
+        biome_data,
+        ..default()
+    };
+}
+
+/// Moves the rings with the player and copies newly generated or edited chunks into the
+/// heightmap and biome textures, queueing the texels that changed for `write_clipmap_texels`.
+fn update_clipmap(
+    settings: Res<ClipmapSettings>,
+    chunkbase: Res<Chunkbase>,
+    mut clipmap: ResMut<Clipmap>,
+    player_query: Query<(&Player, &Transform)>,
+    mut ring_query: Query<&mut Transform, (With<ClipmapRing>, Without<Player>)>,
+    mut writes: ResMut<ClipmapWrites>,
+    mut materials: ResMut<Assets<ClipmapMaterial>>,
+) {
+    let (player, transform) = player_query.single().unwrap();
+    let Some(coarsest) = clipmap.rings.len().checked_sub(1) else { return };
+    let snap = (settings.texel_spacing << coarsest) as f32;
+    let centre = (
+        ((transform.translation.x / snap).floor() * snap) as i32,
+        ((transform.translation.z / snap).floor() * snap) as i32,
+    );
+
+    let window = clipmap.window;
+    let previous = clipmap.centre.replace(centre);
+    //Texels entering the window alias the ones leaving it, only those get cleared and rewritten
+    let (entered_x, entered_z) = match previous {
+        Some(old) if old != centre => (entered(old.0, centre.0, window), entered(old.1, centre.1, window)),
+        _ => (None, None),
+    };
+    if previous != Some(centre) {
+        let overlaps = |(low, high): (i32, i32), start: i32, size: i32| start <= high && start + size >= low;
+        clipmap.written.retain(|&(x, y), _| {
+            let (start_x, start_z) = (x * CHUNK_WIDTH as i32, y * CHUNK_HEIGHT as i32);
+            overlaps((centre.0 - window, centre.0 + window), start_x, CHUNK_WIDTH as i32)
+                && overlaps((centre.1 - window, centre.1 + window), start_z, CHUNK_HEIGHT as i32)
+                && !entered_x.is_some_and(|range| overlaps(range, start_x, CHUNK_WIDTH as i32))
+                && !entered_z.is_some_and(|range| overlaps(range, start_z, CHUNK_HEIGHT as i32))
+        });
+        for mut ring_transform in &mut ring_query {
+            ring_transform.translation = Vec3::new(centre.0 as f32, 0.0, centre.1 as f32);
+        }
+    }
+
+    if clipmap.player_chunk != Some(player.current_chunk.0) {
+        clipmap.player_chunk = Some(player.current_chunk.0);
+        for (_, handle) in &clipmap.rings {
+            let Some(material) = materials.get_mut(handle) else { continue };
+            let params = &mut material.extension.params;
+            params.player_chunk = IVec2::new(player.current_chunk.0.0, player.current_chunk.0.1);
+            params.inner_radius = player.config.lod_radius as i32 * 2;
+            params.outer_radius = clipmap.render_distance as i32;
+        }
+    }
+
+    let (min_x, max_x) = (centre.0 - window, centre.0 + window);
+    let (min_z, max_z) = (centre.1 - window, centre.1 + window);
+    let chunks: Vec<(i32, i32)> = (min_z.div_euclid(CHUNK_HEIGHT as i32)..=max_z.div_euclid(CHUNK_HEIGHT as i32))
+        .flat_map(|y| (min_x.div_euclid(CHUNK_WIDTH as i32)..=max_x.div_euclid(CHUNK_WIDTH as i32)).map(move |x| (x, y)))
+        .filter(|coordinates| chunkbase.get_chunk(coordinates).is_some_and(|chunk| clipmap.written.get(coordinates) != Some(&chunk.revision)))
+        .collect();
+
+    if chunks.is_empty() && entered_x.is_none() && entered_z.is_none() {
+        return;
+    }
+
+    let texture_size = clipmap.texture_size as i32;
+    let spacing = settings.texel_spacing as i32;
+    let clipmap = &mut *clipmap;
+    let (heightmap, biomes) = (&mut clipmap.heightmap_data, &mut clipmap.biome_data);
+    //Column and row runs of every rectangle written
+    let mut rects = Vec::new();
+
+    let mut clear = |texel_x: i32, texel_y: i32| {
+        let index = (texel_y * texture_size + texel_x) as usize * 4;
+        heightmap[index..index + 4].copy_from_slice(&UNLOADED.to_le_bytes());
+        biomes[index..index + 4].fill(0);
+    };
+    for (first, count) in entered_x.into_iter().flat_map(|range| texels(range, spacing, texture_size)) {
+        for texel_x in first..first + count {
+            for texel_y in 0..texture_size {
+                clear(texel_x, texel_y);
+            }
+        }
+        rects.push(((first, count), (0, texture_size)));
+    }
+    for (first, count) in entered_z.into_iter().flat_map(|range| texels(range, spacing, texture_size)) {
+        for texel_y in first..first + count {
+            for texel_x in 0..texture_size {
+                clear(texel_x, texel_y);
+            }
+        }
+        rects.push(((0, texture_size), (first, count)));
+    }
+
+    for coordinates in chunks {
+        let chunk = chunkbase.get_chunk(&coordinates).unwrap();
+        let origin = (coordinates.0 * CHUNK_WIDTH as i32, coordinates.1 * CHUNK_HEIGHT as i32);
+
+        for y in (0..=CHUNK_HEIGHT).step_by(spacing as usize) {
+            for x in (0..=CHUNK_WIDTH).step_by(spacing as usize) {
+                let (world_x, world_z) = (origin.0 + x as i32, origin.1 + y as i32);
+                if !(min_x..=max_x).contains(&world_x) || !(min_z..=max_z).contains(&world_z) {
+                    continue;
+                }
+
+                let texel_x = world_x.div_euclid(spacing).rem_euclid(texture_size);
+                let texel_y = world_z.div_euclid(spacing).rem_euclid(texture_size);
+                let index = (texel_y * texture_size + texel_x) as usize * 4;
+                let sample = y * (CHUNK_WIDTH + 1) + x;
+                heightmap[index..index + 4].copy_from_slice(&chunk.heights[sample].to_le_bytes());
+                biomes[index..index + 4].copy_from_slice(&chunk.biomes[sample].0[..4]);
+            }
+        }
+
+        clipmap.written.insert(coordinates, chunk.revision);
+        let columns = texels((origin.0.max(min_x), (origin.0 + CHUNK_WIDTH as i32).min(max_x)), spacing, texture_size);
+        let rows = texels((origin.1.max(min_z), (origin.1 + CHUNK_HEIGHT as i32).min(max_z)), spacing, texture_size);
+        rects.extend(rows.iter().flat_map(|&row| columns.iter().map(move |&column| (column, row))));
+    }
+
+    writes.rects.extend(rects.into_iter().map(|(columns, rows)| clipmap.rect(columns, rows)));
+}
+
+/// Moves the rectangles written this frame over to the render world.
+fn extract_clipmap_writes(mut main_world: ResMut<MainWorld>, mut writes: ResMut<ClipmapWrites>) {
+    let mut written = main_world.resource_mut::<ClipmapWrites>();
+    //Rectangles of textures `rebuild_clipmap` has since replaced have nowhere to go
+    if written.textures != writes.textures {
+        writes.textures = written.textures;
+        writes.rects.clear();
+    }
+    writes.rects.append(&mut written.rects);
+}
+
+/// Copies the written rectangles into the clipmap textures, once both are on the GPU.
+fn write_clipmap_texels(mut writes: ResMut<ClipmapWrites>, images: Res<RenderAssets<GpuImage>>, queue: Res<RenderQueue>) {
+    let Some((heightmap, biomes)) = writes.textures else { return };
+    let (Some(heightmap), Some(biomes)) = (images.get(heightmap), images.get(biomes)) else { return };
+
+    for rect in writes.rects.drain(..) {
+        for (image, data) in [(heightmap, &rect.heightmap), (biomes, &rect.biomes)] {
+            queue.write_texture(
+                TexelCopyTextureInfo { texture: &image.texture, mip_level: 0, origin: Origin3d { x: rect.x, y: rect.y, z: 0 }, aspect: TextureAspect::All },
+                data,
+                TexelCopyBufferLayout { offset: 0, bytes_per_row: Some(rect.width * 4), rows_per_image: None },
+                Extent3d { width: rect.width, height: rect.height, depth_or_array_layers: 1 },
+            );
+        }
+    }
+}
+
+/// World samples along one axis that came into the window, inclusive, when its centre moved
+/// from `old` to `new`.
+fn entered(old: i32, new: i32, window: i32) -> Option<(i32, i32)> {
+    match new.cmp(&old) {
+        Ordering::Greater => Some(((old + window + 1).max(new - window), new + window)),
+        Ordering::Less => Some((new - window, (old - window - 1).min(new + window))),
+        Ordering::Equal => None,
+    }
+}
+
+/// Texel rows or columns holding the world samples `low..=high`, each at most once, as
+/// `(first, count)` runs that don't wrap around the edge of the texture.
+fn texels((low, high): (i32, i32), spacing: i32, texture_size: i32) -> Vec<(i32, i32)> {
+    let first = (low + spacing - 1).div_euclid(spacing);
+    let count = (high.div_euclid(spacing) - first + 1).clamp(0, texture_size);
+    let start = first.rem_euclid(texture_size);
+    let head = count.min(texture_size - start);
+    [(start, head), (0, count - head)].into_iter().filter(|(_, count)| *count > 0).collect()
+}
+
+/// Flat grid of `2 * half_cells` cells a side, in metres around the origin. Every ring but
+/// the finest leaves a hole where the next finer ring fits.
+fn ring_mesh(half_cells: u32, cell_size: f32, hole: bool) -> Mesh {
+    let n = half_cells as i32;
+    let columns = 2 * n + 1;
+
+    let vertex_buffer: Vec<[f32; 3]> = (-n..=n)
+        .flat_map(|z| (-n..=n).map(move |x| [x as f32 * cell_size, 0.0, z as f32 * cell_size]))
+        .collect();
+
+    let inner = -n / 2..n / 2;
+    let index_buffer: Vec<u32> = (-n..n)
+        .flat_map(|z| (-n..n).map(move |x| (x, z)))
+        .filter(|(x, z)| !(hole && inner.contains(x) && inner.contains(z)))
+        .flat_map(|(x, z)| {
+            let i0 = ((z + n) * columns + x + n) as u32;
+            let i1 = i0 + 1;
+            let i2 = i0 + columns as u32;
+            let i3 = i2 + 1;
+
+            //Counter-clockwise seen from above, split like the chunk meshes
+            [i0, i2, i1, i2, i3, i1]
+        })
+        .collect();
+
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vertex_buffer)
+        .with_inserted_indices(Indices::U32(index_buffer))
+}
@@ -0,0 +1,122 @@
+#import bevy_pbr::{
+    mesh_functions,
+    pbr_bindings,
+    view_transformations::position_world_to_clip,
+    pbr_fragment::pbr_input_from_standard_material,
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#import terrain::splat::{SplatParams, splat}
+
+struct ClipmapParams {
+    player_chunk: vec2<i32>,
+    inner_radius: i32,
+    outer_radius: i32,
+    cell_size: f32,
+    half_cells: f32,
+    texel_size: f32,
+    texture_size: i32,
+    chunk_size: vec2<f32>,
+}
+
+@group(2) @binding(100) var<uniform> clipmap: ClipmapParams;
+@group(2) @binding(101) var heightmap: texture_2d<f32>;
+@group(2) @binding(102) var biomes: texture_2d<f32>;
+@group(2) @binding(103) var<uniform> splat_params: SplatParams;
+@group(2) @binding(104) var layers: texture_2d_array<f32>;
+@group(2) @binding(105) var layers_sampler: sampler;
+
+//Texels no chunk has been written to yet
+const UNLOADED: f32 = -1.0e29;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+}
+
+//Toroidal lookup, the texture wraps around as the clipmap follows the player
+fn texel_at(world: vec2<f32>) -> vec2<i32> {
+    let size = clipmap.texture_size;
+    let texel = vec2<i32>(round(world / clipmap.texel_size));
+    return ((texel % size) + size) % size;
+}
+
+fn load_height(world: vec2<f32>) -> f32 {
+    return textureLoad(heightmap, texel_at(world), 0).r;
+}
+
+fn height_at(world: vec2<f32>) -> f32 {
+    let height = load_height(world);
+    return select(height, 0.0, height < UNLOADED);
+}
+
+//Odd vertices on the outer border of a ring sit halfway between two vertices of the next,
+//coarser ring, so they take the average of those to keep the seam watertight
+fn ring_height(local: vec2<f32>, world: vec2<f32>) -> f32 {
+    let cell = clipmap.cell_size;
+    let border = (clipmap.half_cells - 0.5) * cell;
+    let index = abs(round(local / cell));
+
+    if (abs(local.x) > border && index.y % 2.0 == 1.0) {
+        return 0.5 * (height_at(world - vec2(0.0, cell)) + height_at(world + vec2(0.0, cell)));
+    }
+    if (abs(local.y) > border && index.x % 2.0 == 1.0) {
+        return 0.5 * (height_at(world - vec2(cell, 0.0)) + height_at(world + vec2(cell, 0.0)));
+    }
+    return height_at(world);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    var world_position = mesh_functions::mesh_position_local_to_world(world_from_local, vec4(vertex.position, 1.0));
+    let world = world_position.xz;
+    let cell = clipmap.cell_size;
+    world_position.y = ring_height(vertex.position.xz, world);
+
+    out.world_position = world_position;
+    out.position = position_world_to_clip(world_position.xyz);
+    out.world_normal = normalize(vec3(
+        height_at(world - vec2(cell, 0.0)) - height_at(world + vec2(cell, 0.0)),
+        2.0 * cell,
+        height_at(world - vec2(0.0, cell)) - height_at(world + vec2(0.0, cell)),
+    ));
+
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = mesh_functions::get_visibility_range_dither_level(vertex.instance_index, world_from_local[3]);
+#endif
+
+    return out;
+}
+
+//Only the ring of chunks that used to be LOD 4 entities is drawn, closer chunks are meshes
+fn in_band(world: vec2<f32>) -> bool {
+    let offset = vec2<i32>(floor(world / clipmap.chunk_size)) - clipmap.player_chunk;
+    let distance_sq = dot(offset, offset);
+    let distance = i32(sqrt(f32(distance_sq)));
+
+    return distance >= clipmap.inner_radius && distance_sq <= clipmap.outer_radius * clipmap.outer_radius;
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    if (!in_band(in.world_position.xz) || load_height(in.world_position.xz) < UNLOADED) {
+        discard;
+    }
+
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+    let weights = textureLoad(biomes, texel_at(in.world_position.xz), 0);
+    let albedo = splat(splat_params, layers, layers_sampler, in.world_position.xyz, normalize(in.world_normal), weights);
+    pbr_input.material.base_color = vec4(albedo, 1.0) * pbr_bindings::material.base_color;
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -1 +1,94 @@
+use bevy::math::Vec3;
 
+use crate::{noise::perlin_cpu::lerp, terrain::chunks::{Chunkbase, CHUNK_HEIGHT, CHUNK_WIDTH}};
+
+/// Distance between height checks while marching a ray, half a heightmap sample.
+const RAY_STEP: f32 = 0.5;
+/// Bisection steps refining a hit once the ray has crossed the surface.
+const RAY_REFINEMENT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainHit {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+/// Terrain queries answered straight from the heightmaps in memory, without Rapier.
+/// Everything returns `None` over chunks that aren't loaded. Heights are bilinear across
+/// each cell, while the render meshes and Parry heightfields split it into two triangles, so
+/// inside a cell they can disagree by up to the height change along half its diagonal.
+impl Chunkbase {
+    /// Height of the terrain at world position `(x, z)`, bilinearly interpolated.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (h00, h10, h01, h11, fx, fz) = self.cell_at(x, z)?;
+        Some(lerp(lerp(h00, h10, fx), lerp(h01, h11, fx), fz))
+    }
+
+    /// Upwards unit normal of the interpolated surface at `(x, z)`.
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<Vec3> {
+        let (h00, h10, h01, h11, fx, fz) = self.cell_at(x, z)?;
+        let dx = lerp(h10 - h00, h11 - h01, fz);
+        let dz = lerp(h01 - h00, h11 - h10, fx);
+
+        Some(Vec3::new(-dx, 1.0, -dz).normalize())
+    }
+
+    /// Angle between the surface and the horizontal at `(x, z)`, in radians.
+    pub fn slope_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.normal_at(x, z).map(|normal| normal.angle_between(Vec3::Y))
+    }
+
+    /// First point where the ray from `origin` along `direction` goes below the terrain,
+    /// within `max_distance`. Stretches over unloaded chunks are skipped.
+    pub fn raycast_terrain(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<TerrainHit> {
+        let direction = direction.try_normalize()?;
+        let above = |distance: f32| {
+            let point = origin + direction * distance;
+            self.height_at(point.x, point.z).map(|height| point.y >= height)
+        };
+
+        let mut previous = 0.0;
+        let mut previous_above = above(previous);
+        while previous < max_distance {
+            let distance = (previous + RAY_STEP).min(max_distance);
+            let current_above = above(distance);
+
+            if previous_above == Some(true) && current_above == Some(false) {
+                let (mut low, mut high) = (previous, distance);
+                for _ in 0..RAY_REFINEMENT {
+                    let middle = (low + high) / 2.0;
+                    match above(middle) {
+                        Some(false) => high = middle,
+                        _ => low = middle,
+                    }
+                }
+
+                let point = origin + direction * high;
+                let normal = self.normal_at(point.x, point.z)?;
+                return Some(TerrainHit { point, normal, distance: high });
+            }
+
+            previous = distance;
+            previous_above = current_above;
+        }
+
+        None
+    }
+
+    /// Corner heights of the heightmap cell containing `(x, z)` and the position within it.
+    fn cell_at(&self, x: f32, z: f32) -> Option<(f32, f32, f32, f32, f32, f32)> {
+        let (chunk_x, chunk_y) = ((x / CHUNK_WIDTH as f32).floor(), (z / CHUNK_HEIGHT as f32).floor());
+        let chunk = self.get_chunk(&(chunk_x as i32, chunk_y as i32))?;
+
+        let local_x = (x - chunk_x * CHUNK_WIDTH as f32).clamp(0.0, CHUNK_WIDTH as f32);
+        let local_z = (z - chunk_y * CHUNK_HEIGHT as f32).clamp(0.0, CHUNK_HEIGHT as f32);
+        let (cell_x, cell_z) = ((local_x as usize).min(CHUNK_WIDTH - 1), (local_z as usize).min(CHUNK_HEIGHT - 1));
+
+        let stride = CHUNK_WIDTH + 1;
+        let i = cell_z * stride + cell_x;
+        let heights = &chunk.heights;
+
+        Some((heights[i], heights[i + 1], heights[i + stride], heights[i + stride + 1], local_x - cell_x as f32, local_z - cell_z as f32))
+    }
+}
@@ -0,0 +1,213 @@
+use std::{collections::HashMap, fs::{self, File}, io::{BufReader, BufWriter}, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::terrain::chunks::{Chunkbase, CHUNK_HEIGHT, CHUNK_WIDTH};
+
+pub const TERRAIN_EDITS_PATH: &str = "saves/terrain_edits.json";
+
+/// How far from the player the debug brush can reach the ground.
+const DEBUG_BRUSH_REACH: f32 = 64.0;
+const DEBUG_BRUSH_RADIUS: f32 = 8.0;
+
+pub struct TerrainEditsPlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrushKind {
+    Raise,
+    Lower,
+    /// Pulls the terrain towards the height under the centre of the brush.
+    Flatten,
+    /// Pulls every sample towards the average of its neighbours.
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Brush {
+    pub kind: BrushKind,
+    /// Metres from the centre to where the falloff reaches zero.
+    pub radius: f32,
+    /// Metres at the centre for `Raise` and `Lower`, the fraction of the way to the
+    /// target for `Flatten` and `Smooth`.
+    pub strength: f32,
+}
+
+/// Height offsets painted over the generated terrain. Kept apart from the generated
+/// heights so a chunk regenerated after eviction, or from the region cache, gets its edits
+/// back. Each chunk only stores the samples it owns, its halo belongs to the neighbours.
+#[derive(Debug, Clone, Default)]
+pub struct TerrainEdits {
+    chunks: HashMap<(i32, i32), HashMap<(u16, u16), f32>>,
+}
+
+#[derive(Event)]
+pub struct DebugTerraformEvent(pub (Transform, Vec3, BrushKind));
+
+#[derive(Event)]
+pub struct DebugSaveTerrainEditsEvent;
+
+impl Plugin for TerrainEditsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<DebugTerraformEvent>()
+            .add_event::<DebugSaveTerrainEditsEvent>()
+            .add_systems(Update, (debug_terraform, debug_save_terrain_edits));
+    }
+}
+
+impl TerrainEdits {
+    /// Adds `delta` to world sample `(x, z)`.
+    pub fn add(&mut self, (x, z): (i32, i32), delta: f32) {
+        let (chunk, local) = split((x, z));
+        *self.chunks.entry(chunk).or_default().entry(local).or_insert(0.0) += delta;
+    }
+
+    pub fn delta_at(&self, (x, z): (i32, i32)) -> f32 {
+        let (chunk, local) = split((x, z));
+        self.chunks.get(&chunk).and_then(|samples| samples.get(&local)).copied().unwrap_or(0.0)
+    }
+
+    /// Deltas over the `(CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1)` heights of a chunk as
+    /// `(index, delta)`, the halo ones taken from the neighbours that own them.
+    pub fn chunk_deltas(&self, (x, y): (i32, i32)) -> Vec<(usize, f32)> {
+        let mut deltas = Vec::new();
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let Some(samples) = self.chunks.get(&(x + dx, y + dy)) else { continue };
+            for (&(local_x, local_y), &delta) in samples {
+                let (chunk_x, chunk_y) = (dx as usize * CHUNK_WIDTH + local_x as usize, dy as usize * CHUNK_HEIGHT + local_y as usize);
+                if chunk_x <= CHUNK_WIDTH && chunk_y <= CHUNK_HEIGHT {
+                    deltas.push((chunk_y * (CHUNK_WIDTH + 1) + chunk_x, delta));
+                }
+            }
+        }
+        deltas
+    }
+
+    /// Number of edited samples.
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let samples: Vec<(i32, i32, f32)> = serde_json::from_reader(BufReader::new(file))?;
+
+        let mut edits = TerrainEdits::default();
+        for (x, z, delta) in samples {
+            edits.add((x, z), delta);
+        }
+        Ok(edits)
+    }
+
+    /// Writes every edited sample as `[x, z, delta]`, sorted so unchanged edits give the same file.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let mut samples: Vec<(i32, i32, f32)> = self.chunks.iter()
+            .flat_map(|(&(chunk_x, chunk_y), samples)| samples.iter().map(move |(&(x, y), &delta)| (
+                chunk_x * CHUNK_WIDTH as i32 + x as i32,
+                chunk_y * CHUNK_HEIGHT as i32 + y as i32,
+                delta,
+            )))
+            .collect();
+        samples.sort_by_key(|&(x, z, _)| (z, x));
+
+        if let Some(directory) = path.as_ref().parent() {
+            fs::create_dir_all(directory)?;
+        }
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &samples)?;
+        Ok(())
+    }
+}
+
+/// Owning chunk of a world sample and its position within it.
+fn split((x, z): (i32, i32)) -> ((i32, i32), (u16, u16)) {
+    let (width, height) = (CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
+    ((x.div_euclid(width), z.div_euclid(height)), (x.rem_euclid(width) as u16, z.rem_euclid(height) as u16))
+}
+
+impl Chunkbase {
+    /// Height of world sample `(x, z)`, `None` if its chunk isn't loaded.
+    pub fn sample_height(&self, (x, z): (i32, i32)) -> Option<f32> {
+        let ((chunk_x, chunk_y), (local_x, local_y)) = split((x, z));
+        let chunk = self.get_chunk(&(chunk_x, chunk_y))?;
+        Some(chunk.heights[local_y as usize * (CHUNK_WIDTH + 1) + local_x as usize])
+    }
+
+    /// Applies `brush` around world position `centre` (x, z) and queues the rebuild of every
+    /// chunk it touched. Returns the number of samples changed.
+    pub fn apply_brush(&mut self, centre: Vec2, brush: &Brush) -> usize {
+        let target = match brush.kind {
+            BrushKind::Flatten => match self.height_at(centre.x, centre.y) {
+                Some(height) => height,
+                None => return 0,
+            },
+            _ => 0.0,
+        };
+
+        //Every delta is worked out from the heights before this application
+        let mut changes = Vec::new();
+        for z in (centre.y - brush.radius).ceil() as i32..=(centre.y + brush.radius).floor() as i32 {
+            for x in (centre.x - brush.radius).ceil() as i32..=(centre.x + brush.radius).floor() as i32 {
+                let distance = Vec2::new(x as f32, z as f32).distance(centre);
+                if distance > brush.radius {
+                    continue;
+                }
+                let weight = (1.0 - (distance / brush.radius).powi(2)).powi(2);
+
+                let delta = match brush.kind {
+                    BrushKind::Raise => brush.strength * weight,
+                    BrushKind::Lower => -brush.strength * weight,
+                    BrushKind::Flatten => {
+                        let Some(height) = self.sample_height((x, z)) else { continue };
+                        (target - height) * (brush.strength * weight).min(1.0)
+                    }
+                    BrushKind::Smooth => {
+                        let Some(height) = self.sample_height((x, z)) else { continue };
+                        let neighbours: Vec<f32> = (-1..=1)
+                            .flat_map(|dz| (-1..=1).map(move |dx| (x + dx, z + dz)))
+                            .filter_map(|sample| self.sample_height(sample))
+                            .collect();
+                        let average = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+                        (average - height) * (brush.strength * weight).min(1.0)
+                    }
+                };
+
+                if delta != 0.0 {
+                    changes.push(((x, z), delta));
+                }
+            }
+        }
+
+        self.edit_heights(&changes);
+        changes.len()
+    }
+}
+
+/// Sculpts the ground the player is looking at while a terraform key is held.
+fn debug_terraform(time: Res<Time>, mut chunkbase: ResMut<Chunkbase>, mut events: EventReader<DebugTerraformEvent>) {
+    for DebugTerraformEvent((transform, direction, kind)) in events.read() {
+        let Some(hit) = chunkbase.raycast_terrain(transform.translation, *direction, DEBUG_BRUSH_REACH) else { continue };
+
+        //Per second, the event repeats every frame the key is down
+        let strength = match kind {
+            BrushKind::Raise | BrushKind::Lower => 4.0,
+            BrushKind::Flatten | BrushKind::Smooth => 2.0,
+        };
+        let brush = Brush { kind: *kind, radius: DEBUG_BRUSH_RADIUS, strength: strength * time.delta_secs() };
+        chunkbase.apply_brush(hit.point.xz(), &brush);
+    }
+}
+
+fn debug_save_terrain_edits(chunkbase: Res<Chunkbase>, mut events: EventReader<DebugSaveTerrainEditsEvent>) {
+    for _ in events.read() {
+        match chunkbase.edits().save(TERRAIN_EDITS_PATH) {
+            Ok(()) => info!("Saved {} edited terrain samples to {TERRAIN_EDITS_PATH}", chunkbase.edits().len()),
+            Err(e) => error!("Could not save terrain edits to {TERRAIN_EDITS_PATH}: {e}"),
+        }
+    }
+}
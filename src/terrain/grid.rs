@@ -1,9 +1,9 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
-use crate::{player::player::Player, terrain::chunks::{Chunkbase, RenderDistance, RenderedChunks, CHUNK_HEIGHT, CHUNK_WIDTH}};
+use crate::{player::{camera_controller::CameraController, player::Player}, terrain::{clipmap::ClipmapSettings, occlusion::{hide_occluded, update_chunk_view, ChunkView, Occludable}, material::{TerrainMaterial, TerrainMaterialPlugin, TerrainSplat}, terrain_config::{ColliderConfig, TerrainGenConfig}, chunks::{Chunk, Chunkbase, RenderDistance, RenderedChunks, CHUNK_HEIGHT, CHUNK_WIDTH}}};
 
 pub struct GridPlugin;
 
@@ -13,22 +13,165 @@ struct LastChunk((i32, i32));
 #[derive(Event)]
 pub struct CurrentChunk(pub (i32, i32));
 
+/// Chunks drawn as entities, spawned or queued, with their band from `mesh_bands`.
 #[derive(Resource, Default)]
 struct RenderRadius(pub HashSet<((i32, i32), u32)>);
 
+/// Chunk entities waiting to be spawned or despawned and colliders waiting to be inserted,
+/// worked through a few a frame so crossing into a new chunk doesn't stall, see `StreamingBudget`.
 #[derive(Resource, Default)]
-struct LODRadiusOld(pub HashSet<(i32, i32)>);
+pub struct StreamingQueue {
+    /// Chunks to generate, requested a few at a time so the ones in view go first.
+    loads: HashSet<(i32, i32)>,
+    /// Chunks inside the render radius without an entity, generated or not.
+    spawns: HashSet<((i32, i32), u32)>,
+    despawns: VecDeque<Entity>,
+    /// Colliders built and waiting for their turn, counted by `stream_colliders`.
+    colliders: usize,
+}
+
+/// Most of each kind of work `StreamingQueue` does in a frame.
+#[derive(Resource, Clone)]
+pub struct StreamingBudget {
+    /// Chunks generating at once.
+    pub generating: usize,
+    pub spawns: usize,
+    pub despawns: usize,
+    /// Collider entities inserted into the physics world.
+    pub colliders: usize,
+    /// Chunks whose props are spawned.
+    pub props: usize,
+}
+
+impl Default for StreamingBudget {
+    fn default() -> Self {
+        StreamingBudget { generating: 32, spawns: 16, despawns: 32, colliders: 4, props: 4 }
+    }
+}
+
+impl StreamingQueue {
+    /// Chunks to generate, chunk entities to spawn, to despawn and colliders to insert.
+    pub fn depth(&self) -> (usize, usize, usize, usize) {
+        (self.loads.len(), self.spawns.len(), self.despawns.len(), self.colliders)
+    }
+}
+
+/// Materials for the chunk entities of each LOD. The same one unless the `debug` feature
+/// tints them green, yellow and red.
+#[derive(Resource)]
+struct ChunkMaterials {
+    lod_0: Handle<TerrainMaterial>,
+    lod_2: Handle<TerrainMaterial>,
+    lod_4: Handle<TerrainMaterial>,
+}
+
+impl FromWorld for ChunkMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let splat = world.resource::<TerrainSplat>().clone();
+        let mut materials = world.resource_mut::<Assets<TerrainMaterial>>();
+
+        #[cfg(feature = "debug")]
+        let (lod_0, lod_2, lod_4) = (
+            materials.add(splat.material(Color::srgb_u8(20, 180, 20))),
+            materials.add(splat.material(Color::srgb_u8(180, 180, 20))),
+            materials.add(splat.material(Color::srgb_u8(180, 20, 20))),
+        );
+        #[cfg(not(feature = "debug"))]
+        let (lod_0, lod_2, lod_4) = {
+            let material = materials.add(splat.material(Color::WHITE));
+            (material.clone(), material.clone(), material)
+        };
+
+        ChunkMaterials { lod_0, lod_2, lod_4 }
+    }
+}
+
+impl ChunkMaterials {
+    /// Mesh and material of a chunk at an index into `LOD_STEPS`.
+    fn get(&self, chunk: &Chunk, lod: usize) -> (Handle<Mesh>, Handle<TerrainMaterial>) {
+        match lod {
+            0 => (chunk.mesh.clone(), self.lod_0.clone()),
+            1 => (chunk.mesh_2.clone(), self.lod_2.clone()),
+            _ => (chunk.mesh_4.clone(), self.lod_4.clone()),
+        }
+    }
+}
+
+/// How chunk entities pick their LOD, see `select_lod`.
+#[derive(Resource, Clone)]
+pub struct LodSettings {
+    /// Pixels the terrain may be off by on screen before a finer mesh is used.
+    pub max_pixel_error: f32,
+    /// Fraction below `max_pixel_error` a coarser mesh has to get before switching to it,
+    /// so chunks near the threshold don't flicker between two LODs.
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        LodSettings { max_pixel_error: 2.0, hysteresis: 0.25 }
+    }
+}
+
+/// Chunk a chunk entity shows and the index into `LOD_STEPS` of the mesh it has.
+#[derive(Component)]
+struct ChunkLod {
+    coordinates: (i32, i32),
+    lod: usize,
+}
+
+/// Chunks within `ColliderConfig::reach_radius` of the player and the collider step each
+/// wants, nearest first. Worked out again by `load_map` whenever the player changes chunk.
+#[derive(Resource, Default)]
+pub struct ColliderReach {
+    wanted: Vec<((i32, i32), usize)>,
+    chunks: HashSet<(i32, i32)>,
+    /// `Chunkbase::changes` when `stream_colliders` last went through `wanted`, `None` once
+    /// `wanted` changed.
+    seen: Option<u64>,
+}
+
+impl ColliderReach {
+    pub fn contains(&self, coordinates: &(i32, i32)) -> bool {
+        self.chunks.contains(coordinates)
+    }
+
+    fn update(&mut self, (cx, cy): (i32, i32), settings: &ColliderConfig) {
+        self.wanted = get_circle_area(cx, cy, settings.reach_radius as i32, settings.near_radius)
+            .into_iter()
+            .map(|(coordinates, lod)| (coordinates, if lod == 0 { settings.near_step } else { settings.far_step }))
+            .collect();
+        self.wanted.sort_by_key(|((x, y), _)| (x - cx).pow(2) + (y - cy).pow(2));
+        self.chunks = self.wanted.iter().map(|(coordinates, _)| *coordinates).collect();
+        self.seen = None;
+    }
+}
+
+/// Collider entities around the player with the sample step and chunk revision each was built from.
+#[derive(Resource, Default)]
+pub struct ChunkColliders(pub HashMap<(i32, i32), (usize, u32, Entity)>);
 
 #[derive(Resource, Default)]
 pub struct ChunkRadius(pub Vec<Entity>);
 
 impl Plugin for GridPlugin {
     fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<TerrainMaterialPlugin>() {
+            app.add_plugins(TerrainMaterialPlugin);
+        }
+
         app
             .init_resource::<LastChunk>()
             .insert_resource(RenderRadius::default())
+            .init_resource::<StreamingQueue>()
+            .init_resource::<StreamingBudget>()
+            .init_resource::<ChunkMaterials>()
+            .init_resource::<ChunkColliders>()
+            .init_resource::<ColliderReach>()
+            .init_resource::<LodSettings>()
             .add_event::<CurrentChunk>()
-            .add_systems(Update, (enter_chunk_event, load_map))
+            .init_resource::<ChunkView>()
+            .add_systems(Update, (enter_chunk_event, poll_chunks, load_map, update_chunk_view, stream_chunks, update_lods, hide_occluded, stream_colliders).chain())
         ;
     }
 }
@@ -50,82 +193,245 @@ fn enter_chunk_event(
     }
 }
 
+fn poll_chunks(mut chunkbase: ResMut<Chunkbase>, mut meshes: ResMut<Assets<Mesh>>) {
+    chunkbase.poll_tasks(&mut meshes);
+}
+
+/// Queues the chunks to generate and the chunk entities to spawn and despawn whenever the
+/// player changes chunk or the render distance or clipmap changes. The far ring is only
+/// drawn as entities while the clipmap is off.
 fn load_map(
-    chunkbase: Res<Chunkbase>,
-    render_distance: Res<RenderDistance>,
+    mut chunkbase: ResMut<Chunkbase>,
+    (render_distance, config, clipmap): (Res<RenderDistance>, Res<TerrainGenConfig>, Option<Res<ClipmapSettings>>),
     mut player_query: Query<&mut Player>,
-    mut commands: Commands,
     mut render_radius: ResMut<RenderRadius>,
+    (mut queue, mut reach): (ResMut<StreamingQueue>, ResMut<ColliderReach>),
     mut rendered_chunks: ResMut<RenderedChunks>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     mut events: EventReader<CurrentChunk>,
 ) {
     let mut player = player_query.single_mut().unwrap();
-    let green = materials.add(StandardMaterial { base_color: Color::srgb_u8(20, 180, 20), perceptual_roughness: 0.5, ..default() });
-    let yellow = materials.add(StandardMaterial { base_color: Color::srgb_u8(180, 180, 20), perceptual_roughness: 0.5, ..default() });
-    let red = materials.add(StandardMaterial { base_color: Color::srgb_u8(180, 20, 20), perceptual_roughness: 0.5, ..default() });
-    
-    let mut update_chunks = |load_raw: HashSet<((i32, i32), u32)>| {
-        for chunk_info in render_radius.0.difference(&load_raw) {
+    let clipmap_enabled = clipmap.as_ref().is_some_and(|settings| settings.enabled);
+    let mut update_chunks = |centre: (i32, i32), load_raw: HashSet<((i32, i32), u32)>| {
+        reach.update(centre, &config.colliders);
+        let in_radius: HashSet<(i32, i32)> = load_raw.iter().map(|(coordinates, _)| *coordinates).collect();
+        chunkbase.cancel_unwanted(|coordinates| in_radius.contains(coordinates) || reach.contains(coordinates));
+
+        queue.loads.retain(|coordinates| in_radius.contains(coordinates) || reach.contains(coordinates));
+
+        //The clipmap draws the far ring from the chunk heights, no entity needed
+        let drawn: HashSet<((i32, i32), u32)> = load_raw.iter().filter(|chunk_info| !clipmap_enabled || chunk_info.1 != 4).copied().collect();
+        for chunk_info in render_radius.0.difference(&drawn) {
+            queue.spawns.remove(chunk_info);
             if let Some(entity) = rendered_chunks.0.remove(&chunk_info.0) {
-                commands.entity(entity).despawn();
+                queue.despawns.push_back(entity);
             }
         }
 
-        for chunk_info in load_raw.difference(&render_radius.0) {
-            if let Some(chunk) = chunkbase.get_chunk(&chunk_info.0) {
-                let chunk_entity;
-                if chunk_info.1 == 0 {
-                    chunk_entity = commands.spawn((
-                        Mesh3d(meshes.add(chunk.mesh.clone())), 
-                        MeshMaterial3d(green.clone()), 
-                        RigidBody::Fixed,
-                        chunk.transform,
-                    )).with_child((
-                        RigidBody::Fixed,
-                        chunk.collider.clone(),
-                        Transform::from_xyz(64.0, 0.0, 64.0)
-                                .with_rotation(Quat::from_rotation_y(90_f32.to_radians()))
-                                //FIXME.with_scale(Vec3::new(1.0, 1.0, -1.0))
-                    )).id();
-                } else if chunk_info.1 == 2 {
-                    chunk_entity = commands.spawn((
-                        Mesh3d(meshes.add(chunk.mesh_2.clone())),
-                        MeshMaterial3d(yellow.clone()),
-                        chunk.transform, 
-                    )).id();
-                }
-                else { 
-                    chunk_entity = commands.spawn((
-                        Mesh3d(meshes.add(chunk.mesh_4.clone())),
-                        MeshMaterial3d(red.clone()),
-                        chunk.transform,
-                    )).id();
-                }
-
-                rendered_chunks.0.insert(chunk_info.0, chunk_entity);
+        chunkbase.reserve(&in_radius);
+        for coordinates in &in_radius {
+            //Keeps the loaded ones from being evicted
+            match chunkbase.get_chunk(coordinates) {
+                Some(_) => chunkbase.request(*coordinates),
+                None => { queue.loads.insert(*coordinates); }
             }
         }
 
-        render_radius.0 = load_raw;
+        queue.spawns.extend(drawn.difference(&render_radius.0).copied());
+        render_radius.0 = drawn;
     };
 
     for CurrentChunk((cx, cy)) in events.read() {
-        let load_raw: HashSet<((i32, i32), u32)> = get_circle_area(*cx, *cy, render_distance.0 as i32, player.config.lod_radius).iter().cloned().collect();
+        let load_raw = mesh_bands(get_circle_area(*cx, *cy, render_distance.0 as i32, player.config.lod_radius));
 
-        update_chunks(load_raw);
+        update_chunks((*cx, *cy), load_raw);
 
         player.current_chunk = CurrentChunk((*cx, *cy));
     }
 
-    if render_distance.is_changed() {
+    if render_distance.is_changed() || config.is_changed() || clipmap.is_some_and(|settings| settings.is_changed()) {
         let (cx, cy) = player.current_chunk.0;
-        let load_raw: HashSet<((i32, i32), u32)> = get_circle_area(cx, cy, render_distance.0 as i32, player.config.lod_radius).iter().cloned().collect();
+        let load_raw = mesh_bands(get_circle_area(cx, cy, render_distance.0 as i32, player.config.lod_radius));
+
+        update_chunks((cx, cy), load_raw);
+    }
+}
+
+/// Works through the queue up to the budget, generating, spawning and despawning the chunks
+/// `ChunkView::priority` puts first.
+fn stream_chunks(
+    mut chunkbase: ResMut<Chunkbase>,
+    mut queue: ResMut<StreamingQueue>,
+    mut rendered_chunks: ResMut<RenderedChunks>,
+    materials: Res<ChunkMaterials>,
+    budget: Res<StreamingBudget>,
+    view: Res<ChunkView>,
+    mut commands: Commands,
+) {
+    queue.loads.retain(|coordinates| chunkbase.get_chunk(coordinates).is_none());
+    let mut loads: Vec<(i32, i32)> = queue.loads.iter().copied().collect();
+    loads.sort_by(|a, b| view.priority(*a).total_cmp(&view.priority(*b)));
+    for coordinates in loads.into_iter().take(budget.generating.saturating_sub(chunkbase.pending())) {
+        queue.loads.remove(&coordinates);
+        chunkbase.request(coordinates);
+    }
+
+    for _ in 0..budget.despawns {
+        let Some(entity) = queue.despawns.pop_front() else { break };
+        commands.entity(entity).despawn();
+    }
+
+    let mut ready: Vec<((i32, i32), u32)> = queue.spawns.iter()
+        .filter(|chunk_info| chunkbase.get_chunk(&chunk_info.0).is_some())
+        .copied()
+        .collect();
+    ready.sort_by(|a, b| view.priority(a.0).total_cmp(&view.priority(b.0)));
+
+    for chunk_info in ready.into_iter().take(budget.spawns) {
+        queue.spawns.remove(&chunk_info);
+        let Some(chunk) = chunkbase.get_chunk(&chunk_info.0) else { continue };
+
+        //Finest near the player and coarsest far away until `update_lods` has seen it from the camera
+        let lod = if chunk_info.1 == 4 { 2 } else { 0 };
+        let (mesh, material) = materials.get(chunk, lod);
+        let chunk_entity = commands.spawn((
+            Mesh3d(mesh),
+            MeshMaterial3d(material),
+            chunk.transform,
+            ChunkLod { coordinates: chunk_info.0, lod },
+            Occludable(vec![chunk_info.0]),
+        )).id();
+
+        rendered_chunks.0.insert(chunk_info.0, chunk_entity);
+    }
+}
+
+/// Collapses the LOD rings of `get_circle_area` into chunks drawn as entities, 0, and the far
+/// ring the clipmap can draw, 4. Entities pick their own LOD in `update_lods`, so moving
+/// between the inner rings no longer respawns them.
+fn mesh_bands(area: Vec<((i32, i32), u32)>) -> HashSet<((i32, i32), u32)> {
+    area.into_iter().map(|(coordinates, lod)| (coordinates, if lod == 4 { 4 } else { 0 })).collect()
+}
+
+/// Gives every chunk entity the coarsest mesh whose error stays under
+/// `LodSettings::max_pixel_error` on screen, so flat ground goes coarse close by while
+/// cliffs keep their detail further out.
+fn update_lods(
+    chunkbase: Res<Chunkbase>,
+    settings: Res<LodSettings>,
+    materials: Res<ChunkMaterials>,
+    camera_query: Query<(&Camera, &Projection, &GlobalTransform), With<CameraController>>,
+    mut chunk_query: Query<(Entity, &mut ChunkLod)>,
+    mut commands: Commands,
+) {
+    let Ok((camera, Projection::Perspective(perspective), camera_transform)) = camera_query.single() else { return };
+    let Some(viewport) = camera.physical_viewport_size() else { return };
+    let projection_scale = viewport.y as f32 / (2.0 * (perspective.fov / 2.0).tan());
+    let eye = camera_transform.translation();
+
+    for (entity, mut chunk_lod) in &mut chunk_query {
+        let Some(chunk) = chunkbase.get_chunk(&chunk_lod.coordinates) else { continue };
 
-        update_chunks(load_raw);
+        let min = Vec3::new(chunk.transform.translation.x, chunk.height_range.0, chunk.transform.translation.z);
+        let max = min + Vec3::new(CHUNK_WIDTH as f32, chunk.height_range.1 - chunk.height_range.0, CHUNK_HEIGHT as f32);
+        let distance = eye.distance(eye.clamp(min, max));
+
+        let lod = select_lod(chunk.lod_errors, distance, projection_scale, chunk_lod.lod, &settings);
+        if lod != chunk_lod.lod {
+            chunk_lod.lod = lod;
+            let (mesh, material) = materials.get(chunk, lod);
+            commands.entity(entity).insert((Mesh3d(mesh), MeshMaterial3d(material)));
+        }
     }
+}
 
+/// Index into `LOD_STEPS` of the coarsest mesh whose `errors`, in metres, project to at most
+/// `max_pixel_error` pixels from `distance` away. `projection_scale` is the pixels a metre
+/// covers one metre from the camera, the screen height over `2 * tan(fov / 2)`.
+/// Refines as soon as `current` goes over the limit but only coarsens once the coarser
+/// mesh is under it by the hysteresis margin.
+pub fn select_lod(errors: [f32; 3], distance: f32, projection_scale: f32, current: usize, settings: &LodSettings) -> usize {
+    let coarsest_within = |max_pixels: f32| {
+        (0..errors.len()).rev()
+            .find(|&lod| errors[lod] * projection_scale <= max_pixels * distance)
+            .unwrap_or(0)
+    };
+
+    let needed = coarsest_within(settings.max_pixel_error);
+    if needed < current {
+        return needed;
+    }
+    coarsest_within(settings.max_pixel_error * (1.0 - settings.hysteresis)).max(current)
+}
+
+/// Keeps a heightfield collider on every chunk within reach, full resolution near the
+/// player and coarser further out. Colliders are built on the task pool and swapped in
+/// once ready, the previous one stays until then so there is never a gap. Only
+/// `StreamingBudget::colliders` are inserted a frame, nearest the player first. Only runs
+/// when `ColliderReach` moved, something landed in the `Chunkbase` or colliders are waiting.
+fn stream_colliders(
+    mut chunkbase: ResMut<Chunkbase>,
+    config: Res<TerrainGenConfig>,
+    mut reach: ResMut<ColliderReach>,
+    mut colliders: ResMut<ChunkColliders>,
+    (budget, mut queue): (Res<StreamingBudget>, ResMut<StreamingQueue>),
+    mut commands: Commands,
+) {
+    if reach.seen == Some(chunkbase.changes()) && queue.colliders == 0 {
+        return;
+    }
+    let settings = config.colliders;
+
+    //Only when the player moved, later passes wait on what these requests land
+    if reach.seen.is_none() {
+        for &(coordinates, step) in &reach.wanted {
+            //Only the ground under the player skips the queue
+            if chunkbase.get_chunk(&coordinates).is_some() || step == settings.near_step {
+                chunkbase.request(coordinates);
+            } else {
+                queue.loads.insert(coordinates);
+            }
+        }
+    }
+    reach.seen = Some(chunkbase.changes());
+
+    colliders.0.retain(|coordinates, (_, _, entity)| {
+        let keep = reach.contains(coordinates);
+        if !keep {
+            commands.entity(*entity).despawn();
+        }
+        keep
+    });
+
+    let mut inserted = 0;
+    queue.colliders = 0;
+    for &(coordinates, step) in &reach.wanted {
+        let Some(chunk) = chunkbase.get_chunk(&coordinates) else { continue };
+        let revision = chunk.revision;
+        if colliders.0.get(&coordinates).is_some_and(|(current, built, _)| *current == step && *built == revision) {
+            continue;
+        }
+
+        let Some(collider) = chunk.colliders.get(&step).cloned() else {
+            chunkbase.request_collider(coordinates, step);
+            continue;
+        };
+
+        if inserted == budget.colliders {
+            queue.colliders += 1;
+            continue;
+        }
+        inserted += 1;
+
+        let entity = commands.spawn((
+            RigidBody::Fixed,
+            collider,
+            chunk.collider_transform(),
+        )).id();
+
+        if let Some((_, _, old)) = colliders.0.insert(coordinates, (step, revision, entity)) {
+            commands.entity(old).despawn();
+        }
+    }
 }
 
 pub fn get_circle_area(cx: i32, cy: i32, radius: i32, lod_radius: u32) -> Vec<((i32, i32), u32)> {
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use bevy::{asset::{embedded_asset, load_internal_asset, weak_handle, RenderAssetUsages}, image::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor}, pbr::{ExtendedMaterial, MaterialExtension}, render::render_resource::{AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat}};
+
+use crate::terrain::{biomes::{Biome, BIOME_COUNT}, terrain_config::TerrainGenConfig};
+
+const SHADER_PATH: &str = "embedded://terrain/terrain/terrain.wgsl";
+
+/// `terrain::splat`, imported by both the chunk and the clipmap shaders.
+const SPLAT_SHADER: Handle<Shader> = weak_handle!("5b0f1c7e-3d2a-4e8b-9a61-c4f07d2e8b13");
+
+/// Texels a side of every layer of the texture array.
+const LAYER_SIZE: u32 = 256;
+
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainExtension>;
+
+/// Textures the terrain by splatting one texture per biome. Chunk meshes carry their biome
+/// weights in `Mesh::ATTRIBUTE_COLOR`, and altitude and slope then paint beaches, snow and
+/// triplanar mapped rock over them.
+pub struct TerrainMaterialPlugin;
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TerrainExtension {
+    #[uniform(100)]
+    pub splat: SplatParams,
+    #[texture(101, dimension = "2d_array")]
+    #[sampler(102)]
+    pub layers: Handle<Image>,
+}
+
+pub use params::SplatParams;
+
+mod params {
+    //encase's ShaderType derive emits an unused `check` fn per field
+    #![allow(dead_code)]
+
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(ShaderType, Reflect, Debug, Clone, Copy, PartialEq)]
+    pub struct SplatParams {
+        /// Texture repeats per metre.
+        pub tiling: f32,
+        /// Slope in degrees where rock takes over, and the degrees either side it blends over.
+        pub cliff_angle: f32,
+        pub cliff_blend: f32,
+        /// Height snow covers the ground from.
+        pub snow_height: f32,
+        pub snow_blend: f32,
+        /// Height sand covers the ground up to.
+        pub beach_height: f32,
+        pub beach_blend: f32,
+        /// Higher keeps each triplanar projection to the faces it suits best.
+        pub triplanar_sharpness: f32,
+    }
+}
+
+/// Splat settings and texture array shared by every terrain material.
+#[derive(Resource, Clone)]
+pub struct TerrainSplat {
+    pub splat: SplatParams,
+    pub layers: Handle<Image>,
+}
+
+impl MaterialExtension for TerrainExtension {
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+}
+
+impl Plugin for TerrainMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, SPLAT_SHADER, "splat.wgsl", Shader::from_wgsl);
+        embedded_asset!(app, "terrain.wgsl");
+
+        app
+            .add_plugins(MaterialPlugin::<TerrainMaterial>::default())
+            .init_resource::<TerrainSplat>()
+        ;
+    }
+}
+
+impl SplatParams {
+    /// Beaches just above the sea and snow a little above where the ground turns to rock.
+    pub fn from_config(config: &TerrainGenConfig) -> Self {
+        SplatParams {
+            tiling: 0.125,
+            cliff_angle: 40.0,
+            cliff_blend: 8.0,
+            snow_height: config.biomes.rock_height + 2.0 * config.biomes.rock_blend,
+            snow_blend: 20.0,
+            beach_height: config.sea_level + 2.0,
+            beach_blend: 1.5,
+            triplanar_sharpness: 4.0,
+        }
+    }
+}
+
+impl FromWorld for TerrainSplat {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.get_resource::<TerrainGenConfig>().cloned().unwrap_or_default();
+        let layers = world.resource_mut::<Assets<Image>>().add(layer_textures());
+
+        TerrainSplat { splat: SplatParams::from_config(&config), layers }
+    }
+}
+
+impl TerrainSplat {
+    /// A terrain material multiplied by `tint`, white leaves the textures as they are.
+    pub fn material(&self, tint: Color) -> TerrainMaterial {
+        TerrainMaterial {
+            base: StandardMaterial { base_color: tint, perceptual_roughness: 0.9, ..default() },
+            extension: TerrainExtension { splat: self.splat, layers: self.layers.clone() },
+        }
+    }
+}
+
+/// One tiling texture per biome in `Biome::ALL` order with a full mip chain, generated
+/// from the biome colour roughened by value noise.
+fn layer_textures() -> Image {
+    let levels = LAYER_SIZE.ilog2() + 1;
+    let mut data = Vec::new();
+
+    for biome in Biome::ALL {
+        let [red, green, blue, _] = biome.colour().to_srgba().to_u8_array();
+        let contrast = match biome {
+            Biome::Grassland => 0.25,
+            Biome::Forest => 0.35,
+            Biome::Desert => 0.15,
+            Biome::Tundra => 0.08,
+            Biome::Rock => 0.3,
+        };
+
+        let mut level: Vec<u8> = (0..LAYER_SIZE * LAYER_SIZE).flat_map(|texel| {
+            let (x, y) = (texel % LAYER_SIZE, texel / LAYER_SIZE);
+            let shade = 1.0 + contrast * (2.0 * tiling_noise(biome as u32, x, y) - 1.0);
+            [red, green, blue].map(|channel| (channel as f32 * shade).clamp(0.0, 255.0) as u8).into_iter().chain([u8::MAX])
+        }).collect();
+
+        //Layer major, every mip of a layer before the next layer
+        let mut size = LAYER_SIZE;
+        data.extend_from_slice(&level);
+        while size > 1 {
+            level = downsample(&level, size);
+            size /= 2;
+            data.extend_from_slice(&level);
+        }
+    }
+
+    let mut image = Image::new_uninit(
+        Extent3d { width: LAYER_SIZE, height: LAYER_SIZE, depth_or_array_layers: BIOME_COUNT as u32 },
+        TextureDimension::D2,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.texture_descriptor.mip_level_count = levels;
+    image.data = Some(data);
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Linear,
+        min_filter: ImageFilterMode::Linear,
+        mipmap_filter: ImageFilterMode::Linear,
+        anisotropy_clamp: 8,
+        ..default()
+    });
+    image
+}
+
+/// Averages every 2x2 block of an RGBA8 texture `size` texels a side.
+fn downsample(level: &[u8], size: u32) -> Vec<u8> {
+    let half = size / 2;
+    (0..half * half).flat_map(|texel| {
+        let (x, y) = (texel % half * 2, texel / half * 2);
+        let offsets = [(0, 0), (1, 0), (0, 1), (1, 1)];
+        (0..4).map(move |channel| {
+            let sum: u32 = offsets.iter().map(|(dx, dy)| level[(((y + dy) * size + x + dx) * 4 + channel) as usize] as u32).sum();
+            (sum / 4) as u8
+        })
+    }).collect()
+}
+
+/// Three octaves of value noise in `[0, 1)` that wrap every `LAYER_SIZE` texels.
+fn tiling_noise(seed: u32, x: u32, y: u32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 0.5;
+    for cell in [32, 8, 2] {
+        let cells = LAYER_SIZE / cell;
+        let (cx, cy) = (x / cell, y / cell);
+        let (fx, fy) = ((x % cell) as f32 / cell as f32, (y % cell) as f32 / cell as f32);
+        let corner = |dx: u32, dy: u32| lattice(seed ^ cell, (cx + dx) % cells, (cy + dy) % cells);
+
+        let (sx, sy) = (fx * fx * (3.0 - 2.0 * fx), fy * fy * (3.0 - 2.0 * fy));
+        let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * sx;
+        let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * sx;
+        total += (top + (bottom - top) * sy) * amplitude;
+        amplitude *= 0.5;
+    }
+    total / 0.875
+}
+
+/// Random value in `[0, 1)` at a lattice point.
+fn lattice(seed: u32, x: u32, y: u32) -> f32 {
+    let mut hash = seed.wrapping_mul(0x9e37_79b9) ^ x.wrapping_mul(0x85eb_ca6b) ^ y.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    (hash >> 8) as f32 / 16_777_216.0
+}
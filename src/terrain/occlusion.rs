@@ -0,0 +1,220 @@
+use std::{collections::HashSet, f32::consts::{PI, TAU}};
+
+use bevy::{prelude::*, render::view::RenderLayers};
+
+use crate::{player::{camera_controller::CameraController, player::Player}, terrain::{chunks::{Chunkbase, RenderDistance, CHUNK_HEIGHT, CHUNK_WIDTH}, grid::get_circle_area}};
+
+/// Directions around the camera the horizon is tracked in.
+const HORIZON_BINS: usize = 512;
+
+/// How much further away a chunk right behind the camera counts as than one in view.
+const BEHIND_WEIGHT: f32 = 3.0;
+
+/// Occluded chunks count as this much further away when streaming.
+const OCCLUDED_WEIGHT: f32 = 2.0;
+
+/// Render layer occluded entities move to. Only the lights draw it, so what the terrain
+/// hides keeps casting shadows.
+pub const OCCLUDED_LAYER: usize = 1;
+
+/// Kept from the camera while every chunk it stands on is occluded, e.g. a chunk, a lake,
+/// a river along the chunks it runs through, or a prop.
+#[derive(Component, Clone)]
+pub struct Occludable(pub Vec<(i32, i32)>);
+
+/// Where the chunks are seen from, updated every frame by `update_chunk_view` from the
+/// `CameraController` camera. Orders chunk streaming and hides chunks behind the terrain.
+#[derive(Resource, Default)]
+pub struct ChunkView {
+    pub eye: Vec3,
+    /// Horizontal direction the camera faces.
+    pub forward: Vec2,
+    /// Half the horizontal field of view, in radians.
+    pub half_fov: f32,
+    /// Loaded chunks in the render radius that the terrain in front of them hides entirely.
+    pub occluded: HashSet<(i32, i32)>,
+}
+
+/// A chunk seen from the eye, as a range of directions and distances.
+struct Extent {
+    coordinates: (i32, i32),
+    near: f32,
+    far: f32,
+    /// Lowest and highest height in the chunk.
+    height_range: (f32, f32),
+    /// First and last horizon bin the chunk reaches into.
+    first_bin: usize,
+    last_bin: usize,
+}
+
+impl ChunkView {
+    /// Lower streams in sooner. The distance to the chunk, weighted up the further outside
+    /// the field of view it is and again when the terrain hides it.
+    pub fn priority(&self, (x, y): (i32, i32)) -> f32 {
+        let centre = Vec2::new((x as f32 + 0.5) * CHUNK_WIDTH as f32, (y as f32 + 0.5) * CHUNK_HEIGHT as f32);
+        let offset = centre - self.eye.xz();
+        let distance = offset.length();
+
+        //Anything touching the view cone counts as in view
+        let radius = Vec2::new(CHUNK_WIDTH as f32, CHUNK_HEIGHT as f32).length() / 2.0;
+        let angle = self.forward.angle_to(offset).abs() - radius.atan2(distance);
+        let outside = (angle - self.half_fov).max(0.0) / (PI - self.half_fov).max(f32::EPSILON);
+
+        let weight = 1.0 + BEHIND_WEIGHT * outside.min(1.0);
+        match self.occluded.contains(&(x, y)) {
+            true => distance * weight * OCCLUDED_WEIGHT,
+            false => distance * weight,
+        }
+    }
+
+    pub fn is_occluded(&self, coordinates: (i32, i32)) -> bool {
+        self.occluded.contains(&coordinates)
+    }
+}
+
+/// Loaded chunks of `area` hidden from `eye` by nearer terrain. Walks the chunks from the
+/// nearest out, keeping the steepest slope up from the eye the terrain reaches in each
+/// direction. A chunk is hidden when its highest point stays under that horizon in every
+/// direction it spans. Chunks only raise the horizon by their lowest height, the only
+/// height they are sure to reach across their whole width, so nothing visible is culled.
+pub fn occluded_chunks(chunkbase: &Chunkbase, eye: Vec3, area: impl IntoIterator<Item = (i32, i32)>) -> HashSet<(i32, i32)> {
+    let mut extents: Vec<Extent> = area.into_iter()
+        .filter_map(|coordinates| extent(chunkbase, eye, coordinates))
+        .collect();
+    extents.sort_by(|a, b| a.near.total_cmp(&b.near));
+
+    //Occluders only count once the eye is past them, so they are applied by far distance
+    let mut by_far: Vec<usize> = (0..extents.len()).collect();
+    by_far.sort_by(|&a, &b| extents[a].far.total_cmp(&extents[b].far));
+
+    let mut horizon = [f32::NEG_INFINITY; HORIZON_BINS];
+    let mut applied = 0;
+    let mut occluded = HashSet::new();
+
+    for chunk in &extents {
+        while applied < by_far.len() && extents[by_far[applied]].far <= chunk.near {
+            let occluder = &extents[by_far[applied]];
+            let wall = lowest_slope(occluder.height_range.0 - eye.y, occluder.near, occluder.far);
+            //Only directions the chunk covers from edge to edge
+            for bin in bins(occluder.first_bin, occluder.last_bin).skip(1) {
+                if bin == occluder.last_bin {
+                    break;
+                }
+                horizon[bin] = horizon[bin].max(wall);
+            }
+            applied += 1;
+        }
+
+        let top = highest_slope(chunk.height_range.1 - eye.y, chunk.near, chunk.far);
+        if bins(chunk.first_bin, chunk.last_bin).all(|bin| horizon[bin] > top) {
+            occluded.insert(chunk.coordinates);
+        }
+    }
+    occluded
+}
+
+/// `None` for chunks that aren't loaded or that the eye is above, those are never hidden.
+fn extent(chunkbase: &Chunkbase, eye: Vec3, coordinates: (i32, i32)) -> Option<Extent> {
+    let chunk = chunkbase.get_chunk(&coordinates)?;
+    let min = Vec2::new(chunk.transform.translation.x, chunk.transform.translation.z);
+    let max = min + Vec2::new(CHUNK_WIDTH as f32, CHUNK_HEIGHT as f32);
+    let eye_xz = eye.xz();
+
+    let near = eye_xz.distance(eye_xz.clamp(min, max));
+    if near <= 0.0 {
+        return None;
+    }
+
+    let corners = [min, Vec2::new(max.x, min.y), Vec2::new(min.x, max.y), max];
+    let far = corners.iter().map(|corner| eye_xz.distance(*corner)).fold(0.0, f32::max);
+
+    //The eye is outside the chunk, so its corners span less than half a turn around the middle
+    let middle = ((min + max) / 2.0 - eye_xz).to_angle();
+    let (low, high) = corners.iter()
+        .map(|corner| wrap_angle((*corner - eye_xz).to_angle() - middle))
+        .fold((f32::MAX, f32::MIN), |(low, high), angle| (low.min(angle), high.max(angle)));
+
+    Some(Extent {
+        coordinates,
+        near,
+        far,
+        height_range: chunk.height_range,
+        first_bin: bin_of(middle + low),
+        last_bin: bin_of(middle + high),
+    })
+}
+
+/// Bins from `first` to `last` inclusive, wrapping past the last bin.
+fn bins(first: usize, last: usize) -> impl Iterator<Item = usize> {
+    let count = (last + HORIZON_BINS - first) % HORIZON_BINS + 1;
+    (0..count).map(move |offset| (first + offset) % HORIZON_BINS)
+}
+
+fn bin_of(angle: f32) -> usize {
+    ((wrap_angle(angle) + PI) / TAU * HORIZON_BINS as f32) as usize % HORIZON_BINS
+}
+
+/// Into `(-PI, PI]`.
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(TAU) - PI;
+    if wrapped == -PI { PI } else { wrapped }
+}
+
+/// Steepest slope from the eye to a point `rise` above it between `near` and `far` away.
+fn highest_slope(rise: f32, near: f32, far: f32) -> f32 {
+    if rise >= 0.0 { rise / near } else { rise / far }
+}
+
+/// Shallowest slope from the eye to a point `rise` above it between `near` and `far` away.
+fn lowest_slope(rise: f32, near: f32, far: f32) -> f32 {
+    if rise >= 0.0 { rise / far } else { rise / near }
+}
+
+/// Follows the camera and works out which chunks in the render radius the terrain hides.
+pub fn update_chunk_view(
+    chunkbase: Res<Chunkbase>,
+    render_distance: Res<RenderDistance>,
+    player_query: Query<&Player>,
+    camera_query: Query<(&Projection, &GlobalTransform), With<CameraController>>,
+    mut view: ResMut<ChunkView>,
+) {
+    let (Ok(player), Ok((projection, camera))) = (player_query.single(), camera_query.single()) else { return };
+
+    view.eye = camera.translation();
+    view.forward = camera.forward().xz().normalize_or(Vec2::X);
+    view.half_fov = match projection {
+        Projection::Perspective(perspective) => ((perspective.fov / 2.0).tan() * perspective.aspect_ratio).atan(),
+        _ => PI,
+    };
+
+    let (cx, cy) = player.current_chunk.0;
+    let area = get_circle_area(cx, cy, render_distance.0 as i32, 0).into_iter().map(|(coordinates, _)| coordinates);
+    view.occluded = occluded_chunks(&chunkbase, view.eye, area);
+}
+
+/// Moves what `ChunkView` found behind the terrain onto `OCCLUDED_LAYER` and back once it
+/// comes into view. Only looks at everything again when the occluded chunks change.
+pub fn hide_occluded(
+    view: Res<ChunkView>,
+    mut seen: Local<HashSet<(i32, i32)>>,
+    occludable_query: Query<(Entity, Ref<Occludable>, Has<RenderLayers>)>,
+    mut commands: Commands,
+) {
+    let changed = view.occluded != *seen;
+    if changed {
+        seen.clone_from(&view.occluded);
+    }
+
+    for (entity, occludable, hidden) in &occludable_query {
+        if !changed && !occludable.is_added() {
+            continue;
+        }
+
+        let occluded = !occludable.0.is_empty() && occludable.0.iter().all(|coordinates| view.is_occluded(*coordinates));
+        match (occluded, hidden) {
+            (true, false) => { commands.entity(entity).insert(RenderLayers::layer(OCCLUDED_LAYER)); }
+            (false, true) => { commands.entity(entity).remove::<RenderLayers>(); }
+            _ => {}
+        }
+    }
+}
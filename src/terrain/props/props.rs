@@ -1,32 +1,429 @@
-use bevy::prelude::*;
+use std::{collections::HashMap, f32::consts::TAU, sync::Arc};
+
+use bevy::{ecs::query::QueryItem, prelude::*};
 use bevy_rapier3d::{prelude::{Collider, RigidBody}};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{noise::poisson_disc::PoissonDisc, player::camera_controller::CameraController, terrain::{biomes::Biome, chunks::{Chunk, Chunkbase, RenderedChunks, CHUNK_HEIGHT, CHUNK_WIDTH}, grid::{ColliderReach, StreamingBudget}, occlusion::{ChunkView, Occludable}, props::trees::tree::{leaf_atlas, Tree, TreeSpecies}, terrain_config::TerrainGenConfig}};
+
+/// Closest two props stand, in metres.
+pub const PROP_SPACING: f32 = 10.0;
+
+/// Candidates `PoissonDisc` tries around a point before giving up on it.
+const PROP_TRIES: usize = 10;
+
+/// Mixed into the world seed so props don't line up with anything else seeded from it.
+const PROP_SEED: u64 = 0x7a3c_91d5;
 
-use crate::noise::{perlin_cpu::PerlinCPU, poisson_disc::PoissonDisc};
+/// Trees grown of each species, every tree prop is one of them turned a random way.
+const TREE_VARIANTS: usize = 4;
+
+/// Texels across the impostor of a tree.
+const IMPOSTOR_RESOLUTION: u32 = 64;
+
+/// How far the camera moves around an impostor, in radians, before it is turned to face it again.
+const IMPOSTOR_TURN: f32 = 0.05;
+
+/// How far the camera moves, in metres, before `update_tree_lods` looks at every tree again.
+/// Under `IMPOSTOR_TURN` at the impostor distance, so impostors keep facing the camera.
+const TREE_LOD_MOVE: f32 = 2.0;
 
 pub struct PropPlugin;
 
 impl Plugin for PropPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, load_props);
-    }
-}
-
-fn load_props(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>,  mut meshes: ResMut<Assets<Mesh>>) {
-    let perlin = PerlinCPU::new(1, 0.001, 4, 2.0, 0.5);
-    let mut poisson = PoissonDisc::new(10., Vec2::new(128., 128.), 10);
-    let points = poisson.generate_points();
-    let brown = materials.add(StandardMaterial { base_color: Color::srgb_u8(165, 42, 42), perceptual_roughness: 0.5, ..default() });
-
-    for point in points {
-        let x = point.x + 2048.0;
-        let z = point.y + 2048.0;
-        let y = perlin.from_fractal(x, z) + 40.0;
-        commands.spawn((
-            Mesh3d(meshes.add(Mesh::from(Cylinder::new(0.25, 20.0)))),
-            MeshMaterial3d(brown.clone()),
-            Collider::cylinder(10.0, 0.25),
-            RigidBody::Fixed,
-            Transform::from_xyz(x, y, z),
-        ));
+        app
+            .init_resource::<Props>()
+            .init_resource::<TreeLodSettings>()
+            //Chained so a rebuilt `Props` is in place before anything is spawned from it
+            .add_systems(Update, ((reset_props.run_if(resource_changed::<TerrainGenConfig>), spawn_props).chain(), update_tree_lods));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PropKind {
+    Tree(TreeSpecies),
+    Cactus,
+    Boulder,
+}
+
+/// What a biome scatters and the fraction of the Poisson points it keeps.
+fn biome_props(biome: Biome) -> (PropKind, f32) {
+    match biome {
+        Biome::Forest => (PropKind::Tree(TreeSpecies::Pine), 1.0),
+        Biome::Grassland => (PropKind::Tree(TreeSpecies::Oak), 0.2),
+        Biome::Desert => (PropKind::Cactus, 0.3),
+        Biome::Tundra => (PropKind::Boulder, 0.2),
+        Biome::Rock => (PropKind::Boulder, 0.4),
+    }
+}
+
+/// Camera distances past which trees switch to their simpler mesh and then to their impostor.
+#[derive(Resource, Clone)]
+pub struct TreeLodSettings {
+    pub simplified: f32,
+    pub impostor: f32,
+}
+
+impl Default for TreeLodSettings {
+    fn default() -> Self {
+        TreeLodSettings { simplified: 60.0, impostor: 160.0 }
+    }
+}
+
+/// One grown tree, shared by every prop that picks it.
+struct TreeVariant {
+    /// The meshes of `Tree::mesh` LOD 0 and 1, then `Tree::impostor_mesh`.
+    lods: [Handle<Mesh>; 3],
+    impostor: Handle<StandardMaterial>,
+    collider: Collider,
+}
+
+/// The visible part of a tree prop, a child of the entity holding its collider.
+#[derive(Component)]
+struct TreeLod {
+    species: TreeSpecies,
+    variant: usize,
+    lod: usize,
+    /// Turn of the tree prop, undone to face the impostor to the camera.
+    yaw: f32,
+}
+
+/// The points of a `chunk_disc`, shared by every chunk thinned against it.
+type Disc = Arc<Vec<(Vec2, f32)>>;
+
+/// The props of a rendered chunk.
+struct SpawnedProps {
+    /// Chunk and lake revisions they were grounded on.
+    revision: (u32, u32),
+    /// Each prop with the collider it holds while the chunk is in `ColliderReach`.
+    entities: Vec<(Entity, Collider)>,
+    solid: bool,
+}
+
+/// The props of every rendered chunk, laid out by `scatter` and spawned and despawned
+/// along with the chunk entities in `RenderedChunks`.
+#[derive(Resource)]
+pub struct Props {
+    seed: u64,
+    sea_level: f32,
+    green: Handle<StandardMaterial>,
+    grey: Handle<StandardMaterial>,
+    /// Bark and leaves of every tree mesh, textured with `leaf_atlas`.
+    tree_material: Handle<StandardMaterial>,
+    trees: HashMap<TreeSpecies, Vec<TreeVariant>>,
+    cactus: Handle<Mesh>,
+    boulder: Handle<Mesh>,
+    spawned: HashMap<(i32, i32), SpawnedProps>,
+    /// `chunk_disc` of the rendered chunks and their neighbours, each is needed by up to five.
+    discs: HashMap<(i32, i32), Disc>,
+}
+
+impl FromWorld for Props {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.get_resource::<TerrainGenConfig>().cloned().unwrap_or_default();
+
+        let seed = config.seed ^ PROP_SEED;
+        let grown: Vec<(TreeSpecies, Vec<Tree>)> = [TreeSpecies::Oak, TreeSpecies::Pine].into_iter()
+            .map(|species| (species, (0..TREE_VARIANTS).map(|variant| Tree::grow(seed ^ ((species as u64) << 32 | variant as u64), species.species())).collect()))
+            .collect();
+
+        let mut images = world.resource_mut::<Assets<Image>>();
+        let atlas = images.add(leaf_atlas());
+        let impostor_images: Vec<Vec<Handle<Image>>> = grown.iter()
+            .map(|(_, trees)| trees.iter().map(|tree| images.add(tree.impostor_image(IMPOSTOR_RESOLUTION))).collect())
+            .collect();
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        //Leaf cards are seen from both sides
+        let tree_material = materials.add(StandardMaterial {
+            base_color_texture: Some(atlas),
+            alpha_mode: AlphaMode::Mask(0.5),
+            perceptual_roughness: 0.8,
+            double_sided: true,
+            cull_mode: None,
+            ..default()
+        });
+        let impostor_materials: Vec<Vec<Handle<StandardMaterial>>> = impostor_images.into_iter()
+            .map(|images| images.into_iter().map(|image| materials.add(StandardMaterial {
+                base_color_texture: Some(image),
+                alpha_mode: AlphaMode::Mask(0.5),
+                perceptual_roughness: 0.8,
+                ..default()
+            })).collect())
+            .collect();
+        let green = materials.add(StandardMaterial { base_color: Color::srgb_u8(60, 120, 50), perceptual_roughness: 0.7, ..default() });
+        let grey = materials.add(StandardMaterial { base_color: Color::srgb_u8(110, 110, 105), perceptual_roughness: 0.9, ..default() });
+
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let trees = grown.into_iter().zip(impostor_materials).map(|((species, trees), impostors)| {
+            let variants = trees.iter().zip(impostors).map(|(tree, impostor)| TreeVariant {
+                lods: [meshes.add(tree.mesh(0)), meshes.add(tree.mesh(1)), meshes.add(tree.impostor_mesh(IMPOSTOR_RESOLUTION))],
+                impostor,
+                collider: tree.collider(),
+            }).collect();
+            (species, variants)
+        }).collect();
+        let cactus = meshes.add(Mesh::from(Cylinder::new(0.3, 4.0)));
+        let boulder = meshes.add(Mesh::from(Sphere::new(1.5)));
+
+        Props {
+            seed,
+            sea_level: config.sea_level,
+            green,
+            grey,
+            tree_material,
+            trees,
+            cactus,
+            boulder,
+            spawned: HashMap::new(),
+            discs: HashMap::new(),
+        }
+    }
+}
+
+impl Props {
+    /// The prop standing at world position `point` of `chunk`, where on the ground, and what
+    /// is left of `roll` in `[0, 1)` to pick its look with. `None` where the biome thins it
+    /// out by `roll` or the ground is under water.
+    fn place(&self, chunkbase: &Chunkbase, chunk: &Chunk, point: Vec2, roll: f32) -> Option<(PropKind, Vec3, f32)> {
+        let ground = chunkbase.height_at(point.x, point.y)?;
+        let local = point - chunk.transform.translation.xz();
+        let index = local.y.round() as usize * (CHUNK_WIDTH + 1) + local.x.round() as usize;
+        if ground <= self.sea_level || chunk.lakes.iter().any(|lake| lake.contains(index)) {
+            return None;
+        }
+
+        let (kind, density) = biome_props(chunk.biomes[index].dominant());
+        if roll >= density {
+            return None;
+        }
+        Some((kind, Vec3::new(point.x, ground, point.y), roll / density))
+    }
+
+    /// Spawns `kind` on the ground at `foot` in chunk `coordinates`, along with the collider
+    /// it takes in reach of the player. Trees are one of the variants of their species, picked
+    /// and turned by `pick`.
+    fn spawn(&self, commands: &mut Commands, coordinates: (i32, i32), kind: PropKind, foot: Vec3, pick: f32) -> (Entity, Collider) {
+        let (mesh, material, collider, y) = match kind {
+            PropKind::Tree(species) => {
+                let pick = pick * TREE_VARIANTS as f32;
+                let (variant, yaw) = ((pick as usize).min(TREE_VARIANTS - 1), pick.fract() * TAU);
+                let tree = &self.trees[&species][variant];
+
+                //Sunk a little so the trunk doesn't float on slopes. Most chunks stream in far
+                //away, so trees start out as impostors until `update_tree_lods` sees them.
+                let entity = commands.spawn((
+                    Transform::from_xyz(foot.x, foot.y - 0.3, foot.z).with_rotation(Quat::from_rotation_y(yaw)),
+                    Visibility::default(),
+                )).with_child((
+                    Mesh3d(tree.lods[2].clone()),
+                    MeshMaterial3d(tree.impostor.clone()),
+                    TreeLod { species, variant, lod: 2, yaw },
+                    Occludable(vec![coordinates]),
+                )).id();
+                return (entity, tree.collider.clone());
+            }
+            PropKind::Cactus => (self.cactus.clone(), self.green.clone(), Collider::cylinder(2.0, 0.3), foot.y + 2.0),
+            //Sunk a little so it doesn't sit on a single point on slopes
+            PropKind::Boulder => (self.boulder.clone(), self.grey.clone(), Collider::ball(1.5), foot.y + 0.5),
+        };
+        let entity = commands.spawn((
+            Mesh3d(mesh),
+            MeshMaterial3d(material),
+            Transform::from_xyz(foot.x, y, foot.z),
+            Occludable(vec![coordinates]),
+        )).id();
+        (entity, collider)
+    }
+}
+
+/// Prop positions in chunk `coordinates`, in world space, each with a roll in `[0, 1)` the
+/// biome density thins them by. Depends on nothing but `seed` and the coordinates, so a chunk
+/// gets the same props every time it streams in. Each chunk lays out its own Poisson disc and
+/// drops the points too close to a point of a neighbour that outranks it, so props on either
+/// side of a border stay `PROP_SPACING` apart without the neighbours being loaded.
+pub fn scatter(seed: u64, coordinates: (i32, i32)) -> Vec<(Vec2, f32)> {
+    thin(coordinates, |neighbour| Arc::new(chunk_disc(seed, neighbour)))
+}
+
+/// `scatter` with the `chunk_disc` of each chunk coming from `disc`, e.g. a cache.
+fn thin((x, y): (i32, i32), mut disc: impl FnMut((i32, i32)) -> Disc) -> Vec<(Vec2, f32)> {
+    let outranking: Vec<Disc> = (-1..=1)
+        .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(|neighbour| *neighbour > (x, y))
+        .map(&mut disc)
+        .collect();
+
+    disc((x, y)).iter()
+        .filter(|(point, _)| outranking.iter().flat_map(|other| other.iter()).all(|(other, _)| point.distance_squared(*other) >= PROP_SPACING * PROP_SPACING))
+        .copied()
+        .collect()
+}
+
+/// The Poisson points of chunk `(x, y)` in world space, each with its roll.
+fn chunk_disc(seed: u64, (x, y): (i32, i32)) -> Vec<(Vec2, f32)> {
+    let coordinates = ((x as u32 as u64) << 32) | y as u32 as u64;
+    let chunk_seed = seed ^ coordinates.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+
+    let origin = Vec2::new((x * CHUNK_WIDTH as i32) as f32, (y * CHUNK_HEIGHT as i32) as f32);
+    let mut poisson = PoissonDisc::new(PROP_SPACING, Vec2::new(CHUNK_WIDTH as f32, CHUNK_HEIGHT as f32), PROP_TRIES).with_seed(chunk_seed);
+    //Rolled for every point first so dropping one doesn't shift the rolls of the rest
+    let mut rolls = StdRng::seed_from_u64(chunk_seed.rotate_left(32));
+    poisson.generate_points().iter().map(|point| (origin + *point, rolls.random::<f32>())).collect()
+}
+
+/// Despawns every prop when the terrain config changes what they depend on. A new seed grows
+/// new trees and lays out new discs, so `Props` is built again, a new sea level only needs
+/// them respawned.
+fn reset_props(mut commands: Commands, mut props: ResMut<Props>, config: Res<TerrainGenConfig>) {
+    let seed = config.seed ^ PROP_SEED;
+    if props.seed == seed && props.sea_level == config.sea_level {
+        return;
+    }
+
+    for (entity, _) in props.spawned.drain().flat_map(|(_, spawned)| spawned.entities) {
+        commands.entity(entity).despawn();
+    }
+    props.sea_level = config.sea_level;
+    if props.seed != seed {
+        commands.queue(|world: &mut World| {
+            let props = Props::from_world(world);
+            world.insert_resource(props);
+        });
+    }
+}
+
+/// Scatters props over the rendered chunks `ChunkView` puts first, `StreamingBudget::props`
+/// a frame, again whenever an edit changes the chunk, and despawns them with the chunk
+/// entity. Props only hold colliders while their chunk is in `ColliderReach`.
+fn spawn_props(
+    mut commands: Commands,
+    mut props: ResMut<Props>,
+    chunkbase: Res<Chunkbase>,
+    rendered_chunks: Res<RenderedChunks>,
+    (budget, view, reach): (Res<StreamingBudget>, Res<ChunkView>, Res<ColliderReach>),
+) {
+    let props = &mut *props;
+    let spawned = props.spawned.len();
+    props.spawned.retain(|coordinates, spawned| {
+        let keep = rendered_chunks.0.contains_key(coordinates);
+        if !keep {
+            for (entity, _) in spawned.entities.drain(..) {
+                commands.entity(entity).despawn();
+            }
+        }
+        keep
+    });
+    if props.spawned.len() < spawned {
+        props.discs.retain(|(x, y), _| (-1..=1).any(|dy| (-1..=1).any(|dx| rendered_chunks.0.contains_key(&(x + dx, y + dy)))));
+    }
+
+    if reach.is_changed() {
+        for (coordinates, spawned) in &mut props.spawned {
+            let solid = reach.contains(coordinates);
+            if spawned.solid == solid {
+                continue;
+            }
+            spawned.solid = solid;
+            for (entity, collider) in &spawned.entities {
+                match solid {
+                    true => { commands.entity(*entity).insert((collider.clone(), RigidBody::Fixed)); }
+                    false => { commands.entity(*entity).remove::<(Collider, RigidBody)>(); }
+                }
+            }
+        }
+    }
+
+    let mut ready: Vec<(i32, i32)> = rendered_chunks.0.keys()
+        .filter(|coordinates| chunkbase.get_chunk(coordinates)
+            .is_some_and(|chunk| props.spawned.get(coordinates).is_none_or(|spawned| spawned.revision != (chunk.revision, chunk.lake_revision))))
+        .copied()
+        .collect();
+    ready.sort_by(|a, b| view.priority(*a).total_cmp(&view.priority(*b)));
+
+    for coordinates in ready.into_iter().take(budget.props) {
+        let Some(chunk) = chunkbase.get_chunk(&coordinates) else { continue };
+        let (seed, discs) = (props.seed, &mut props.discs);
+        let scattered = thin(coordinates, |neighbour| discs.entry(neighbour).or_insert_with(|| Arc::new(chunk_disc(seed, neighbour))).clone());
+
+        let solid = reach.contains(&coordinates);
+        let entities = scattered.into_iter()
+            .filter_map(|(point, roll)| props.place(&chunkbase, chunk, point, roll))
+            .map(|(kind, foot, pick)| {
+                let (entity, collider) = props.spawn(&mut commands, coordinates, kind, foot, pick);
+                if solid {
+                    commands.entity(entity).insert((collider.clone(), RigidBody::Fixed));
+                }
+                (entity, collider)
+            })
+            .collect();
+
+        let spawned = SpawnedProps { revision: (chunk.revision, chunk.lake_revision), entities, solid };
+        for (old, _) in props.spawned.insert(coordinates, spawned).into_iter().flat_map(|spawned| spawned.entities) {
+            commands.entity(old).despawn();
+        }
+    }
+}
+
+/// What `update_tree_lods` reads and changes of a tree.
+type TreeLodData = (&'static mut TreeLod, &'static GlobalTransform, &'static mut Transform, &'static mut Mesh3d, &'static mut MeshMaterial3d<StandardMaterial>);
+
+/// Every tree, then only the trees spawned since `update_tree_lods` last ran.
+type TreeQueries<'w, 's> = ParamSet<'w, 's, (Query<'static, 'static, TreeLodData>, Query<'static, 'static, TreeLodData, Added<TreeLod>>)>;
+
+/// Gives every tree the mesh for its distance to the camera and turns the impostors to face
+/// it. Only new trees are looked at until the camera has moved `TREE_LOD_MOVE`.
+fn update_tree_lods(
+    props: Res<Props>,
+    settings: Res<TreeLodSettings>,
+    camera_query: Query<&GlobalTransform, With<CameraController>>,
+    mut walked_from: Local<Option<Vec3>>,
+    mut tree_queries: TreeQueries,
+) {
+    let Ok(camera) = camera_query.single() else { return };
+    let eye = camera.translation();
+
+    if walked_from.is_none_or(|from| from.distance(eye) > TREE_LOD_MOVE) {
+        *walked_from = Some(eye);
+        for tree in &mut tree_queries.p0() {
+            fit_tree_lod(&props, &settings, eye, tree);
+        }
+    } else {
+        for tree in &mut tree_queries.p1() {
+            fit_tree_lod(&props, &settings, eye, tree);
+        }
+    }
+}
+
+fn fit_tree_lod(
+    props: &Props,
+    settings: &TreeLodSettings,
+    eye: Vec3,
+    (mut tree_lod, global_transform, mut transform, mut mesh, mut material): QueryItem<TreeLodData>,
+) {
+    let offset = eye - global_transform.translation();
+    let lod = match offset.length() {
+        distance if distance > settings.impostor => 2,
+        distance if distance > settings.simplified => 1,
+        _ => 0,
+    };
+
+    if lod != tree_lod.lod {
+        let tree = &props.trees[&tree_lod.species][tree_lod.variant];
+        tree_lod.lod = lod;
+        mesh.0 = tree.lods[lod].clone();
+        material.0 = if lod == 2 { tree.impostor.clone() } else { props.tree_material.clone() };
+        if lod != 2 {
+            transform.rotation = Quat::IDENTITY;
+        }
+    }
+
+    if lod == 2 {
+        //Turned in the space of the tree prop, which has its own yaw
+        let facing = Quat::from_rotation_y(offset.x.atan2(offset.z) - tree_lod.yaw);
+        if transform.rotation.angle_between(facing) > IMPOSTOR_TURN {
+            transform.rotation = facing;
+        }
     }
 }
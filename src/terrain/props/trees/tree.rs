@@ -1,4 +1,398 @@
+use std::f32::consts::TAU;
+
+use bevy::{asset::RenderAssetUsages, prelude::*, render::{mesh::{Indices, PrimitiveTopology}, render_resource::{Extent3d, TextureDimension, TextureFormat}}};
+use bevy_rapier3d::prelude::Collider;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Turn between successive forks around their parent, spreads them without ever lining up.
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+/// Sides of the branch tubes in each mesh LOD.
+const BRANCH_SIDES: [usize; 2] = [6, 4];
+
+/// Texels a side of the leaf half of `leaf_atlas`, the bark half is as big again to its left.
+const LEAF_TEXELS: u32 = 16;
+
+/// Where bark samples `leaf_atlas`, the middle of its opaque half.
+const BARK_UV: [f32; 2] = [0.25, 0.5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TreeSpecies {
+    Oak,
+    Pine,
+}
+
+/// The shape of a species, expanded by `Tree::grow`. Trunk segments fork into limbs,
+/// limbs into smaller branches `depth` times, and the last ones carry the leaves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Species {
+    /// Length of the trunk, in metres.
+    pub height: f32,
+    pub trunk_radius: f32,
+    /// Segments the trunk bends between, each above `crown_start` forks limbs off its top.
+    pub trunk_segments: usize,
+    /// Fraction of the trunk without limbs.
+    pub crown_start: f32,
+    /// Times a limb forks again, 0 for leaves straight on the limbs.
+    pub depth: usize,
+    /// Branches each fork point grows.
+    pub forks: usize,
+    /// Angle between a branch and its parent, in radians.
+    pub fork_angle: f32,
+    /// Length of a branch relative to its parent, the trunk for limbs.
+    pub length_ratio: f32,
+    /// Radius a branch tapers to and its children start at, relative to where it starts.
+    pub radius_ratio: f32,
+    /// How much branches turn up, or droop when negative.
+    pub gravitropism: f32,
+    /// How much shorter limbs get towards the top, 0 for a round crown and 1 for a cone.
+    pub taper: f32,
+    /// Random bend of every segment and fork, in radians.
+    pub wobble: f32,
+    /// Leaf cards on each of the last branches.
+    pub leaves: usize,
+    /// Side of a leaf card, in metres.
+    pub leaf_size: f32,
+    pub bark: Color,
+    pub leaf: Color,
+}
+
+impl TreeSpecies {
+    pub fn species(self) -> Species {
+        match self {
+            TreeSpecies::Oak => Species {
+                height: 9.0,
+                trunk_radius: 0.35,
+                trunk_segments: 4,
+                crown_start: 0.4,
+                depth: 1,
+                forks: 3,
+                fork_angle: 0.8,
+                length_ratio: 0.55,
+                radius_ratio: 0.6,
+                gravitropism: 0.15,
+                taper: 0.2,
+                wobble: 0.15,
+                leaves: 12,
+                leaf_size: 1.8,
+                bark: Color::srgb(0.3, 0.22, 0.15),
+                leaf: Color::srgb(0.27, 0.45, 0.16),
+            },
+            TreeSpecies::Pine => Species {
+                height: 18.0,
+                trunk_radius: 0.3,
+                trunk_segments: 9,
+                crown_start: 0.25,
+                depth: 0,
+                forks: 5,
+                fork_angle: 1.35,
+                length_ratio: 0.3,
+                radius_ratio: 0.4,
+                gravitropism: -0.15,
+                taper: 0.95,
+                wobble: 0.05,
+                leaves: 6,
+                leaf_size: 1.3,
+                bark: Color::srgb(0.35, 0.24, 0.18),
+                leaf: Color::srgb(0.12, 0.3, 0.16),
+            },
+        }
+    }
+}
+
+/// One straight, tapering piece of the trunk or a branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub start_radius: f32,
+    pub end_radius: f32,
+    /// 0 on the trunk, one more with every fork.
+    pub level: usize,
+}
+
+/// A square card of leaves, flat in its local XZ plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Leaf {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub size: f32,
+    /// Index into `Tree::segments` of the branch it hangs from.
+    pub branch: usize,
+}
+
+/// A tree grown from a seed, in its own space with the foot of the trunk at the origin.
+#[derive(Debug, Clone)]
 pub struct Tree {
-    _seed: usize,
-    
+    pub seed: u64,
+    pub species: Species,
+    pub segments: Vec<Segment>,
+    pub leaves: Vec<Leaf>,
+}
+
+impl Tree {
+    /// Grows `species` from `seed`, the same seed always gives the same tree.
+    pub fn grow(seed: u64, species: Species) -> Tree {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut tree = Tree { seed, species, segments: Vec::new(), leaves: Vec::new() };
+        let mut phase = rng.random_range(0.0..TAU);
+
+        let length = species.height / species.trunk_segments as f32;
+        let (mut start, mut direction) = (Vec3::ZERO, Vec3::Y);
+        for i in 0..species.trunk_segments {
+            let (t0, t1) = (i as f32 / species.trunk_segments as f32, (i + 1) as f32 / species.trunk_segments as f32);
+            //Pulled back upright every segment so the wobble never adds up to a lean
+            direction = bend(direction.lerp(Vec3::Y, 0.5), species.wobble, &mut rng);
+            let end = start + direction * length;
+            let radius = |t: f32| species.trunk_radius * (1.0 + (species.radius_ratio - 1.0) * t);
+            tree.segments.push(Segment { start, end, start_radius: radius(t0), end_radius: radius(t1), level: 0 });
+
+            if t1 > species.crown_start {
+                let limb_length = species.height * species.length_ratio * (1.0 - species.taper * t1).max(0.1);
+                for _ in 0..species.forks {
+                    phase += GOLDEN_ANGLE;
+                    let limb = bend(fork(direction, phase, species.fork_angle, species.gravitropism), species.wobble, &mut rng);
+                    tree.branch(end, limb * limb_length, radius(t1) * species.radius_ratio, 1, &mut phase, &mut rng);
+                }
+            }
+            start = end;
+        }
+        tree
+    }
+
+    /// A branch from `start` to `start + reach`, its forks and its leaves.
+    fn branch(&mut self, start: Vec3, reach: Vec3, radius: f32, level: usize, phase: &mut f32, rng: &mut StdRng) {
+        let species = self.species;
+        let end = start + reach;
+        let direction = reach.normalize_or(Vec3::Y);
+        let end_radius = radius * species.radius_ratio;
+        let index = self.segments.len();
+        self.segments.push(Segment { start, end, start_radius: radius, end_radius, level });
+
+        if level > species.depth {
+            for _ in 0..species.leaves {
+                let along = rng.random_range(0.3..1.0);
+                let spread = Vec3::new(rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0));
+                self.leaves.push(Leaf {
+                    position: start.lerp(end, along) + spread * species.leaf_size * 0.3,
+                    rotation: Quat::from_euler(EulerRot::YXZ, rng.random_range(0.0..TAU), rng.random_range(-1.0..1.0), 0.0),
+                    size: species.leaf_size * rng.random_range(0.8..1.2),
+                    branch: index,
+                });
+            }
+            return;
+        }
+
+        //Forks from the outer half, the last one continuing the branch from its tip
+        for k in 0..species.forks {
+            let along = 0.5 + 0.5 * (k + 1) as f32 / species.forks as f32;
+            *phase += GOLDEN_ANGLE;
+            let child = bend(fork(direction, *phase, species.fork_angle, species.gravitropism), species.wobble, rng);
+            let child_radius = radius.lerp(end_radius, along) * species.radius_ratio;
+            self.branch(start.lerp(end, along), child * reach.length() * species.length_ratio, child_radius, level + 1, phase, rng);
+        }
+    }
+
+    /// LOD 0 is every branch and leaf. LOD 1 drops the twigs past the limbs, pulling their
+    /// leaves in to where the twig left the limb, and every other leaf, making the rest
+    /// bigger to cover for them. Bark and leaves share one mesh, coloured per
+    /// vertex, for a double sided material with `leaf_atlas` as its alpha masked texture.
+    pub fn mesh(&self, lod: usize) -> Mesh {
+        let lod = lod.min(1);
+        let sides = BRANCH_SIDES[lod];
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut uvs: Vec<[f32; 2]> = Vec::new();
+        let mut colours: Vec<[f32; 4]> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+
+        let bark = self.species.bark.to_linear().to_f32_array();
+        for segment in &self.segments {
+            if lod == 1 && segment.level > 1 {
+                continue;
+            }
+
+            let axis = (segment.end - segment.start).normalize_or(Vec3::Y);
+            let (u, v) = (axis.any_orthonormal_vector(), axis.cross(axis.any_orthonormal_vector()));
+            let first = positions.len() as u32;
+            for side in 0..sides {
+                let angle = side as f32 / sides as f32 * TAU;
+                let normal = u * angle.cos() + v * angle.sin();
+                for (centre, radius) in [(segment.start, segment.start_radius), (segment.end, segment.end_radius)] {
+                    positions.push((centre + normal * radius).to_array());
+                    normals.push(normal.to_array());
+                    uvs.push(BARK_UV);
+                    colours.push(bark);
+                }
+            }
+            for side in 0..sides as u32 {
+                let next = (side + 1) % sides as u32;
+                let (start, end, next_start, next_end) = (first + side * 2, first + side * 2 + 1, first + next * 2, first + next * 2 + 1);
+                indices.extend([start, next_start, end, next_start, next_end, end]);
+            }
+        }
+
+        let height = self.species.height.max(f32::EPSILON);
+        let (leaf_min, leaf_max) = leaf_uv_rect();
+        for leaf in self.leaves.iter().step_by(lod + 1) {
+            let half = leaf.size * if lod == 1 { 0.7 } else { 0.5 };
+            let branch = &self.segments[leaf.branch];
+            let position = match lod == 1 && branch.level > 1 {
+                true => leaf.position - (leaf.position - branch.start).project_onto(branch.end - branch.start),
+                false => leaf.position,
+            };
+            let normal = leaf.rotation * Vec3::Y;
+            //Lighter up in the sun
+            let shade = 0.8 + 0.3 * (position.y / height).clamp(0.0, 1.0);
+            let colour = (self.species.leaf.to_linear() * shade).with_alpha(1.0).to_f32_array();
+
+            let first = positions.len() as u32;
+            let corners = [(-1.0, -1.0, [leaf_min[0], leaf_min[1]]), (-1.0, 1.0, [leaf_min[0], leaf_max[1]]), (1.0, 1.0, [leaf_max[0], leaf_max[1]]), (1.0, -1.0, [leaf_max[0], leaf_min[1]])];
+            for (x, z, uv) in corners {
+                positions.push((position + leaf.rotation * Vec3::new(x * half, 0.0, z * half)).to_array());
+                normals.push(normal.to_array());
+                uvs.push(uv);
+                colours.push(colour);
+            }
+            indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colours)
+            .with_inserted_indices(Indices::U32(indices))
+    }
+
+    /// Capsules along the trunk, in the tree's own space like the meshes. Branches and leaves
+    /// are left out so the player and bullets only stop at the trunk.
+    pub fn collider(&self) -> Collider {
+        Collider::compound(self.segments.iter()
+            .filter(|segment| segment.level == 0)
+            .map(|segment| (Vec3::ZERO, Quat::IDENTITY, Collider::capsule(segment.start, segment.end, segment.start_radius)))
+            .collect())
+    }
+
+    /// Stand-in for far away trees: a quad over the silhouette of `impostor_image`, standing
+    /// on the foot of the trunk and facing +Z, to be turned towards the camera.
+    pub fn impostor_mesh(&self, resolution: u32) -> Mesh {
+        let (half_width, _) = self.bounds();
+        let (_, height) = self.impostor_size(resolution);
+        let positions = vec![[-half_width, 0.0, 0.0], [half_width, 0.0, 0.0], [half_width, height, 0.0], [-half_width, height, 0.0]];
+
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; 4])
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]])
+            .with_inserted_indices(Indices::U32(vec![0, 1, 2, 0, 2, 3]))
+    }
+
+    /// The tree seen from +Z, `resolution` texels across and transparent around it. Branches
+    /// and leaf cards are drawn as their outlines, leaves nearer the viewer lighter and on top.
+    pub fn impostor_image(&self, resolution: u32) -> Image {
+        let (half_width, _) = self.bounds();
+        let (rows, height) = self.impostor_size(resolution);
+        let texel = 2.0 * half_width / resolution as f32;
+        let mut data = vec![0u8; (resolution * rows * 4) as usize];
+
+        let mut fill = |min: Vec2, max: Vec2, colour: [u8; 4], inside: &dyn Fn(Vec2) -> bool| {
+            let columns = ((min.x + half_width) / texel).floor().max(0.0) as u32..((max.x + half_width) / texel).ceil().min(resolution as f32) as u32;
+            let lines = ((height - max.y) / texel).floor().max(0.0) as u32..((height - min.y) / texel).ceil().min(rows as f32) as u32;
+            for row in lines {
+                for column in columns.clone() {
+                    let point = Vec2::new(-half_width + (column as f32 + 0.5) * texel, height - (row as f32 + 0.5) * texel);
+                    if inside(point) {
+                        let index = ((row * resolution + column) * 4) as usize;
+                        data[index..index + 4].copy_from_slice(&colour);
+                    }
+                }
+            }
+        };
+
+        let bark = self.species.bark.to_srgba().to_u8_array();
+        for segment in &self.segments {
+            let (a, b) = (segment.start.xy(), segment.end.xy());
+            let reach = segment.start_radius.max(segment.end_radius).max(texel);
+            fill(a.min(b) - reach, a.max(b) + reach, bark, &|point| {
+                let along = ((point - a).dot(b - a) / (b - a).length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+                //Never thinner than half a texel, so twigs don't flicker out
+                let radius = segment.start_radius.lerp(segment.end_radius, along).max(texel / 2.0);
+                point.distance(a.lerp(b, along)) <= radius
+            });
+        }
+
+        let mut leaves: Vec<&Leaf> = self.leaves.iter().collect();
+        leaves.sort_by(|a, b| a.position.z.total_cmp(&b.position.z));
+        for leaf in leaves {
+            let shade = 0.7 + 0.3 * (leaf.position.z / half_width * 0.5 + 0.5).clamp(0.0, 1.0);
+            let colour = (self.species.leaf.to_srgba() * shade).with_alpha(1.0).to_u8_array();
+            let (centre, radius) = (leaf.position.xy(), leaf.size / 2.0);
+            fill(centre - radius, centre + radius, colour, &|point| point.distance(centre) <= radius);
+        }
+
+        Image::new(
+            Extent3d { width: resolution, height: rows, depth_or_array_layers: 1 },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+
+    /// Texel rows of `impostor_image` and the height they cover, in metres.
+    fn impostor_size(&self, resolution: u32) -> (u32, f32) {
+        let (half_width, height) = self.bounds();
+        let texel = 2.0 * half_width / resolution as f32;
+        let rows = ((height / texel).ceil() as u32).max(1);
+        (rows, rows as f32 * texel)
+    }
+
+    /// Furthest the tree reaches along X either side of the trunk, and its height.
+    fn bounds(&self) -> (f32, f32) {
+        let segments = self.segments.iter().flat_map(|segment| [(segment.start, segment.start_radius), (segment.end, segment.end_radius)]);
+        let leaves = self.leaves.iter().map(|leaf| (leaf.position, leaf.size / 2.0));
+        segments.chain(leaves).fold((f32::EPSILON, f32::EPSILON), |(half_width, height), (point, reach)| {
+            (half_width.max(point.x.abs() + reach), height.max(point.y + reach))
+        })
+    }
+}
+
+/// Texture shared by the bark and leaves of every tree mesh: an opaque white half the bark
+/// samples and a white leaf cluster the leaf cards are cut out of, tinted by the vertex colours.
+pub fn leaf_atlas() -> Image {
+    let size = LEAF_TEXELS as f32;
+    let blobs = [(0.5, 0.55, 0.38), (0.3, 0.35, 0.24), (0.7, 0.35, 0.24), (0.25, 0.7, 0.2), (0.75, 0.7, 0.2)];
+    let data = (0..LEAF_TEXELS * LEAF_TEXELS * 2).flat_map(|texel| {
+        let (x, y) = (texel % (LEAF_TEXELS * 2), texel / (LEAF_TEXELS * 2));
+        let point = Vec2::new((x as f32 - size + 0.5) / size, (y as f32 + 0.5) / size);
+        let opaque = x < LEAF_TEXELS || blobs.iter().any(|&(bx, by, radius)| point.distance(Vec2::new(bx, by)) <= radius);
+        [u8::MAX, u8::MAX, u8::MAX, if opaque { u8::MAX } else { 0 }]
+    }).collect();
+
+    Image::new(
+        Extent3d { width: LEAF_TEXELS * 2, height: LEAF_TEXELS, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+/// Corners of the leaf half of `leaf_atlas` in UVs, half a texel in so the bark never bleeds in.
+fn leaf_uv_rect() -> ([f32; 2], [f32; 2]) {
+    let (width, height) = ((LEAF_TEXELS * 2) as f32, LEAF_TEXELS as f32);
+    ([(LEAF_TEXELS as f32 + 0.5) / width, 0.5 / height], [(width - 0.5) / width, (height - 0.5) / height])
+}
+
+/// `angle` away from `parent`, turned `phase` around it and then up by `gravitropism`.
+fn fork(parent: Vec3, phase: f32, angle: f32, gravitropism: f32) -> Vec3 {
+    let side = Quat::from_axis_angle(parent, phase) * parent.any_orthonormal_vector();
+    let direction = parent * angle.cos() + side * angle.sin();
+    (direction + Vec3::Y * gravitropism).normalize_or(direction)
+}
+
+fn bend(direction: Vec3, wobble: f32, rng: &mut StdRng) -> Vec3 {
+    let jitter = Vec3::new(rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0));
+    (direction + jitter * wobble).normalize_or(direction)
 }
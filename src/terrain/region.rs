@@ -0,0 +1,201 @@
+use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::PathBuf, sync::{Arc, Mutex}};
+
+use crate::terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH}, terrain_config::TerrainGenConfig};
+
+pub const REGION_DIRECTORY: &str = "cache/regions";
+
+/// Chunks along each side of a region file.
+pub const REGION_SIZE: i32 = 32;
+
+/// Region files kept open at once, the least recently used one is closed past that.
+const OPEN_REGIONS: usize = 16;
+
+const MAGIC: &[u8; 4] = b"TRGN";
+const VERSION: u32 = 2;
+
+/// Heights per chunk, halo included.
+const SAMPLES: usize = (CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1);
+
+/// Heights on the border of a chunk, the ones it shares with its neighbours.
+const EDGE_SAMPLES: usize = 2 * (CHUNK_WIDTH + CHUNK_HEIGHT);
+
+//magic, version, seed, config hash
+const HEADER_SIZE: u64 = 4 + 4 + 8 + 8;
+//present flag, min and max height per chunk
+const ENTRY_SIZE: u64 = 4 + 4 + 4;
+const DATA_OFFSET: u64 = HEADER_SIZE + ENTRY_SIZE * (REGION_SIZE * REGION_SIZE) as u64;
+//u16 per height, then f32 per edge height
+const SLOT_SIZE: u64 = SAMPLES as u64 * 2 + EDGE_SAMPLES as u64 * 4;
+
+/// On-disk cache of generated heights, one file per `REGION_SIZE` x `REGION_SIZE` chunks.
+/// Heights are quantised to u16 between each chunk's min and max, a few millimetres
+/// of error for a few hundred metres of relief. The border is kept as is, so chunks
+/// still share their edges exactly. Every file carries the seed and
+/// `TerrainGenConfig::generation_hash` it was written with, and a file written under
+/// another config is wiped on first use.
+pub struct RegionCache {
+    directory: PathBuf,
+    seed: u64,
+    config_hash: u64,
+    files: Mutex<OpenRegions>,
+}
+
+/// A region file, locked on its own so chunks in different regions are read and written
+/// side by side.
+type RegionFile = Arc<Mutex<File>>;
+
+/// Open region files with the tick they were last used at.
+#[derive(Default)]
+struct OpenRegions {
+    files: HashMap<(i32, i32), (RegionFile, u64)>,
+    tick: u64,
+}
+
+impl RegionCache {
+    pub fn open(directory: impl Into<PathBuf>, config: &TerrainGenConfig) -> anyhow::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(RegionCache {
+            directory,
+            seed: config.seed,
+            config_hash: config.generation_hash(),
+            files: Mutex::new(OpenRegions::default()),
+        })
+    }
+
+    /// Cached heights of a chunk, `None` if it hasn't been stored yet.
+    pub fn load(&self, coordinates: (i32, i32)) -> anyhow::Result<Option<Vec<f32>>> {
+        self.with_region(coordinates, |file, slot| {
+            let mut entry = [0; ENTRY_SIZE as usize];
+            file.seek(SeekFrom::Start(HEADER_SIZE + ENTRY_SIZE * slot))?;
+            file.read_exact(&mut entry)?;
+
+            if u32::from_le_bytes(entry[0..4].try_into().unwrap()) == 0 {
+                return Ok(None);
+            }
+            let min = f32::from_le_bytes(entry[4..8].try_into().unwrap());
+            let max = f32::from_le_bytes(entry[8..12].try_into().unwrap());
+
+            let mut data = vec![0; SLOT_SIZE as usize];
+            file.seek(SeekFrom::Start(DATA_OFFSET + SLOT_SIZE * slot))?;
+            file.read_exact(&mut data)?;
+
+            let (quantised, edges) = data.split_at(SAMPLES * 2);
+            let mut heights: Vec<f32> = quantised.chunks_exact(2).map(|q| dequantise(u16::from_le_bytes([q[0], q[1]]), min, max)).collect();
+            for (index, bytes) in edge_indices().zip(edges.chunks_exact(4)) {
+                heights[index] = f32::from_le_bytes(bytes.try_into().unwrap());
+            }
+            Ok(Some(heights))
+        })
+    }
+
+    /// Writes a chunk's heights and returns them as they will read back, so a freshly
+    /// generated chunk is identical to the cached one on the next launch.
+    pub fn store(&self, coordinates: (i32, i32), heights: &[f32]) -> anyhow::Result<Vec<f32>> {
+        assert_eq!(heights.len(), SAMPLES, "chunk heights must include the halo");
+
+        let min = heights.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let quantised: Vec<u16> = heights.iter().map(|height| quantise(*height, min, max)).collect();
+
+        self.with_region(coordinates, |file, slot| {
+            let data: Vec<u8> = quantised.iter().flat_map(|q| q.to_le_bytes())
+                .chain(edge_indices().flat_map(|index| heights[index].to_le_bytes()))
+                .collect();
+            file.seek(SeekFrom::Start(DATA_OFFSET + SLOT_SIZE * slot))?;
+            file.write_all(&data)?;
+
+            //The entry goes last so a torn write leaves the chunk missing rather than corrupt
+            let entry: Vec<u8> = [1u32.to_le_bytes(), min.to_le_bytes(), max.to_le_bytes()].concat();
+            file.seek(SeekFrom::Start(HEADER_SIZE + ENTRY_SIZE * slot))?;
+            file.write_all(&entry)?;
+            Ok(())
+        })?;
+
+        let mut stored: Vec<f32> = quantised.iter().map(|q| dequantise(*q, min, max)).collect();
+        for index in edge_indices() {
+            stored[index] = heights[index];
+        }
+        Ok(stored)
+    }
+
+    /// Runs `f` on the file holding `coordinates` and the chunk's slot within it,
+    /// opening and validating the file on first use.
+    fn with_region<T>(&self, (x, y): (i32, i32), f: impl FnOnce(&mut File, u64) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let region = (x.div_euclid(REGION_SIZE), y.div_euclid(REGION_SIZE));
+        let slot = (y.rem_euclid(REGION_SIZE) * REGION_SIZE + x.rem_euclid(REGION_SIZE)) as u64;
+
+        let file = self.region_file(region)?;
+        let mut file = file.lock().unwrap();
+        f(&mut file, slot)
+    }
+
+    /// The open file of `region`, opening it and closing the least recently used one if
+    /// needed. A closed file still in use stays open until its last user is done.
+    fn region_file(&self, region: (i32, i32)) -> anyhow::Result<RegionFile> {
+        let mut open = self.files.lock().unwrap();
+        open.tick += 1;
+        let tick = open.tick;
+
+        if let Some((file, last_used)) = open.files.get_mut(&region) {
+            *last_used = tick;
+            return Ok(file.clone());
+        }
+
+        if open.files.len() >= OPEN_REGIONS {
+            let oldest = open.files.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(region, _)| *region);
+            if let Some(oldest) = oldest {
+                open.files.remove(&oldest);
+            }
+        }
+
+        let file = Arc::new(Mutex::new(self.open_region(region)?));
+        open.files.insert(region, (file.clone(), tick));
+        Ok(file)
+    }
+
+    fn open_region(&self, (x, y): (i32, i32)) -> anyhow::Result<File> {
+        let path = self.directory.join(format!("r.{x}.{y}.bin"));
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let mut header = [0; HEADER_SIZE as usize];
+        let valid = file.read_exact(&mut header).is_ok() && header == self.header();
+
+        if !valid {
+            file.set_len(0)?;
+            file.set_len(DATA_OFFSET)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&self.header())?;
+        }
+
+        Ok(file)
+    }
+
+    fn header(&self) -> [u8; HEADER_SIZE as usize] {
+        let mut header = [0; HEADER_SIZE as usize];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        header[8..16].copy_from_slice(&self.seed.to_le_bytes());
+        header[16..24].copy_from_slice(&self.config_hash.to_le_bytes());
+        header
+    }
+}
+
+/// Indices into a chunk's heights of its border, row by row.
+fn edge_indices() -> impl Iterator<Item = usize> {
+    let stride = CHUNK_WIDTH + 1;
+    (0..SAMPLES).filter(move |index| {
+        let (x, y) = (index % stride, index / stride);
+        x == 0 || y == 0 || x == CHUNK_WIDTH || y == CHUNK_HEIGHT
+    })
+}
+
+fn quantise(height: f32, min: f32, max: f32) -> u16 {
+    let range = (max - min).max(f32::EPSILON);
+    ((height - min) / range * u16::MAX as f32).round() as u16
+}
+
+fn dequantise(quantised: u16, min: f32, max: f32) -> f32 {
+    min + quantised as f32 / u16::MAX as f32 * (max - min)
+}
@@ -0,0 +1,67 @@
+#define_import_path terrain::splat
+
+//Layers of the texture array, in the same order as `Biome::ALL`
+const GRASSLAND: i32 = 0;
+const FOREST: i32 = 1;
+const DESERT: i32 = 2;
+const TUNDRA: i32 = 3;
+const ROCK: i32 = 4;
+
+struct SplatParams {
+    tiling: f32,
+    cliff_angle: f32,
+    cliff_blend: f32,
+    snow_height: f32,
+    snow_blend: f32,
+    beach_height: f32,
+    beach_blend: f32,
+    triplanar_sharpness: f32,
+}
+
+//Looks down on the ground, fine everywhere the terrain isn't steep
+fn planar(layers: texture_2d_array<f32>, layers_sampler: sampler, layer: i32, position: vec3<f32>, tiling: f32) -> vec3<f32> {
+    return textureSample(layers, layers_sampler, position.xz * tiling, layer).rgb;
+}
+
+//Projects along all three axes and blends by the normal so cliffs don't smear the texture
+fn triplanar(layers: texture_2d_array<f32>, layers_sampler: sampler, layer: i32, position: vec3<f32>, normal: vec3<f32>, params: SplatParams) -> vec3<f32> {
+    var blend = pow(abs(normal), vec3(params.triplanar_sharpness));
+    blend = blend / (blend.x + blend.y + blend.z);
+
+    let x = textureSample(layers, layers_sampler, position.zy * params.tiling, layer).rgb;
+    let y = textureSample(layers, layers_sampler, position.xz * params.tiling, layer).rgb;
+    let z = textureSample(layers, layers_sampler, position.xy * params.tiling, layer).rgb;
+    return x * blend.x + y * blend.y + z * blend.z;
+}
+
+//Weight of each layer from the biome weights, grassland to tundra with rock making up the
+//rest, then sand on beaches, snow up high and rock on anything steep
+fn layer_weights(params: SplatParams, position: vec3<f32>, normal: vec3<f32>, biomes: vec4<f32>) -> array<f32, 5> {
+    var weights = array<f32, 5>(biomes.x, biomes.y, biomes.z, biomes.w, max(1.0 - dot(biomes, vec4(1.0)), 0.0));
+
+    let beach = 1.0 - smoothstep(params.beach_height - params.beach_blend, params.beach_height + params.beach_blend, position.y);
+    let snow = smoothstep(params.snow_height - params.snow_blend, params.snow_height + params.snow_blend, position.y);
+    let slope = degrees(acos(clamp(normal.y, -1.0, 1.0)));
+    let cliff = smoothstep(params.cliff_angle - params.cliff_blend, params.cliff_angle + params.cliff_blend, slope);
+
+    for (var layer = 0; layer < 5; layer = layer + 1) {
+        weights[layer] = weights[layer] * (1.0 - beach) * (1.0 - snow) * (1.0 - cliff);
+    }
+    weights[DESERT] = weights[DESERT] + beach * (1.0 - snow) * (1.0 - cliff);
+    weights[TUNDRA] = weights[TUNDRA] + snow * (1.0 - cliff);
+    weights[ROCK] = weights[ROCK] + cliff;
+    return weights;
+}
+
+//Albedo of the terrain at a world position. Every layer is sampled whatever its weight,
+//texture samples need uniform control flow for their derivatives
+fn splat(params: SplatParams, layers: texture_2d_array<f32>, layers_sampler: sampler, position: vec3<f32>, normal: vec3<f32>, biomes: vec4<f32>) -> vec3<f32> {
+    let weights = layer_weights(params, position, normal, biomes);
+
+    var colour = planar(layers, layers_sampler, GRASSLAND, position, params.tiling) * weights[GRASSLAND];
+    colour = colour + planar(layers, layers_sampler, FOREST, position, params.tiling) * weights[FOREST];
+    colour = colour + planar(layers, layers_sampler, DESERT, position, params.tiling) * weights[DESERT];
+    colour = colour + planar(layers, layers_sampler, TUNDRA, position, params.tiling) * weights[TUNDRA];
+    colour = colour + triplanar(layers, layers_sampler, ROCK, position, normal, params) * weights[ROCK];
+    return colour;
+}
@@ -0,0 +1,30 @@
+#import bevy_pbr::{
+    pbr_bindings,
+    pbr_fragment::pbr_input_from_standard_material,
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#import terrain::splat::{SplatParams, splat}
+
+@group(2) @binding(100) var<uniform> params: SplatParams;
+@group(2) @binding(101) var layers: texture_2d_array<f32>;
+@group(2) @binding(102) var layers_sampler: sampler;
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    //The vertex colour carries the biome weights, not a colour
+#ifdef VERTEX_COLORS
+    let biomes = in.color;
+#else
+    let biomes = vec4(1.0, 0.0, 0.0, 0.0);
+#endif
+    let albedo = splat(params, layers, layers_sampler, in.world_position.xyz, normalize(in.world_normal), biomes);
+    pbr_input.material.base_color = vec4(albedo, 1.0) * pbr_bindings::material.base_color;
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
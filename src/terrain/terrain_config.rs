@@ -0,0 +1,356 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use bevy::ecs::resource::Resource;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::noise::heightmap_backend::BackendKind;
+
+pub const TERRAIN_CONFIG_PATH: &str = "assets/terrain_gen.json";
+
+/// Everything that shapes the generated world. Consumed by both `Perlin` (as a
+/// uniform) and `PerlinCPU`, so changing it never requires a recompile.
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainGenConfig {
+    pub seed: u64,
+    pub scale: f32,
+
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub persistence: f32,
+
+    pub noise_mode: NoiseMode,
+    pub warp: DomainWarp,
+
+    pub height_curve: HeightCurve,
+    pub sea_level: f32,
+
+    pub erosion: ErosionConfig,
+    pub biomes: BiomeConfig,
+    pub water: WaterConfig,
+
+    pub colliders: ColliderConfig,
+
+    pub backend: BackendKind,
+}
+
+/// How the octaves are combined. Every mode is normalised to roughly `[-1, 1]`
+/// so the height curve applies unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum NoiseMode {
+    /// Plain fractal Brownian motion, gentle rolling hills.
+    #[default]
+    Fbm,
+    /// Ridged multifractal, sharp crests for mountain ranges. `gain` controls how
+    /// strongly each ridge feeds the detail of the next octave.
+    Ridged { gain: f32 },
+    /// Folded `|noise|` octaves, rounded bumps for dunes.
+    Billow,
+    /// Weighted mix of the three modes, e.g. ridges over rolling hills. The weights are
+    /// divided by their sum and `gain` is the ridged one. Every mode with a non-zero weight
+    /// costs a full set of octaves.
+    Blend { fbm: f32, ridged: f32, billow: f32, gain: f32 },
+}
+
+/// Offsets the sample position by two fBm fields before evaluating the noise mode.
+/// A `strength` of zero disables warping.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DomainWarp {
+    pub strength: f32,
+    pub frequency: f32,
+}
+
+/// Optional erosion pass run over the noise before chunks are meshed, see `Erosion`.
+/// The world is eroded in square regions simulated with some padding around them, and
+/// erosion fades out towards each region's border so neighbouring regions meet seamlessly.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErosionConfig {
+    pub enabled: bool,
+    /// Chunks along each side of a region.
+    pub region_size: u32,
+    /// Samples simulated around a region so water can flow in from outside it.
+    pub padding: u32,
+    /// Samples over which erosion fades out towards a region's border.
+    pub border_fade: u32,
+    pub hydraulic: HydraulicErosion,
+    pub thermal: ThermalErosion,
+}
+
+/// Droplets rolling downhill, picking up sediment where they speed up and dropping it
+/// where they slow down, which carves gullies and fills valley floors.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HydraulicErosion {
+    pub droplets_per_sample: f32,
+    /// Droplets are simulated in this many rounds, each seeing the terrain the previous ones left.
+    pub batches: u32,
+    pub max_lifetime: u32,
+    /// How much of its direction a droplet keeps instead of following the slope, in `[0, 1]`.
+    pub inertia: f32,
+    pub sediment_capacity: f32,
+    pub min_capacity: f32,
+    /// Fraction of the excess sediment dropped per step.
+    pub deposition: f32,
+    /// Fraction of the spare capacity eroded per step.
+    pub erosion: f32,
+    pub evaporation: f32,
+    pub gravity: f32,
+}
+
+/// Material sliding off slopes steeper than `talus`, which leaves scree slopes at the foot of cliffs.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalErosion {
+    pub iterations: u32,
+    /// Steepest stable height difference between neighbouring samples, in metres.
+    pub talus: f32,
+    /// Fraction of the excess moved per iteration, at most 0.5.
+    pub rate: f32,
+}
+
+/// Temperature and moisture noise sampled alongside the heights, see `BiomeMap`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeConfig {
+    /// Frequency of the climate noise, far lower than the terrain's so biomes span many chunks.
+    pub climate_scale: f32,
+    pub climate_octaves: u32,
+    /// Sea level temperatures the noise maps to, in degrees Celsius.
+    pub min_temperature: f32,
+    pub max_temperature: f32,
+    /// Degrees lost per metre above sea level.
+    pub lapse_rate: f32,
+    /// Climate distance over which neighbouring biomes blend, smaller is sharper.
+    pub blend: f32,
+    /// Height rock takes over at, and the metres either side it blends over.
+    pub rock_height: f32,
+    pub rock_blend: f32,
+}
+
+/// Lakes and rivers above `sea_level`, see `LakeFinder` and `Water`. Only decides where
+/// water goes, the heights are the same whatever it is set to.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaterConfig {
+    /// Metres a basin has to be deep at its deepest sample to hold a lake.
+    pub lake_min_depth: f32,
+    /// Fewest samples a lake covers.
+    pub lake_min_area: u32,
+    /// Chance of a chunk having a spring, and the metres above sea level it has to be at.
+    pub spring_chance: f32,
+    pub spring_height: f32,
+    /// Samples a river is traced over at most.
+    pub river_max_length: u32,
+    /// Width of a river at its spring, the metres it widens by per sample and its widest.
+    pub river_width: f32,
+    pub river_widening: f32,
+    pub river_max_width: f32,
+}
+
+/// Resolution of the terrain heightfield colliders. Steps are in heightmap samples,
+/// 1 being full resolution, and must be powers of two.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColliderConfig {
+    /// Step of the colliders the player can walk on.
+    pub near_step: usize,
+    /// Step of everything else within `reach_radius`.
+    pub far_step: usize,
+    /// Chunks around the player that get `near_step` colliders.
+    pub near_radius: u32,
+    /// Chunks around the player that get a collider at all, as far as a bullet can fly.
+    pub reach_radius: u32,
+}
+
+/// Maps fBm output in `[-1, 1]` to metres: `(noise + offset)^exponent * amplitude`.
+/// The exponent is an integer so both paths can expand it into plain multiplications.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeightCurve {
+    pub offset: f32,
+    pub exponent: u32,
+    pub amplitude: f32,
+}
+
+impl Default for TerrainGenConfig {
+    fn default() -> Self {
+        TerrainGenConfig {
+            seed: 1,
+            scale: 0.001,
+
+            octaves: 4,
+            lacunarity: 2.0,
+            persistence: 0.5,
+
+            noise_mode: NoiseMode::Fbm,
+            warp: DomainWarp::default(),
+
+            height_curve: HeightCurve::default(),
+            sea_level: 20.0,
+
+            erosion: ErosionConfig::default(),
+            biomes: BiomeConfig::default(),
+            water: WaterConfig::default(),
+
+            colliders: ColliderConfig::default(),
+
+            backend: BackendKind::Auto,
+        }
+    }
+}
+
+impl Default for HeightCurve {
+    fn default() -> Self {
+        HeightCurve { offset: 1.0, exponent: 4, amplitude: 30.0 }
+    }
+}
+
+impl Default for ErosionConfig {
+    fn default() -> Self {
+        ErosionConfig {
+            enabled: false,
+            region_size: 4,
+            padding: 32,
+            border_fade: 32,
+            hydraulic: HydraulicErosion::default(),
+            thermal: ThermalErosion::default(),
+        }
+    }
+}
+
+impl Default for HydraulicErosion {
+    fn default() -> Self {
+        HydraulicErosion {
+            droplets_per_sample: 1.0,
+            batches: 32,
+            max_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_capacity: 0.01,
+            deposition: 0.3,
+            erosion: 0.3,
+            evaporation: 0.02,
+            gravity: 4.0,
+        }
+    }
+}
+
+impl Default for ThermalErosion {
+    fn default() -> Self {
+        ThermalErosion { iterations: 16, talus: 0.7, rate: 0.5 }
+    }
+}
+
+impl Default for BiomeConfig {
+    fn default() -> Self {
+        BiomeConfig {
+            climate_scale: 0.0004,
+            climate_octaves: 3,
+            min_temperature: -10.0,
+            max_temperature: 35.0,
+            lapse_rate: 0.01,
+            blend: 0.2,
+            rock_height: 300.0,
+            rock_blend: 40.0,
+        }
+    }
+}
+
+impl Default for WaterConfig {
+    fn default() -> Self {
+        WaterConfig {
+            lake_min_depth: 0.5,
+            lake_min_area: 16,
+            spring_chance: 0.1,
+            spring_height: 20.0,
+            river_max_length: 2048,
+            river_width: 2.0,
+            river_widening: 0.01,
+            river_max_width: 12.0,
+        }
+    }
+}
+
+impl Default for ColliderConfig {
+    fn default() -> Self {
+        ColliderConfig { near_step: 1, far_step: 8, near_radius: 2, reach_radius: 12 }
+    }
+}
+
+impl Default for DomainWarp {
+    fn default() -> Self {
+        DomainWarp { strength: 0.0, frequency: 1.0 }
+    }
+}
+
+impl NoiseMode {
+    /// Mode index, ridge gain and `Blend` weights as laid out in the `perlin.wgsl` uniform.
+    pub fn to_uniform(&self) -> (u32, f32, [f32; 3]) {
+        match self {
+            NoiseMode::Fbm => (0, 0.0, [0.0; 3]),
+            NoiseMode::Ridged { gain } => (1, *gain, [0.0; 3]),
+            NoiseMode::Billow => (2, 0.0, [0.0; 3]),
+            NoiseMode::Blend { fbm, ridged, billow, gain } => (3, *gain, [*fbm, *ridged, *billow]),
+        }
+    }
+}
+
+impl HeightCurve {
+    /// Same operation order as `height_curve` in `perlin.wgsl`.
+    pub fn apply(&self, noise: f32) -> f32 {
+        let base = noise + self.offset;
+        let mut height = 1.0;
+        for _ in 0..self.exponent {
+            height *= base;
+        }
+        height * self.amplitude
+    }
+}
+
+impl TerrainGenConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Loads `TERRAIN_CONFIG_PATH` if it exists, otherwise the defaults.
+    /// `TERRAIN_BACKEND` in the environment overrides the configured backend.
+    pub fn load_or_default() -> Self {
+        let mut config = match Self::load(TERRAIN_CONFIG_PATH) {
+            Ok(config) => {
+                info!("Loaded terrain config from {TERRAIN_CONFIG_PATH}");
+                config
+            }
+            Err(e) => {
+                warn!("Using default terrain config, could not read {TERRAIN_CONFIG_PATH}: {e}");
+                TerrainGenConfig::default()
+            }
+        };
+
+        if let Some(backend) = BackendKind::from_env() {
+            config.backend = backend;
+        }
+
+        config
+    }
+
+    /// FNV-1a hash of every setting that changes the generated heights, so caches
+    /// written under another config can be told apart. The region cache holds the heights
+    /// before biome shaping, so the biomes and `sea_level` don't count either.
+    pub fn generation_hash(&self) -> u64 {
+        let generation = TerrainGenConfig {
+            sea_level: 0.0,
+            biomes: BiomeConfig::default(),
+            water: WaterConfig::default(),
+            colliders: ColliderConfig::default(),
+            backend: BackendKind::Auto,
+            ..self.clone()
+        };
+        let json = serde_json::to_string(&generation).unwrap();
+
+        json.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+}
@@ -0,0 +1,524 @@
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap, HashSet}};
+
+use bevy::prelude::*;
+use bevy::{asset::RenderAssetUsages, render::mesh::{Indices, PrimitiveTopology}};
+
+use crate::{player::player::{Player, CAPSULE_HALF_HEIGHT, CAPSULE_RADIUS}, terrain::{chunks::{Chunkbase, RenderDistance, RenderedChunks, CHUNK_HEIGHT, CHUNK_WIDTH}, occlusion::Occludable, terrain_config::{TerrainGenConfig, WaterConfig}}};
+
+/// Metres a river's surface sits above the samples it was traced along.
+const RIVER_DEPTH: f32 = 0.3;
+
+/// Candidate samples per chunk a spring is picked from, the highest wins.
+const SPRING_CANDIDATES: u32 = 8;
+
+/// Offsets the world seed so springs don't line up with anything else hashed from it.
+const SPRING_SEED: u64 = 0x51f2;
+
+/// Samples of the neighbouring chunks flooded along with a chunk in `find_padded`.
+pub const LAKE_PADDING: usize = 64;
+
+/// The sea at `sea_level`, lakes filling the basins of each chunk and rivers running
+/// downhill from springs, each with a mesh of its own. Lakes and rivers are only shown on
+/// chunks with an entity in `RenderedChunks`, the sea reaches the edge of the render distance.
+pub struct WaterPlugin;
+
+/// Water surface over the samples of a chunk that a basin floods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lake {
+    /// Height of the water surface.
+    pub surface: f32,
+    /// Flooded samples as indices into `Chunk::heights`, ascending.
+    pub samples: Vec<u32>,
+}
+
+/// Finds the lakes in a chunk, see `find`.
+#[derive(Debug, Clone, Copy)]
+pub struct LakeFinder {
+    sea_level: f32,
+    min_depth: f32,
+    min_area: usize,
+}
+
+/// A river traced down from a spring, one point per sample it flows over.
+#[derive(Debug, Clone)]
+pub struct River {
+    /// Water surface above each sample along the river, from the spring down.
+    pub path: Vec<Vec3>,
+    /// Width of the river at each point of `path`.
+    pub widths: Vec<f32>,
+    /// The chunk the trace ran into before it was loaded, `None` once the river is complete.
+    pub frontier: Option<(i32, i32)>,
+}
+
+/// Every water body in the world and the entities showing them.
+#[derive(Resource)]
+pub struct Water {
+    pub sea_level: f32,
+    config: WaterConfig,
+    seed: u64,
+    material: Handle<StandardMaterial>,
+    /// Rivers keyed by the world sample of their spring.
+    rivers: HashMap<(i32, i32), (River, Entity)>,
+    /// Lake entities of every rendered chunk and the chunk revision they were built from.
+    lakes: HashMap<(i32, i32), (u32, Vec<Entity>)>,
+}
+
+#[derive(Component)]
+struct Sea;
+
+/// Lower priority for higher samples, so `BinaryHeap` pops the lowest first.
+struct Lowest(f32, usize);
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Water>()
+            .add_systems(Startup, spawn_sea)
+            .add_systems(Update, (follow_sea, spawn_lakes, spawn_rivers, update_player_water))
+        ;
+    }
+}
+
+impl LakeFinder {
+    pub fn new(config: &TerrainGenConfig) -> Self {
+        LakeFinder {
+            sea_level: config.sea_level,
+            min_depth: config.water.lake_min_depth,
+            min_area: config.water.lake_min_area as usize,
+        }
+    }
+
+    /// Priority flood from the border of the chunk inwards, raising every sample to the
+    /// lowest level water could drain out of the chunk from. Each connected patch raised by
+    /// at least `min_depth` somewhere becomes a lake. Water always drains through the border,
+    /// so a basin a chunk border cuts through only fills up to the lowest sample on the border.
+    pub fn find(&self, heights: &[f32]) -> Vec<Lake> {
+        self.find_padded(heights, 0)
+    }
+
+    /// `find` over the heights of a chunk with `padding` samples of its neighbours around
+    /// it, row by row. Basins the chunk border cuts through fill up as far as the padding
+    /// reaches, and each lake keeps only its samples inside the chunk.
+    pub fn find_padded(&self, heights: &[f32], padding: usize) -> Vec<Lake> {
+        let (stride, rows) = (CHUNK_WIDTH + 1 + 2 * padding, CHUNK_HEIGHT + 1 + 2 * padding);
+        assert_eq!(heights.len(), stride * rows, "heights are not padded by {padding}");
+        let neighbours = |index: usize| {
+            let (x, y) = (index % stride, index / stride);
+            [(x > 0).then(|| index - 1), (x + 1 < stride).then(|| index + 1), (y > 0).then(|| index - stride), (y + 1 < rows).then(|| index + stride)]
+                .into_iter()
+                .flatten()
+        };
+
+        let mut filled = heights.to_vec();
+        let mut visited = vec![false; heights.len()];
+        let mut open = BinaryHeap::new();
+        for index in 0..heights.len() {
+            let (x, y) = (index % stride, index / stride);
+            if x == 0 || y == 0 || x == stride - 1 || y == rows - 1 {
+                visited[index] = true;
+                open.push(Lowest(heights[index], index));
+            }
+        }
+
+        while let Some(Lowest(level, index)) = open.pop() {
+            for neighbour in neighbours(index) {
+                if !visited[neighbour] {
+                    visited[neighbour] = true;
+                    filled[neighbour] = heights[neighbour].max(level);
+                    open.push(Lowest(filled[neighbour], neighbour));
+                }
+            }
+        }
+
+        //Group the flooded samples into lakes
+        let mut lakes = Vec::new();
+        let mut grouped = vec![false; heights.len()];
+        for start in 0..heights.len() {
+            if grouped[start] || filled[start] <= heights[start] {
+                continue;
+            }
+
+            grouped[start] = true;
+            let mut samples = vec![start as u32];
+            let mut next = 0;
+            while next < samples.len() {
+                let index = samples[next] as usize;
+                next += 1;
+                for neighbour in neighbours(index) {
+                    if !grouped[neighbour] && filled[neighbour] > heights[neighbour] {
+                        grouped[neighbour] = true;
+                        samples.push(neighbour as u32);
+                    }
+                }
+            }
+
+            let surface = samples.iter().map(|&index| filled[index as usize]).fold(f32::MIN, f32::max);
+            let depth = samples.iter().map(|&index| surface - heights[index as usize]).fold(0.0, f32::max);
+            if depth < self.min_depth || samples.len() < self.min_area || surface <= self.sea_level {
+                continue;
+            }
+
+            //Back to indices into the chunk's heights
+            let mut samples: Vec<u32> = samples.into_iter()
+                .map(|index| (index as usize % stride, index as usize / stride))
+                .filter(|&(x, y)| (padding..=padding + CHUNK_WIDTH).contains(&x) && (padding..=padding + CHUNK_HEIGHT).contains(&y))
+                .map(|(x, y)| ((y - padding) * (CHUNK_WIDTH + 1) + x - padding) as u32)
+                .collect();
+            if !samples.is_empty() {
+                samples.sort_unstable();
+                lakes.push(Lake { surface, samples });
+            }
+        }
+        lakes
+    }
+}
+
+impl Lake {
+    pub fn contains(&self, index: usize) -> bool {
+        self.samples.binary_search(&(index as u32)).is_ok()
+    }
+
+    /// Flat surface over every cell with a flooded corner, in the chunk's local space.
+    /// The terrain around the shore hides the part of each cell that isn't under water.
+    pub fn mesh(&self) -> Mesh {
+        let stride = CHUNK_WIDTH + 1;
+        let mut cells = HashSet::new();
+        for &index in &self.samples {
+            let (x, y) = (index as usize % stride, index as usize / stride);
+            for (cx, cy) in [(x, y), (x.wrapping_sub(1), y), (x, y.wrapping_sub(1)), (x.wrapping_sub(1), y.wrapping_sub(1))] {
+                if cx < CHUNK_WIDTH && cy < CHUNK_HEIGHT {
+                    cells.insert((cx, cy));
+                }
+            }
+        }
+
+        let mut cells: Vec<(usize, usize)> = cells.into_iter().collect();
+        cells.sort_unstable();
+
+        let mut positions = Vec::with_capacity(cells.len() * 4);
+        let mut indices = Vec::with_capacity(cells.len() * 6);
+        for (x, y) in cells {
+            let first = positions.len() as u32;
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                positions.push([(x + dx) as f32, self.surface, (y + dy) as f32]);
+            }
+            indices.extend([first, first + 2, first + 1, first + 1, first + 2, first + 3]);
+        }
+
+        water_mesh(positions, indices)
+    }
+}
+
+impl River {
+    /// Chunks the river runs through, from the spring down.
+    pub fn chunks(&self) -> Vec<(i32, i32)> {
+        let mut chunks: Vec<(i32, i32)> = Vec::new();
+        for point in &self.path {
+            let coordinates = ((point.x / CHUNK_WIDTH as f32).floor() as i32, (point.z / CHUNK_HEIGHT as f32).floor() as i32);
+            if !chunks.contains(&coordinates) {
+                chunks.push(coordinates);
+            }
+        }
+        chunks
+    }
+
+    /// Height of the river's surface at `(x, z)`, `None` off the river.
+    pub fn surface_at(&self, x: f32, z: f32) -> Option<f32> {
+        let point = Vec2::new(x, z);
+        self.path.iter().zip(&self.widths)
+            .filter(|(sample, width)| sample.xz().distance_squared(point) <= (*width / 2.0).powi(2))
+            .map(|(sample, _)| sample.y)
+            .reduce(f32::max)
+    }
+
+    /// Ribbon along the path relative to the spring, facing up.
+    pub fn mesh(&self) -> Mesh {
+        let origin = self.path[0];
+        let mut positions = Vec::with_capacity(self.path.len() * 2);
+        for (index, (point, width)) in self.path.iter().zip(&self.widths).enumerate() {
+            let before = self.path[index.saturating_sub(1)];
+            let after = self.path[(index + 1).min(self.path.len() - 1)];
+            let along = (after - before).xz().normalize_or(Vec2::X);
+            let right = Vec3::new(-along.y, 0.0, along.x) * width / 2.0;
+
+            positions.push((point - right - origin).to_array());
+            positions.push((point + right - origin).to_array());
+        }
+
+        let mut indices = Vec::with_capacity(self.path.len().saturating_sub(1) * 6);
+        for segment in 0..self.path.len().saturating_sub(1) as u32 {
+            let (left, right) = (segment * 2, segment * 2 + 1);
+            indices.extend([left, right, left + 2, right, right + 2, left + 2]);
+        }
+
+        water_mesh(positions, indices)
+    }
+}
+
+impl Water {
+    /// Height of the water surface at `(x, z)`, `None` where the ground is dry or not loaded.
+    pub fn surface_at(&self, chunkbase: &Chunkbase, x: f32, z: f32) -> Option<f32> {
+        let ground = chunkbase.height_at(x, z)?;
+        let lake = chunkbase.lake_at((x.round() as i32, z.round() as i32)).map(|lake| lake.surface);
+        let rivers = self.rivers.values().filter_map(|(river, _)| river.surface_at(x, z));
+
+        [self.sea_level].into_iter().chain(lake).chain(rivers)
+            .reduce(f32::max)
+            .filter(|&surface| surface > ground)
+    }
+
+    /// World sample of the spring in a chunk, if it has one. The highest of a few hashed
+    /// samples, so every run picks the same one.
+    pub fn spring_in(&self, chunkbase: &Chunkbase, (chunk_x, chunk_y): (i32, i32)) -> Option<(i32, i32)> {
+        if hash(self.seed, chunk_x, chunk_y, 0) as f32 / u32::MAX as f32 >= self.config.spring_chance {
+            return None;
+        }
+
+        let (spring, height) = (1..=SPRING_CANDIDATES)
+            .map(|candidate| {
+                let sample = hash(self.seed, chunk_x, chunk_y, candidate);
+                let (x, z) = ((sample % CHUNK_WIDTH as u32) as i32, (sample / CHUNK_WIDTH as u32 % CHUNK_HEIGHT as u32) as i32);
+                (chunk_x * CHUNK_WIDTH as i32 + x, chunk_y * CHUNK_HEIGHT as i32 + z)
+            })
+            .filter_map(|sample| Some((sample, chunkbase.sample_height(sample)?)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        (height >= self.sea_level + self.config.spring_height).then_some(spring)
+    }
+
+    /// Follows the steepest way down from `spring` until the river reaches the sea or a
+    /// lake, sinks into a pit or runs `river_max_length` samples.
+    pub fn trace_river(&self, chunkbase: &Chunkbase, spring: (i32, i32)) -> Option<River> {
+        let mut current = spring;
+        let mut height = chunkbase.sample_height(current)?;
+        let mut river = River { path: Vec::new(), widths: Vec::new(), frontier: None };
+
+        loop {
+            let width = (self.config.river_width + river.path.len() as f32 * self.config.river_widening).min(self.config.river_max_width);
+            river.path.push(Vec3::new(current.0 as f32, height + RIVER_DEPTH, current.1 as f32));
+            river.widths.push(width);
+
+            if height <= self.sea_level || chunkbase.lake_at(current).is_some() || river.path.len() >= self.config.river_max_length as usize {
+                break;
+            }
+
+            let mut lowest = None;
+            for (dx, dz) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let neighbour = (current.0 + dx, current.1 + dz);
+                let Some(neighbour_height) = chunkbase.sample_height(neighbour) else {
+                    river.frontier = Some((neighbour.0.div_euclid(CHUNK_WIDTH as i32), neighbour.1.div_euclid(CHUNK_HEIGHT as i32)));
+                    return Some(river);
+                };
+                //Diagonals are further away, compare the slope rather than the drop
+                let drop = (height - neighbour_height) / ((dx * dx + dz * dz) as f32).sqrt();
+                if drop > 0.0 && lowest.is_none_or(|(_, steepest)| drop > steepest) {
+                    lowest = Some((neighbour, drop));
+                }
+            }
+
+            let Some((next, _)) = lowest else { break };
+            height = chunkbase.sample_height(next)?;
+            current = next;
+        }
+
+        Some(river)
+    }
+}
+
+impl FromWorld for Water {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.get_resource::<TerrainGenConfig>().cloned().unwrap_or_default();
+        let material = world.resource_mut::<Assets<StandardMaterial>>().add(StandardMaterial {
+            base_color: Color::srgba(0.1, 0.3, 0.45, 0.75),
+            alpha_mode: AlphaMode::Blend,
+            perceptual_roughness: 0.05,
+            reflectance: 0.3,
+            //Seen from underwater too
+            double_sided: true,
+            cull_mode: None,
+            ..default()
+        });
+
+        Water {
+            sea_level: config.sea_level,
+            config: config.water,
+            seed: config.seed ^ SPRING_SEED,
+            material,
+            rivers: HashMap::new(),
+            lakes: HashMap::new(),
+        }
+    }
+}
+
+impl Chunkbase {
+    /// Lake covering world sample `(x, z)`, `None` if there is none or its chunk isn't loaded.
+    pub fn lake_at(&self, (x, z): (i32, i32)) -> Option<&Lake> {
+        let (width, height) = (CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
+        let chunk = self.get_chunk(&(x.div_euclid(width), z.div_euclid(height)))?;
+        let index = z.rem_euclid(height) as usize * (CHUNK_WIDTH + 1) + x.rem_euclid(width) as usize;
+        chunk.lakes.iter().find(|lake| lake.contains(index))
+    }
+}
+
+impl PartialEq for Lowest {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Lowest {}
+
+impl PartialOrd for Lowest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Lowest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+
+/// Upwards facing mesh, UVs in metres for anything animating the surface.
+fn water_mesh(positions: Vec<[f32; 3]>, indices: Vec<u32>) -> Mesh {
+    let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+    let uvs: Vec<[f32; 2]> = positions.iter().map(|&[x, _, z]| [x, z]).collect();
+
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_indices(Indices::U32(indices))
+}
+
+fn hash(seed: u64, x: i32, z: i32, salt: u32) -> u32 {
+    let mut hash = (seed as u32 ^ (seed >> 32) as u32).wrapping_mul(0x9e37_79b9)
+        ^ (x as u32).wrapping_mul(0x85eb_ca6b)
+        ^ (z as u32).wrapping_mul(0xc2b2_ae35)
+        ^ salt.wrapping_mul(0x27d4_eb2f);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    hash
+}
+
+/// One unit plane scaled out to the render distance in `follow_sea`.
+fn spawn_sea(mut commands: Commands, water: Res<Water>, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.spawn((
+        Sea,
+        Mesh3d(meshes.add(Plane3d::default().mesh().size(1.0, 1.0))),
+        MeshMaterial3d(water.material.clone()),
+        Transform::from_xyz(0.0, water.sea_level, 0.0),
+    ));
+}
+
+/// Keeps the sea centred on the chunk the player is in.
+fn follow_sea(player_query: Query<&Player>, render_distance: Res<RenderDistance>, mut sea_query: Query<&mut Transform, With<Sea>>) {
+    let (Ok(player), Ok(mut transform)) = (player_query.single(), sea_query.single_mut()) else { return };
+    let (cx, cy) = player.current_chunk.0;
+    let size = (2 * render_distance.0 + 1) as f32;
+
+    transform.translation.x = (cx as f32 + 0.5) * CHUNK_WIDTH as f32;
+    transform.translation.z = (cy as f32 + 0.5) * CHUNK_HEIGHT as f32;
+    transform.scale = Vec3::new(size * CHUNK_WIDTH as f32, 1.0, size * CHUNK_HEIGHT as f32);
+}
+
+/// Gives every lake of a rendered chunk an entity, rebuilt whenever the chunk's lakes change.
+fn spawn_lakes(
+    mut commands: Commands,
+    mut water: ResMut<Water>,
+    chunkbase: Res<Chunkbase>,
+    rendered_chunks: Res<RenderedChunks>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mesh_query: Query<&Mesh3d>,
+) {
+    let water = &mut *water;
+    let gone: Vec<(i32, i32)> = water.lakes.keys().filter(|coordinates| !rendered_chunks.0.contains_key(coordinates)).copied().collect();
+    for coordinates in gone {
+        for entity in water.lakes.remove(&coordinates).into_iter().flat_map(|(_, entities)| entities) {
+            despawn_body(&mut commands, &mut meshes, &mesh_query, entity);
+        }
+    }
+
+    for coordinates in rendered_chunks.0.keys() {
+        let Some(chunk) = chunkbase.get_chunk(coordinates) else { continue };
+        if water.lakes.get(coordinates).is_some_and(|(revision, _)| *revision == chunk.lake_revision) {
+            continue;
+        }
+
+        let entities = chunk.lakes.iter().map(|lake| commands.spawn((
+            Mesh3d(meshes.add(lake.mesh())),
+            MeshMaterial3d(water.material.clone()),
+            chunk.transform,
+            Occludable(vec![*coordinates]),
+        )).id()).collect();
+
+        for old in water.lakes.insert(*coordinates, (chunk.lake_revision, entities)).into_iter().flat_map(|(_, entities)| entities) {
+            despawn_body(&mut commands, &mut meshes, &mesh_query, old);
+        }
+    }
+}
+
+/// Traces the river of every rendered chunk with a spring. Rivers that ran into an unloaded
+/// chunk are traced again once that chunk has loaded.
+fn spawn_rivers(
+    mut commands: Commands,
+    mut water: ResMut<Water>,
+    chunkbase: Res<Chunkbase>,
+    rendered_chunks: Res<RenderedChunks>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mesh_query: Query<&Mesh3d>,
+) {
+    let water = &mut *water;
+    let width = CHUNK_WIDTH as i32;
+    let height = CHUNK_HEIGHT as i32;
+
+    water.rivers.retain(|(x, z), (_, entity)| {
+        let keep = rendered_chunks.0.contains_key(&(x.div_euclid(width), z.div_euclid(height)));
+        if !keep {
+            despawn_body(&mut commands, &mut meshes, &mesh_query, *entity);
+        }
+        keep
+    });
+
+    for coordinates in rendered_chunks.0.keys() {
+        let Some(spring) = water.spring_in(&chunkbase, *coordinates) else { continue };
+        let stale = match water.rivers.get(&spring) {
+            Some((river, _)) => river.frontier.is_some_and(|frontier| chunkbase.get_chunk(&frontier).is_some()),
+            None => true,
+        };
+        if !stale {
+            continue;
+        }
+
+        let Some(river) = water.trace_river(&chunkbase, spring) else { continue };
+        let entity = commands.spawn((
+            Mesh3d(meshes.add(river.mesh())),
+            MeshMaterial3d(water.material.clone()),
+            Transform::from_translation(river.path[0]),
+            Occludable(river.chunks()),
+        )).id();
+
+        if let Some((_, old)) = water.rivers.insert(spring, (river, entity)) {
+            despawn_body(&mut commands, &mut meshes, &mesh_query, old);
+        }
+    }
+}
+
+/// Despawns a lake or river along with its mesh, which nothing else shares.
+fn despawn_body(commands: &mut Commands, meshes: &mut Assets<Mesh>, mesh_query: &Query<&Mesh3d>, entity: Entity) {
+    if let Ok(mesh) = mesh_query.get(entity) {
+        meshes.remove(&mesh.0);
+    }
+    commands.entity(entity).despawn();
+}
+
+/// How deep the player's feet are under water, for `apply_player_movement`.
+fn update_player_water(water: Res<Water>, chunkbase: Res<Chunkbase>, mut player_query: Query<(&mut Player, &Transform)>) {
+    let Ok((mut player, transform)) = player_query.single_mut() else { return };
+    let feet = transform.translation.y - CAPSULE_HALF_HEIGHT - CAPSULE_RADIUS;
+    let surface = water.surface_at(&chunkbase, transform.translation.x, transform.translation.z);
+
+    player.state.water_depth = surface.map_or(0.0, |surface| (surface - feet).max(0.0));
+}
@@ -0,0 +1,92 @@
+use bevy::math::Vec3;
+use terrain::terrain::chunks::{Chunkbase, CHUNK_HEIGHT, CHUNK_WIDTH};
+
+mod common;
+
+/// Climbs half a metre per metre along x.
+fn slope(x: i32, _: i32) -> f32 {
+    x as f32 * 0.5
+}
+
+fn chunkbase(chunks: impl IntoIterator<Item = (i32, i32)>, ground: impl Fn(i32, i32) -> f32) -> Chunkbase {
+    let mut chunkbase = Chunkbase::new(common::backend(), 16);
+    common::load(&mut chunkbase, chunks, ground);
+    chunkbase
+}
+
+#[test]
+fn heights_match_the_samples_exactly() {
+    let ground = |x: i32, z: i32| ((x * 7 + z * 13) % 23) as f32 * 0.25;
+    let chunkbase = chunkbase([(0, 0), (1, 0), (0, 1), (1, 1)], ground);
+
+    let (width, height) = (CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
+    for (x, z) in [(0, 0), (5, 9), (width - 1, 3), (width, 3), (width + 1, height), (2 * width - 1, 2 * height - 1)] {
+        assert_eq!(chunkbase.height_at(x as f32, z as f32), Some(ground(x, z)), "({x}, {z})");
+    }
+
+    //Bilinear between the four corners of the cell
+    let corners = [ground(5, 9), ground(6, 9), ground(5, 10), ground(6, 10)];
+    let centre = corners.iter().sum::<f32>() / 4.0;
+    assert!((chunkbase.height_at(5.5, 9.5).unwrap() - centre).abs() < 1e-5);
+}
+
+#[test]
+fn flat_ground_faces_straight_up() {
+    let chunkbase = chunkbase([(0, 0)], |_, _| 12.0);
+
+    for (x, z) in [(0.0, 0.0), (31.3, 77.9), (CHUNK_WIDTH as f32 - 0.5, CHUNK_HEIGHT as f32 - 0.5)] {
+        assert_eq!(chunkbase.height_at(x, z), Some(12.0));
+        assert_eq!(chunkbase.normal_at(x, z), Some(Vec3::Y));
+        assert_eq!(chunkbase.slope_at(x, z), Some(0.0));
+    }
+}
+
+#[test]
+fn slope_normal_and_angle() {
+    let chunkbase = chunkbase([(0, 0)], slope);
+
+    let normal = chunkbase.normal_at(40.2, 17.6).unwrap();
+    assert!(normal.abs_diff_eq(Vec3::new(-0.5, 1.0, 0.0).normalize(), 1e-5), "{normal}");
+    assert!((chunkbase.slope_at(40.2, 17.6).unwrap() - 0.5f32.atan()).abs() < 1e-5);
+}
+
+#[test]
+fn ray_hits_a_known_slope() {
+    let chunkbase = chunkbase([(0, 0)], slope);
+
+    //Straight down onto x = 10, where the ground is 5 m up
+    let hit = chunkbase.raycast_terrain(Vec3::new(10.0, 100.0, 32.0), Vec3::NEG_Y, 200.0).unwrap();
+    assert!(hit.point.abs_diff_eq(Vec3::new(10.0, 5.0, 32.0), 1e-3), "{:?}", hit.point);
+    assert!((hit.distance - 95.0).abs() < 1e-3);
+
+    //Down at 45 degrees, meeting y = 0.5x where y = 50 - x
+    let hit = chunkbase.raycast_terrain(Vec3::new(0.0, 50.0, 32.0), Vec3::new(1.0, -1.0, 0.0), 100.0).unwrap();
+    assert!(hit.point.abs_diff_eq(Vec3::new(100.0 / 3.0, 50.0 / 3.0, 32.0), 1e-3), "{:?}", hit.point);
+    assert!(hit.normal.abs_diff_eq(Vec3::new(-0.5, 1.0, 0.0).normalize(), 1e-5));
+
+    //Stops short of the ground
+    assert_eq!(chunkbase.raycast_terrain(Vec3::new(10.0, 100.0, 32.0), Vec3::NEG_Y, 50.0), None);
+}
+
+#[test]
+fn ray_from_below_the_surface_misses() {
+    let chunkbase = chunkbase([(0, 0)], |_, _| 20.0);
+
+    assert_eq!(chunkbase.raycast_terrain(Vec3::new(32.0, 10.0, 32.0), Vec3::NEG_Y, 50.0), None);
+    assert_eq!(chunkbase.raycast_terrain(Vec3::new(32.0, 10.0, 32.0), Vec3::Y, 50.0), None);
+}
+
+#[test]
+fn unloaded_chunks_give_nothing() {
+    let chunkbase = chunkbase([(0, 0)], |_, _| 20.0);
+
+    assert_eq!(chunkbase.height_at(-0.5, 10.0), None);
+    assert_eq!(chunkbase.normal_at(10.0, CHUNK_HEIGHT as f32 + 0.5), None);
+    assert_eq!(chunkbase.slope_at(-10.0, -10.0), None);
+    assert_eq!(chunkbase.raycast_terrain(Vec3::new(-30.0, 50.0, 10.0), Vec3::NEG_Y, 100.0), None);
+
+    //Descends over the unloaded stretch and hits once it reaches the loaded chunk
+    let hit = chunkbase.raycast_terrain(Vec3::new(-20.0, 30.0, 10.0), Vec3::new(1.0, -0.25, 0.0), 100.0).unwrap();
+    assert!((hit.point.y - 20.0).abs() < 1e-3);
+    assert!(hit.point.x >= 0.0);
+}
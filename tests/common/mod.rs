@@ -0,0 +1,35 @@
+//Each test crate only uses some of these
+#![allow(dead_code)]
+
+use bevy::{asset::Assets, render::mesh::Mesh};
+use terrain::{noise::{heightmap_backend::HeightmapBackend, perlin_cpu::PerlinCPU}, terrain::chunks::{Chunk, Chunkbase, CHUNK_HEIGHT, CHUNK_WIDTH}};
+
+/// The noise the tests generate terrain from.
+pub fn perlin() -> PerlinCPU {
+    PerlinCPU::new(1, 0.004, 4, 2.0, 0.5)
+}
+
+pub fn backend() -> HeightmapBackend {
+    HeightmapBackend::Cpu(Box::new(perlin()))
+}
+
+/// Heights of a chunk, halo included, from `ground` at each world sample.
+pub fn chunk_heights((chunk_x, chunk_y): (i32, i32), ground: &impl Fn(i32, i32) -> f32) -> Vec<f32> {
+    (0..=CHUNK_HEIGHT as i32)
+        .flat_map(|y| (0..=CHUNK_WIDTH as i32).map(move |x| (x, y)))
+        .map(|(x, y)| ground(chunk_x * CHUNK_WIDTH as i32 + x, chunk_y * CHUNK_HEIGHT as i32 + y))
+        .collect()
+}
+
+/// Adds a chunk built from `heights` to `chunkbase` as if it had been generated.
+pub fn insert(chunkbase: &mut Chunkbase, coordinates: (i32, i32), heights: &[f32]) {
+    let mut meshes = Assets::<Mesh>::default();
+    chunkbase.insert(coordinates, Chunk::from_heights(coordinates, heights).into_chunk(&mut meshes));
+}
+
+/// Adds `chunks` shaped by `ground` to `chunkbase`.
+pub fn load(chunkbase: &mut Chunkbase, chunks: impl IntoIterator<Item = (i32, i32)>, ground: impl Fn(i32, i32) -> f32) {
+    for coordinates in chunks {
+        insert(chunkbase, coordinates, &chunk_heights(coordinates, &ground));
+    }
+}
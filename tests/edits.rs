@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, TaskPool}};
+use terrain::terrain::{chunks::{Chunk, Chunkbase, CHUNK_HEIGHT, CHUNK_WIDTH}, edits::{Brush, BrushKind, TerrainEdits}};
+
+mod common;
+
+const STRIDE: usize = CHUNK_WIDTH + 1;
+
+/// Loads `chunks` shaped by `ground` into a chunkbase ready to rebuild edited chunks.
+fn chunkbase(chunks: impl IntoIterator<Item = (i32, i32)>, ground: impl Fn(i32, i32) -> f32) -> Chunkbase {
+    AsyncComputeTaskPool::get_or_init(TaskPool::default);
+    let mut chunkbase = Chunkbase::new(common::backend(), 64);
+    common::load(&mut chunkbase, chunks, ground);
+    chunkbase
+}
+
+fn brush(kind: BrushKind, strength: f32) -> Brush {
+    Brush { kind, radius: 4.0, strength }
+}
+
+/// Polls until every chunk in `chunks` has been rebuilt at least once.
+fn wait_for_rebuilds(chunkbase: &mut Chunkbase, chunks: &[(i32, i32)], meshes: &mut Assets<Mesh>) {
+    let start = Instant::now();
+    while chunks.iter().any(|coordinates| chunkbase.get_chunk(coordinates).unwrap().revision == 0) {
+        assert!(start.elapsed() < Duration::from_secs(30), "chunks were never rebuilt");
+        chunkbase.poll_tasks(meshes);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn border_edit_is_in_both_chunks_deltas() {
+    let mut edits = TerrainEdits::default();
+    edits.add((CHUNK_WIDTH as i32, 5), 2.0);
+
+    assert_eq!(edits.chunk_deltas((1, 0)), vec![(5 * STRIDE, 2.0)]);
+    assert_eq!(edits.chunk_deltas((0, 0)), vec![(5 * STRIDE + CHUNK_WIDTH, 2.0)]);
+    assert!(edits.chunk_deltas((0, 1)).is_empty());
+}
+
+#[test]
+fn corner_edit_is_in_all_four_chunks_deltas() {
+    let mut edits = TerrainEdits::default();
+    edits.add((CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32), 1.0);
+
+    assert_eq!(edits.chunk_deltas((1, 1)), vec![(0, 1.0)]);
+    assert_eq!(edits.chunk_deltas((0, 1)), vec![(CHUNK_WIDTH, 1.0)]);
+    assert_eq!(edits.chunk_deltas((1, 0)), vec![(CHUNK_HEIGHT * STRIDE, 1.0)]);
+    assert_eq!(edits.chunk_deltas((0, 0)), vec![(CHUNK_HEIGHT * STRIDE + CHUNK_WIDTH, 1.0)]);
+}
+
+#[test]
+fn save_and_load_round_trip() {
+    let mut edits = TerrainEdits::default();
+    for (sample, delta) in [((3, 4), 1.25), ((-7, 200), -0.1), ((CHUNK_WIDTH as i32, -1), 3.0e-4)] {
+        edits.add(sample, delta);
+    }
+    edits.add((3, 4), 0.5);
+
+    let path = std::env::temp_dir().join(format!("terrain_edits_{}", std::process::id())).join("edits.json");
+    edits.save(&path).unwrap();
+    let loaded = TerrainEdits::load(&path).unwrap();
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(loaded.len(), 3);
+    for sample in [(3, 4), (-7, 200), (CHUNK_WIDTH as i32, -1), (0, 0)] {
+        assert_eq!(loaded.delta_at(sample).to_bits(), edits.delta_at(sample).to_bits(), "{sample:?}");
+    }
+}
+
+#[test]
+fn raise_and_lower_peak_at_the_centre() {
+    let mut chunkbase = chunkbase([(0, 0)], |_, _| 10.0);
+
+    assert!(chunkbase.apply_brush(Vec2::new(32.0, 32.0), &brush(BrushKind::Raise, 2.0)) > 0);
+    assert_eq!(chunkbase.sample_height((32, 32)), Some(12.0));
+    let edge = chunkbase.sample_height((35, 32)).unwrap();
+    assert!(edge > 10.0 && edge < 12.0);
+    assert_eq!(chunkbase.sample_height((37, 32)), Some(10.0));
+
+    chunkbase.apply_brush(Vec2::new(32.0, 32.0), &brush(BrushKind::Lower, 2.0));
+    assert_eq!(chunkbase.sample_height((32, 32)), Some(10.0));
+    assert!((chunkbase.sample_height((35, 32)).unwrap() - 10.0).abs() < 1e-5);
+}
+
+#[test]
+fn flatten_pulls_towards_the_centre_height() {
+    let slope = |x: i32, _| x as f32 * 0.5;
+    let mut chunkbase = chunkbase([(0, 0)], slope);
+
+    chunkbase.apply_brush(Vec2::new(32.0, 32.0), &brush(BrushKind::Flatten, 1.0));
+    assert_eq!(chunkbase.sample_height((32, 32)), Some(16.0));
+    for x in [29, 30, 31, 33, 34, 35] {
+        let height = chunkbase.sample_height((x, 32)).unwrap();
+        assert!((height - 16.0).abs() < (slope(x, 32) - 16.0).abs(), "{x}: {height}");
+    }
+
+    //Nothing loaded under the centre to flatten towards
+    assert_eq!(chunkbase.apply_brush(Vec2::new(-32.0, 32.0), &brush(BrushKind::Flatten, 1.0)), 0);
+}
+
+#[test]
+fn smooth_levels_a_spike() {
+    let mut chunkbase = chunkbase([(0, 0)], |x, z| if (x, z) == (32, 32) { 19.0 } else { 10.0 });
+
+    chunkbase.apply_brush(Vec2::new(32.0, 32.0), &brush(BrushKind::Smooth, 1.0));
+    let (spike, neighbour) = (chunkbase.sample_height((32, 32)).unwrap(), chunkbase.sample_height((33, 32)).unwrap());
+    assert_eq!(spike, 11.0);
+    assert!(neighbour > 10.0 && neighbour < spike);
+}
+
+#[test]
+fn edited_heights_reach_loaded_chunks_and_their_rebuilds() {
+    //Rebuilds replace the meshes in place, so they have to be in the same store
+    let mut meshes = Assets::<Mesh>::default();
+    let mut chunkbase = chunkbase([], |_, _| 0.0);
+    for coordinates in [(0, 0), (1, 0)] {
+        let heights = common::chunk_heights(coordinates, &|_, _| 10.0);
+        chunkbase.insert(coordinates, Chunk::from_heights(coordinates, &heights).into_chunk(&mut meshes));
+    }
+    let border = CHUNK_WIDTH as i32;
+
+    chunkbase.edit_heights(&[((border, 5), 4.0), ((3, 5), -2.0)]);
+    assert_eq!(chunkbase.get_chunk(&(0, 0)).unwrap().heights[5 * STRIDE + CHUNK_WIDTH], 14.0);
+    assert_eq!(chunkbase.get_chunk(&(1, 0)).unwrap().heights[5 * STRIDE], 14.0);
+    assert_eq!(chunkbase.sample_height((3, 5)), Some(8.0));
+
+    wait_for_rebuilds(&mut chunkbase, &[(0, 0), (1, 0)], &mut meshes);
+    for coordinates in [(0, 0), (1, 0)] {
+        let chunk = chunkbase.get_chunk(&coordinates).unwrap();
+        assert_eq!(chunk.height_range.1, 14.0, "{coordinates:?}");
+    }
+    assert_eq!(chunkbase.get_chunk(&(0, 0)).unwrap().height_range.0, 8.0);
+    assert_eq!(chunkbase.edits().len(), 2);
+}
+
+#[test]
+fn regenerated_chunks_get_their_edits_back() {
+    let mut edits = TerrainEdits::default();
+    edits.add((CHUNK_WIDTH as i32, 5), 4.0);
+
+    let mut meshes = Assets::<Mesh>::default();
+    let mut plain = Chunkbase::new(common::backend(), 4);
+    let mut edited = Chunkbase::new(common::backend(), 4).with_edits(edits);
+    for chunkbase in [&mut plain, &mut edited] {
+        chunkbase.generate_blocking((0, 0), &mut meshes);
+        chunkbase.generate_blocking((1, 0), &mut meshes);
+    }
+
+    let (plain_heights, edited_heights) = (&plain.get_chunk(&(0, 0)).unwrap().heights, &edited.get_chunk(&(0, 0)).unwrap().heights);
+    assert_eq!(edited_heights[5 * STRIDE + CHUNK_WIDTH], plain_heights[5 * STRIDE + CHUNK_WIDTH] + 4.0);
+    assert_eq!(edited.sample_height((CHUNK_WIDTH as i32, 5)), plain.sample_height((CHUNK_WIDTH as i32, 5)).map(|height| height + 4.0));
+}
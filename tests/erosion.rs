@@ -0,0 +1,48 @@
+use bevy::tasks::block_on;
+use common::perlin;
+use terrain::{noise::erosion::Erosion, terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH}, terrain_config::TerrainGenConfig}};
+
+mod common;
+
+const STRIDE: usize = CHUNK_WIDTH + 1;
+const SIZE: usize = 96;
+
+/// Erosion on the CPU over one-chunk regions, so each region erodes quickly.
+fn erosion() -> Erosion {
+    let mut config = TerrainGenConfig::default();
+    config.erosion.enabled = true;
+    config.erosion.region_size = 1;
+    Erosion::new(&config, &common::backend())
+}
+
+fn eroded_map(erosion: &Erosion) -> Vec<f32> {
+    //Steep enough for droplets to carve into
+    let mut heights: Vec<f32> = perlin().compute_region((0, 0), (SIZE as u32, SIZE as u32)).into_iter().map(|height| height * 200.0).collect();
+    erosion.erode_map(&mut heights, SIZE, SIZE).unwrap();
+    heights
+}
+
+#[test]
+fn same_seed_erodes_bit_identically() {
+    let erosion = erosion();
+    let a = eroded_map(&erosion);
+    let b = eroded_map(&erosion);
+    assert!(a.iter().zip(&b).all(|(a, b)| a.to_bits() == b.to_bits()));
+
+    let raw: Vec<f32> = perlin().compute_region((0, 0), (SIZE as u32, SIZE as u32)).into_iter().map(|height| height * 200.0).collect();
+    assert_ne!(a, raw, "nothing was eroded");
+}
+
+#[test]
+fn adjacent_regions_agree_on_their_border() {
+    let erosion = erosion();
+    let (left, right) = (block_on(erosion.compute_chunk((0, 0))).unwrap(), block_on(erosion.compute_chunk((1, 0))).unwrap());
+    let below = block_on(erosion.compute_chunk((0, 1))).unwrap();
+
+    for y in 0..=CHUNK_HEIGHT {
+        assert_eq!(left[y * STRIDE + CHUNK_WIDTH].to_bits(), right[y * STRIDE].to_bits(), "row {y}");
+    }
+    for x in 0..=CHUNK_WIDTH {
+        assert_eq!(left[CHUNK_HEIGHT * STRIDE + x].to_bits(), below[x].to_bits(), "column {x}");
+    }
+}
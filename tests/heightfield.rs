@@ -0,0 +1,95 @@
+use bevy::{math::{Quat, Vec3}, render::mesh::{Mesh, VertexAttributeValues}};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use terrain::terrain::chunks::{generate_heightfield, Chunk, CHUNK_HEIGHT, CHUNK_WIDTH, HEIGHTFIELD_OFFSET};
+
+mod common;
+
+const RAYS: usize = 1024;
+const TOLERANCE: f32 = 1e-3;
+
+/// Distance down from `origin` to the chunk mesh, testing the two triangles of the cell below it.
+fn raycast_mesh(mesh: &Mesh, lod: usize, origin: Vec3) -> Option<f32> {
+    let Some(VertexAttributeValues::Float32x3(vertices)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { panic!("mesh has no positions") };
+    let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+
+    //Surface cells come first, row by row
+    let cell = (origin.z as usize / lod) * (CHUNK_WIDTH / lod) + origin.x as usize / lod;
+
+    indices[cell * 6..cell * 6 + 6]
+        .chunks_exact(3)
+        .filter_map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(vertices[triangle[i]]));
+            intersect_down(origin, a, b, c)
+        })
+        .min_by(f32::total_cmp)
+}
+
+/// Möller-Trumbore against a ray pointing straight down.
+fn intersect_down(origin: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let direction = Vec3::NEG_Y;
+    let (ab, ac) = (b - a, c - a);
+    let p = direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let t = origin - a;
+    let u = t.dot(p) / determinant;
+    let q = t.cross(ab);
+    let v = direction.dot(q) / determinant;
+    if !(-1e-6..=1.0 + 1e-6).contains(&u) || v < -1e-6 || u + v > 1.0 + 1e-6 {
+        return None;
+    }
+
+    Some(ac.dot(q) / determinant)
+}
+
+#[test]
+fn heightfield_matches_mesh_at_every_lod() {
+    let perlin = common::perlin();
+    let mut rng = StdRng::seed_from_u64(5);
+
+    for coordinates in [(0, 0), (3, -2), (-7, 11)] {
+        let heights = perlin.compute_chunk(coordinates);
+        let chunk = Chunk::from_heights(coordinates, &heights);
+        let top = heights.iter().copied().fold(f32::MIN, f32::max) + 10.0;
+
+        for (lod, mesh) in [(1, &chunk.mesh), (2, &chunk.mesh_2), (4, &chunk.mesh_4)] {
+            let collider = generate_heightfield(&heights, lod);
+
+            for _ in 0..RAYS {
+                let origin = Vec3::new(rng.random_range(0.0..CHUNK_WIDTH as f32), top, rng.random_range(0.0..CHUNK_HEIGHT as f32));
+
+                let mesh_hit = raycast_mesh(mesh, lod, origin).expect("ray missed the mesh");
+                let collider_hit = collider
+                    .cast_ray(HEIGHTFIELD_OFFSET, Quat::IDENTITY, origin, Vec3::NEG_Y, f32::MAX, true)
+                    .expect("ray missed the heightfield");
+
+                let (mesh_height, collider_height) = (top - mesh_hit, top - collider_hit);
+                assert!(
+                    (mesh_height - collider_height).abs() <= TOLERANCE * mesh_height.abs().max(1.0),
+                    "chunk {coordinates:?}, lod {lod} at ({}, {}): mesh {mesh_height}, heightfield {collider_height}",
+                    origin.x, origin.z,
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn heightfield_corners_sit_on_the_chunk_corners() {
+    let perlin = common::perlin();
+    let heights = perlin.compute_chunk((2, 5));
+    let collider = generate_heightfield(&heights, 1);
+    let stride = CHUNK_WIDTH + 1;
+
+    for (x, y) in [(0, 0), (CHUNK_WIDTH, 0), (0, CHUNK_HEIGHT), (CHUNK_WIDTH, CHUNK_HEIGHT), (17, 90)] {
+        //Nudge inwards so the ray doesn't graze the outer edge
+        let origin = Vec3::new((x as f32).clamp(0.01, CHUNK_WIDTH as f32 - 0.01), 1000.0, (y as f32).clamp(0.01, CHUNK_HEIGHT as f32 - 0.01));
+        let hit = collider.cast_ray(HEIGHTFIELD_OFFSET, Quat::IDENTITY, origin, Vec3::NEG_Y, f32::MAX, true).unwrap();
+        let expected = heights[y * stride + x];
+
+        assert!((1000.0 - hit - expected).abs() < 0.05, "({x}, {y}): heightfield {}, heightmap {expected}", 1000.0 - hit);
+    }
+}
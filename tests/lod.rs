@@ -0,0 +1,37 @@
+use terrain::terrain::grid::{select_lod, LodSettings};
+
+//LOD 1 is within 2 pixels from 500 away, within the hysteresis margin from 666.7,
+//LOD 2 from 2000 and 2666.7
+const ERRORS: [f32; 3] = [0.0, 1.0, 4.0];
+const SCALE: f32 = 1000.0;
+
+fn settings() -> LodSettings {
+    LodSettings { max_pixel_error: 2.0, hysteresis: 0.25 }
+}
+
+#[test]
+fn refines_as_soon_as_the_error_shows() {
+    assert_eq!(select_lod(ERRORS, 1000.0, SCALE, 2, &settings()), 1);
+    assert_eq!(select_lod(ERRORS, 100.0, SCALE, 2, &settings()), 0);
+    assert_eq!(select_lod(ERRORS, 499.0, SCALE, 1, &settings()), 0);
+}
+
+#[test]
+fn coarsens_only_past_the_hysteresis_band() {
+    assert_eq!(select_lod(ERRORS, 600.0, SCALE, 0, &settings()), 0);
+    assert_eq!(select_lod(ERRORS, 700.0, SCALE, 0, &settings()), 1);
+    assert_eq!(select_lod(ERRORS, 2500.0, SCALE, 1, &settings()), 1);
+    assert_eq!(select_lod(ERRORS, 3000.0, SCALE, 1, &settings()), 2);
+}
+
+#[test]
+fn keeps_its_lod_inside_the_band() {
+    for current in [0, 1] {
+        assert_eq!(select_lod(ERRORS, 600.0, SCALE, current, &settings()), current);
+    }
+}
+
+#[test]
+fn flat_chunks_go_coarsest_up_close() {
+    assert_eq!(select_lod([0.0; 3], 1.0, SCALE, 0, &settings()), 2);
+}
@@ -0,0 +1,104 @@
+use bevy::math::Vec3;
+use terrain::{noise::perlin_cpu::PerlinCPU, terrain::chunks::{ChunkData, CHUNK_HEIGHT, CHUNK_WIDTH}};
+
+const LODS: [usize; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+/// Full resolution heights for chunk (3, -2), halo included, as `Chunk::from_heights` takes them.
+fn chunk_heights() -> Vec<f32> {
+    PerlinCPU::new(1, 0.001, 4, 2.0, 0.5).compute_chunk((3, -2))
+}
+
+fn triangle_normal(data: &ChunkData, triangle: &[u32]) -> Vec3 {
+    let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(data.vertex_buffer[triangle[i] as usize]));
+    (b - a).cross(c - a)
+}
+
+#[test]
+fn vertex_and_index_counts() {
+    let heights = chunk_heights();
+
+    for lod in LODS {
+        let data = ChunkData::from_heights(&heights, lod);
+        let columns = CHUNK_WIDTH / lod + 1;
+        let rows = CHUNK_HEIGHT / lod + 1;
+
+        let surface_vertices = columns * rows;
+        let skirt_vertices = 2 * (2 * columns + 2 * rows);
+        let surface_indices = (columns - 1) * (rows - 1) * 6;
+        let skirt_indices = 2 * ((columns - 1) + (rows - 1)) * 6;
+
+        assert_eq!(data.vertex_buffer.len(), surface_vertices + skirt_vertices, "lod {lod}");
+        assert_eq!(data.uv_buffer.len(), data.vertex_buffer.len(), "lod {lod}");
+        assert_eq!(data.skirt_sources.len(), skirt_vertices, "lod {lod}");
+        assert_eq!(data.index_buffer.len(), surface_indices + skirt_indices, "lod {lod}");
+        assert!(data.index_buffer.iter().all(|&i| (i as usize) < data.vertex_buffer.len()), "lod {lod}");
+    }
+}
+
+#[test]
+fn vertices_sample_the_heightmap_on_the_lod_grid() {
+    let heights = chunk_heights();
+
+    for lod in LODS {
+        let data = ChunkData::from_heights(&heights, lod);
+        let columns = CHUNK_WIDTH / lod + 1;
+
+        for (i, vertex) in data.vertex_buffer.iter().take(columns * (CHUNK_HEIGHT / lod + 1)).enumerate() {
+            let (x, y) = ((i % columns) * lod, (i / columns) * lod);
+
+            assert_eq!(*vertex, [x as f32, heights[y * (CHUNK_WIDTH + 1) + x], y as f32], "lod {lod}, vertex {i}");
+            assert_eq!(data.uv_buffer[i], [x as f32 / CHUNK_WIDTH as f32, y as f32 / CHUNK_HEIGHT as f32], "lod {lod}, vertex {i}");
+        }
+    }
+}
+
+#[test]
+fn surface_faces_up_and_skirts_face_out() {
+    let heights = chunk_heights();
+    let centre = Vec3::new(CHUNK_WIDTH as f32 / 2.0, 0.0, CHUNK_HEIGHT as f32 / 2.0);
+
+    for lod in LODS {
+        let data = ChunkData::from_heights(&heights, lod);
+        let surface_indices = (CHUNK_WIDTH / lod) * (CHUNK_HEIGHT / lod) * 6;
+
+        for triangle in data.index_buffer[..surface_indices].chunks_exact(3) {
+            assert!(triangle_normal(&data, triangle).y > 0.0, "lod {lod}: surface triangle {triangle:?} faces down");
+        }
+
+        for triangle in data.index_buffer[surface_indices..].chunks_exact(3) {
+            let normal = triangle_normal(&data, triangle);
+            let position = Vec3::from(data.vertex_buffer[triangle[0] as usize]);
+            let outwards = (position - centre).with_y(0.0);
+
+            assert!(normal.y.abs() < 1e-3, "lod {lod}: skirt triangle {triangle:?} is not vertical");
+            assert!(normal.dot(outwards) > 0.0, "lod {lod}: skirt triangle {triangle:?} faces inwards");
+        }
+    }
+}
+
+#[test]
+fn skirts_reach_below_the_edge() {
+    let heights = chunk_heights();
+
+    for lod in LODS {
+        let data = ChunkData::from_heights(&heights, lod);
+        let skirt_start = data.vertex_buffer.len() - data.skirt_sources.len();
+
+        for (pair, sources) in data.vertex_buffer[skirt_start..].chunks_exact(2).zip(data.skirt_sources.chunks_exact(2)) {
+            let edge = data.vertex_buffer[sources[0] as usize];
+
+            assert_eq!(pair[0], edge, "lod {lod}");
+            assert!(pair[1][1] < edge[1], "lod {lod}: skirt at {edge:?} does not hang below the edge");
+        }
+    }
+}
+
+#[test]
+fn meshes_are_tangent_ready() {
+    let heights = chunk_heights();
+
+    for lod in LODS {
+        let mut mesh = ChunkData::from_heights(&heights, lod).into_mesh_with_normals();
+        assert!(mesh.generate_tangents().is_ok(), "lod {lod}");
+    }
+}
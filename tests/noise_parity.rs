@@ -0,0 +1,195 @@
+use bevy::tasks::block_on;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use terrain::{noise::{heightmap_backend::{BackendKind, HeightmapBackend}, perlin::Perlin, perlin_cpu::PerlinCPU}, terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH, MAP_HEIGHT, MAP_WIDTH}, terrain_config::{DomainWarp, HeightCurve, NoiseMode, TerrainGenConfig}}};
+
+const SEED: u64 = 1;
+const SCALE: f32 = 0.001;
+const SAMPLES: usize = 4096;
+const TOLERANCE: f32 = 1e-3;
+const BLEND: NoiseMode = NoiseMode::Blend { fbm: 1.0, ridged: 0.5, billow: 0.25, gain: 2.0 };
+
+fn config(backend: BackendKind) -> TerrainGenConfig {
+    TerrainGenConfig { seed: SEED, scale: SCALE, backend, ..Default::default() }
+}
+
+fn chunk_major_index(gx: usize, gy: usize) -> usize {
+    let chunk_index = (gy / CHUNK_HEIGHT) * MAP_WIDTH + gx / CHUNK_WIDTH;
+    let within_chunk_index = (gy % CHUNK_HEIGHT) * CHUNK_WIDTH + gx % CHUNK_WIDTH;
+
+    chunk_index * CHUNK_WIDTH * CHUNK_HEIGHT + within_chunk_index
+}
+
+#[test]
+fn cpu_chunkmap_matches_terrain_height() {
+    let perlin = PerlinCPU::new(SEED, SCALE, 4, 2.0, 0.5);
+    let chunkmap = perlin.compute_from_fractal();
+    let mut rng = StdRng::seed_from_u64(0);
+
+    assert_eq!(chunkmap.len(), MAP_WIDTH * CHUNK_WIDTH * MAP_HEIGHT * CHUNK_HEIGHT);
+
+    for _ in 0..SAMPLES {
+        let gx = rng.random_range(0..MAP_WIDTH * CHUNK_WIDTH);
+        let gy = rng.random_range(0..MAP_HEIGHT * CHUNK_HEIGHT);
+
+        assert_eq!(chunkmap[chunk_major_index(gx, gy)], perlin.terrain_height(gx as f32, gy as f32));
+    }
+}
+
+#[test]
+fn chunk_rectangles_are_chunk_major() {
+    let backend = block_on(HeightmapBackend::new(&config(BackendKind::Cpu))).unwrap();
+    let perlin = PerlinCPU::new(SEED, SCALE, 4, 2.0, 0.5);
+
+    let chunks = block_on(backend.compute_chunks((3, 5), (2, 3))).unwrap();
+    let mut rng = StdRng::seed_from_u64(3);
+
+    assert_eq!(chunks.len(), 6 * CHUNK_WIDTH * CHUNK_HEIGHT);
+
+    for _ in 0..SAMPLES {
+        let gx = rng.random_range(0..2 * CHUNK_WIDTH);
+        let gy = rng.random_range(0..3 * CHUNK_HEIGHT);
+        let chunk_index = (gy / CHUNK_HEIGHT) * 2 + gx / CHUNK_WIDTH;
+        let index = chunk_index * CHUNK_WIDTH * CHUNK_HEIGHT + (gy % CHUNK_HEIGHT) * CHUNK_WIDTH + gx % CHUNK_WIDTH;
+
+        let expected = perlin.terrain_height((3 * CHUNK_WIDTH + gx) as f32, (5 * CHUNK_HEIGHT + gy) as f32);
+        assert_eq!(chunks[index], expected);
+    }
+}
+
+#[test]
+fn gpu_regions_match_cpu() {
+    let Ok(gpu) = block_on(Perlin::new(&config(BackendKind::Gpu))) else {
+        eprintln!("no GPU adapter available, skipping GPU/CPU parity check");
+        return;
+    };
+    let cpu = PerlinCPU::from_config(&config(BackendKind::Cpu));
+
+    for (origin, size) in [((0, 0), (512, 512)), ((-1500, -700), (333, 257)), ((-64, 4000), (129, 129))] {
+        let expected = cpu.compute_region(origin, size);
+        let actual = block_on(gpu.compute_region(origin, size)).unwrap();
+
+        assert_eq!(expected.len(), actual.len());
+        for (i, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+            let (x, y) = (origin.0 + (i as u32 % size.0) as i32, origin.1 + (i as u32 / size.0) as i32);
+            assert!((expected - actual).abs() <= TOLERANCE * expected.abs().max(1.0), "({x}, {y}): cpu {expected}, gpu {actual}");
+        }
+    }
+}
+
+#[test]
+fn gpu_chunk_matches_cpu() {
+    let Ok(gpu) = block_on(Perlin::new(&config(BackendKind::Gpu))) else {
+        eprintln!("no GPU adapter available, skipping GPU/CPU parity check");
+        return;
+    };
+    let cpu = PerlinCPU::from_config(&config(BackendKind::Cpu));
+
+    for coordinates in [(0, 0), (-1, -1), (17, -4)] {
+        let expected = cpu.compute_chunk(coordinates);
+        let actual = block_on(gpu.compute_chunk(coordinates)).unwrap();
+
+        assert_eq!(actual.len(), (CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1));
+        for (expected, actual) in expected.iter().zip(&actual) {
+            assert!((expected - actual).abs() <= TOLERANCE * expected.abs().max(1.0));
+        }
+    }
+}
+
+#[test]
+fn gpu_matches_cpu_for_custom_config() {
+    let config = TerrainGenConfig {
+        seed: 42,
+        scale: 0.0037,
+        octaves: 6,
+        lacunarity: 2.3,
+        persistence: 0.45,
+        height_curve: HeightCurve { offset: 1.2, exponent: 3, amplitude: 55.0 },
+        ..config(BackendKind::Gpu)
+    };
+    let Ok(gpu) = block_on(Perlin::new(&config)) else {
+        eprintln!("no GPU adapter available, skipping GPU/CPU parity check");
+        return;
+    };
+    let cpu = PerlinCPU::from_config(&config);
+
+    let expected = cpu.compute_region((-300, 200), (256, 256));
+    let actual = block_on(gpu.compute_region((-300, 200), (256, 256))).unwrap();
+    for (expected, actual) in expected.iter().zip(&actual) {
+        assert!((expected - actual).abs() <= TOLERANCE * expected.abs().max(1.0), "cpu {expected}, gpu {actual}");
+    }
+}
+
+#[test]
+fn gpu_matches_cpu_for_every_noise_mode() {
+    let Ok(probe) = block_on(Perlin::new(&config(BackendKind::Gpu))) else {
+        eprintln!("no GPU adapter available, skipping GPU/CPU parity check");
+        return;
+    };
+    drop(probe);
+
+    let modes = [NoiseMode::Fbm, NoiseMode::Ridged { gain: 2.0 }, NoiseMode::Billow, BLEND];
+    let warps = [DomainWarp::default(), DomainWarp { strength: 4.0, frequency: 0.5 }];
+
+    for noise_mode in modes {
+        for warp in warps {
+            let config = TerrainGenConfig { noise_mode, warp, ..config(BackendKind::Gpu) };
+            let gpu = block_on(Perlin::new(&config)).unwrap();
+            let cpu = PerlinCPU::from_config(&config);
+
+            let expected = cpu.compute_region((-200, -900), (160, 160));
+            let actual = block_on(gpu.compute_region((-200, -900), (160, 160))).unwrap();
+            for (expected, actual) in expected.iter().zip(&actual) {
+                assert!((expected - actual).abs() <= TOLERANCE * expected.abs().max(1.0), "{noise_mode:?} {warp:?}: cpu {expected}, gpu {actual}");
+            }
+        }
+    }
+}
+
+#[test]
+fn noise_modes_stay_in_range() {
+    let mut rng = StdRng::seed_from_u64(4);
+
+    for noise_mode in [NoiseMode::Fbm, NoiseMode::Ridged { gain: 2.0 }, NoiseMode::Billow, BLEND] {
+        let perlin = PerlinCPU::from_config(&TerrainGenConfig { noise_mode, warp: DomainWarp { strength: 4.0, frequency: 0.5 }, ..config(BackendKind::Cpu) });
+
+        for _ in 0..SAMPLES {
+            let x = rng.random_range(-100.0..100.0);
+            let y = rng.random_range(-100.0..100.0);
+            let noise = perlin.from_mode(x, y);
+            assert!((-1.0..=1.0).contains(&noise), "{noise_mode:?} out of range at ({x}, {y}): {noise}");
+        }
+    }
+}
+
+#[test]
+fn gradients_wrap_for_negative_lattice_points() {
+    let perlin = PerlinCPU::new(SEED, SCALE, 4, 2.0, 0.5);
+
+    for y in -512..512 {
+        assert_eq!(perlin.from_point(-1, y), perlin.from_point(255, y));
+        assert_eq!(perlin.from_point(-256, y), perlin.from_point(0, y));
+    }
+}
+
+#[test]
+fn terrain_height_is_continuous_across_origin() {
+    let perlin = PerlinCPU::new(SEED, SCALE, 4, 2.0, 0.5);
+    let mut rng = StdRng::seed_from_u64(2);
+
+    for _ in 0..SAMPLES {
+        let x = rng.random_range(-5000.0..5000.0);
+        let z = rng.random_range(-5000.0..5000.0);
+
+        for (dx, dz) in [(0.01, 0.0), (0.0, 0.01)] {
+            let a = perlin.terrain_height(x, z);
+            let b = perlin.terrain_height(x + dx, z + dz);
+            assert!((a - b).abs() < 0.5, "discontinuity at ({x}, {z}): {a} vs {b}");
+        }
+    }
+
+    // Lattice points have zero noise regardless of sign.
+    for x in -8..8 {
+        let lattice = x as f32 / SCALE;
+        assert_eq!(perlin.terrain_height(lattice, lattice), HeightCurve::default().apply(0.0));
+    }
+}
@@ -0,0 +1,41 @@
+use bevy::math::Vec3;
+use terrain::terrain::{chunks::{Chunkbase, CHUNK_HEIGHT, CHUNK_WIDTH}, occlusion::occluded_chunks};
+
+mod common;
+
+/// Chunks `(0, 0)` to `(3, 0)` at flat heights, the eye in the middle of the first one.
+fn row(heights: [f32; 4]) -> Chunkbase {
+    let mut chunkbase = Chunkbase::new(common::backend(), 16);
+    for (x, height) in heights.into_iter().enumerate() {
+        common::insert(&mut chunkbase, (x as i32, 0), &vec![height; (CHUNK_WIDTH + 1) * (CHUNK_HEIGHT + 1)]);
+    }
+    chunkbase
+}
+
+fn eye() -> Vec3 {
+    Vec3::new(CHUNK_WIDTH as f32 / 2.0, 2.0, CHUNK_HEIGHT as f32 / 2.0)
+}
+
+const AREA: [(i32, i32); 4] = [(0, 0), (1, 0), (2, 0), (3, 0)];
+
+#[test]
+fn wall_hides_the_chunk_behind_it() {
+    //Walls only hide what lies past their far corner, the chunk right behind is too close
+    let chunkbase = row([0.0, 100.0, 0.0, 0.0]);
+    let occluded = occluded_chunks(&chunkbase, eye(), AREA);
+    assert!(occluded.contains(&(3, 0)));
+    assert!(!occluded.contains(&(1, 0)));
+}
+
+#[test]
+fn wall_does_not_hide_a_taller_chunk() {
+    let chunkbase = row([0.0, 100.0, 0.0, 500.0]);
+    let occluded = occluded_chunks(&chunkbase, eye(), AREA);
+    assert!(!occluded.contains(&(3, 0)));
+}
+
+#[test]
+fn flat_ground_hides_nothing() {
+    let chunkbase = row([0.0; 4]);
+    assert!(occluded_chunks(&chunkbase, eye(), AREA).is_empty());
+}
@@ -0,0 +1,59 @@
+use bevy::math::Vec2;
+use terrain::noise::poisson_disc::PoissonDisc;
+
+const SIZE: Vec2 = Vec2::new(100.0, 100.0);
+
+fn min_distance(points: &[Vec2]) -> f32 {
+    let mut min = f32::MAX;
+    for (i, a) in points.iter().enumerate() {
+        for b in &points[i + 1..] {
+            min = min.min(a.distance(*b));
+        }
+    }
+    min
+}
+
+#[test]
+fn same_seed_same_points() {
+    let a = PoissonDisc::new(5.0, SIZE, 20).with_seed(3).generate_points().clone();
+    let b = PoissonDisc::new(5.0, SIZE, 20).with_seed(3).generate_points().clone();
+    let c = PoissonDisc::new(5.0, SIZE, 20).with_seed(4).generate_points().clone();
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn constant_radius_keeps_min_distance() {
+    let points = PoissonDisc::new(5.0, SIZE, 20).with_seed(3).generate_points().clone();
+    assert!(points.len() > 100);
+    assert!(min_distance(&points) >= 5.0);
+}
+
+#[test]
+fn variable_radius_keeps_min_distance() {
+    //2 on the left edge up to 8 on the right
+    let mut poisson = PoissonDisc::new(2.0, SIZE, 20).with_seed(1).with_variable_radius(8.0, |point| 2.0 + point.x / SIZE.x * 6.0);
+    let points = poisson.generate_points().clone();
+    let radii = poisson.radii();
+
+    for (i, a) in points.iter().enumerate() {
+        for (j, b) in points.iter().enumerate().skip(i + 1) {
+            assert!(a.distance(*b) >= radii[i].max(radii[j]) - 1e-4, "{a} and {b} closer than their radii");
+        }
+    }
+
+    let left = points.iter().filter(|point| point.x < SIZE.x / 2.0).count();
+    assert!(left > (points.len() - left) * 2);
+}
+
+#[test]
+fn wrapped_tiles_keep_min_distance() {
+    let size = Vec2::new(37.0, 53.0);
+    let tile = PoissonDisc::new(4.0, size, 30).with_seed(9).with_wrapping().generate_points().clone();
+
+    let tiled: Vec<Vec2> = (0..3)
+        .flat_map(|y| (0..3).map(move |x| Vec2::new(x as f32, y as f32) * size))
+        .flat_map(|offset| tile.iter().map(move |point| *point + offset))
+        .collect();
+    assert!(min_distance(&tiled) >= 4.0 - 1e-4);
+}
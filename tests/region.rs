@@ -0,0 +1,65 @@
+use std::{fs, path::PathBuf};
+
+use terrain::terrain::{chunks::{CHUNK_HEIGHT, CHUNK_WIDTH}, region::{RegionCache, REGION_SIZE}, terrain_config::TerrainGenConfig};
+
+mod common;
+
+const STRIDE: usize = CHUNK_WIDTH + 1;
+
+/// An empty cache directory of its own for each test.
+fn directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("region-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+}
+
+/// Stores `coordinates` fresh and loads them back through a cache opened again, as on the next launch.
+fn round_trip(directory: &PathBuf, coordinates: &[(i32, i32)]) -> Vec<Vec<f32>> {
+    let config = TerrainGenConfig::default();
+    let perlin = common::perlin();
+
+    let cache = RegionCache::open(directory, &config).unwrap();
+    for &chunk in coordinates {
+        cache.store(chunk, &perlin.compute_chunk(chunk)).unwrap();
+    }
+    drop(cache);
+
+    let cache = RegionCache::open(directory, &config).unwrap();
+    coordinates.iter().map(|&chunk| cache.load(chunk).unwrap().expect("chunk was stored")).collect()
+}
+
+#[test]
+fn neighbours_share_their_edges_exactly() {
+    let directory = directory("edges");
+    //The last pair straddles two region files
+    let chunks = round_trip(&directory, &[(0, 0), (1, 0), (0, 1), (REGION_SIZE - 1, 0), (REGION_SIZE, 0)]);
+
+    for (left, right) in [(&chunks[0], &chunks[1]), (&chunks[3], &chunks[4])] {
+        for y in 0..=CHUNK_HEIGHT {
+            assert_eq!(left[y * STRIDE + CHUNK_WIDTH], right[y * STRIDE], "row {y}");
+        }
+    }
+    for x in 0..=CHUNK_WIDTH {
+        assert_eq!(chunks[0][CHUNK_HEIGHT * STRIDE + x], chunks[2][x], "column {x}");
+    }
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn store_returns_what_load_reads() {
+    let directory = directory("store");
+    let config = TerrainGenConfig::default();
+    let heights = common::perlin().compute_chunk((-3, 5));
+
+    let cache = RegionCache::open(&directory, &config).unwrap();
+    let stored = cache.store((-3, 5), &heights).unwrap();
+    assert_eq!(cache.load((-3, 5)).unwrap(), Some(stored.clone()));
+    assert_eq!(cache.load((-2, 5)).unwrap(), None);
+
+    let error = stored.iter().zip(&heights).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+    let range = heights.iter().copied().fold(f32::MIN, f32::max) - heights.iter().copied().fold(f32::MAX, f32::min);
+    assert!(error <= range / u16::MAX as f32, "quantisation error {error} over a range of {range}");
+
+    fs::remove_dir_all(directory).unwrap();
+}
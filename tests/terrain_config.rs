@@ -0,0 +1,61 @@
+use terrain::{noise::heightmap_backend::BackendKind, terrain::terrain_config::{NoiseMode, TerrainGenConfig, TERRAIN_CONFIG_PATH}};
+
+type Tweak = fn(&mut TerrainGenConfig);
+
+#[test]
+fn generation_hash_counts_only_what_the_cached_heights_depend_on() {
+    let base = TerrainGenConfig::default().generation_hash();
+    let hash = |tweak: Tweak| {
+        let mut config = TerrainGenConfig::default();
+        tweak(&mut config);
+        config.generation_hash()
+    };
+
+    let counted: [(&str, Tweak); 9] = [
+        ("seed", |config| config.seed += 1),
+        ("scale", |config| config.scale *= 2.0),
+        ("octaves", |config| config.octaves += 1),
+        ("lacunarity", |config| config.lacunarity += 0.1),
+        ("persistence", |config| config.persistence += 0.1),
+        ("noise_mode", |config| config.noise_mode = NoiseMode::Billow),
+        ("warp", |config| config.warp.strength = 10.0),
+        ("height_curve", |config| config.height_curve.amplitude += 1.0),
+        ("erosion", |config| config.erosion.enabled = !config.erosion.enabled),
+    ];
+    for (field, tweak) in counted {
+        assert_ne!(hash(tweak), base, "{field} should change the hash");
+    }
+
+    let ignored: [(&str, Tweak); 5] = [
+        ("sea_level", |config| config.sea_level += 5.0),
+        ("biomes", |config| config.biomes.lapse_rate *= 2.0),
+        ("water", |config| config.water.lake_min_area += 1),
+        ("colliders", |config| config.colliders.far_step = 4),
+        ("backend", |config| config.backend = BackendKind::Cpu),
+    ];
+    for (field, tweak) in ignored {
+        assert_eq!(hash(tweak), base, "{field} should not change the hash");
+    }
+}
+
+#[test]
+fn partial_nested_settings_keep_their_defaults() {
+    let config: TerrainGenConfig = serde_json::from_str(r#"{ "seed": 7, "erosion": { "enabled": true, "hydraulic": { "batches": 4 } }, "water": { "river_width": 3.0 } }"#).unwrap();
+    let default = TerrainGenConfig::default();
+
+    assert_eq!(config.seed, 7);
+    assert!(config.erosion.enabled);
+    assert_eq!(config.erosion.hydraulic.batches, 4);
+    assert_eq!(config.erosion.hydraulic.max_lifetime, default.erosion.hydraulic.max_lifetime);
+    assert_eq!(config.erosion.region_size, default.erosion.region_size);
+    assert_eq!(config.erosion.thermal.talus, default.erosion.thermal.talus);
+    assert_eq!(config.water.river_width, 3.0);
+    assert_eq!(config.water.lake_min_area, default.water.lake_min_area);
+    assert_eq!(config.biomes.lapse_rate, default.biomes.lapse_rate);
+}
+
+#[test]
+fn shipped_config_loads_with_erosion_off_like_the_default() {
+    let config = TerrainGenConfig::load(TERRAIN_CONFIG_PATH).unwrap();
+    assert_eq!(config.erosion.enabled, TerrainGenConfig::default().erosion.enabled);
+}
@@ -0,0 +1,33 @@
+use bevy::math::{Quat, Vec3};
+use terrain::terrain::props::trees::tree::{Tree, TreeSpecies};
+
+const SPECIES: [TreeSpecies; 2] = [TreeSpecies::Oak, TreeSpecies::Pine];
+
+#[test]
+fn grow_is_deterministic_per_seed() {
+    for species in SPECIES {
+        let a = Tree::grow(5, species.species());
+        let b = Tree::grow(5, species.species());
+        assert_eq!(a.segments, b.segments);
+        assert_eq!(a.leaves, b.leaves);
+        assert_ne!(a.segments, Tree::grow(6, species.species()).segments);
+    }
+}
+
+#[test]
+fn collider_covers_only_the_trunk() {
+    for species in SPECIES {
+        let tree = Tree::grow(5, species.species());
+        let collider = tree.collider();
+        let trunk = tree.segments.iter().filter(|segment| segment.level == 0).count();
+        assert_eq!(collider.as_compound().unwrap().shapes().count(), trunk);
+
+        let contains = |point: Vec3| collider.contains_point(Vec3::ZERO, Quat::IDENTITY, point);
+        for segment in &tree.segments {
+            match segment.level {
+                0 => assert!(contains(segment.start.lerp(segment.end, 0.5)), "{species:?} trunk left out"),
+                _ => assert!(!contains(segment.end), "{species:?} branch tip at {} collides", segment.end),
+            }
+        }
+    }
+}
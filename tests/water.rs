@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, TaskPool}};
+use common::chunk_heights;
+use terrain::terrain::{chunks::{Chunkbase, CHUNK_WIDTH}, terrain_config::TerrainGenConfig, water::{LakeFinder, Water}};
+
+mod common;
+
+const STRIDE: usize = CHUNK_WIDTH + 1;
+
+fn config() -> TerrainGenConfig {
+    TerrainGenConfig { sea_level: 0.0, ..default() }
+}
+
+/// Flat ground 10 m up with a bowl 20 samples across sunk into it around `centre`.
+fn bowl(centre: (i32, i32)) -> impl Fn(i32, i32) -> f32 {
+    move |x, z| {
+        let distance = (((x - centre.0).pow(2) + (z - centre.1).pow(2)) as f32).sqrt();
+        (distance / 2.0).min(10.0)
+    }
+}
+
+/// Loads `chunks` shaped by `ground` into a chunkbase looking for lakes.
+fn chunkbase(chunks: impl IntoIterator<Item = (i32, i32)>, ground: impl Fn(i32, i32) -> f32) -> Chunkbase {
+    AsyncComputeTaskPool::get_or_init(TaskPool::default);
+    let mut chunkbase = Chunkbase::new(common::backend(), 64).with_lakes(LakeFinder::new(&config()));
+    common::load(&mut chunkbase, chunks, ground);
+    chunkbase
+}
+
+fn water() -> Water {
+    let mut world = World::new();
+    world.insert_resource(config());
+    world.init_resource::<Assets<StandardMaterial>>();
+    Water::from_world(&mut world)
+}
+
+#[test]
+fn bowl_inside_a_chunk_fills_to_its_rim() {
+    let lakes = LakeFinder::new(&config()).find(&chunk_heights((0, 0), &bowl((64, 64))));
+    assert_eq!(lakes.len(), 1);
+    assert_eq!(lakes[0].surface, 10.0);
+    assert!(lakes[0].contains(64 * STRIDE + 64));
+    assert!(!lakes[0].contains(64 * STRIDE + 90));
+}
+
+#[test]
+fn bowl_on_a_border_drains_within_one_chunk() {
+    let lakes = LakeFinder::new(&config()).find(&chunk_heights((0, 0), &bowl((CHUNK_WIDTH as i32, 64))));
+    assert!(lakes.iter().all(|lake| !lake.contains(64 * STRIDE + CHUNK_WIDTH - 2)));
+}
+
+#[test]
+fn bowl_on_a_border_fills_once_the_neighbours_load() {
+    //Every neighbour of (0, 0) and (1, 0)
+    let chunks = (-1..=1).flat_map(|y| (-1..=2).map(move |x| (x, y)));
+    let mut chunkbase = chunkbase(chunks, bowl((CHUNK_WIDTH as i32, 64)));
+    let mut meshes = Assets::<Mesh>::default();
+
+    let start = Instant::now();
+    while [(0, 0), (1, 0)].iter().any(|coordinates| chunkbase.get_chunk(coordinates).unwrap().lake_revision == 0) {
+        assert!(start.elapsed() < Duration::from_secs(30), "lakes were never flooded");
+        chunkbase.poll_tasks(&mut meshes);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let border = CHUNK_WIDTH as i32;
+    for sample in [(border - 2, 64), (border, 64), (border + 2, 64)] {
+        assert_eq!(chunkbase.lake_at(sample).map(|lake| lake.surface), Some(10.0), "{sample:?}");
+    }
+}
+
+#[test]
+fn river_runs_down_to_the_sea() {
+    let chunkbase = chunkbase([(0, 0)], |x, _| 100.0 - x as f32);
+    let river = water().trace_river(&chunkbase, (10, 64)).unwrap();
+
+    assert_eq!(river.frontier, None);
+    assert!(river.path.windows(2).all(|pair| pair[1].y < pair[0].y));
+    assert!(river.path.last().unwrap().y <= config().sea_level + 1.0);
+}
+
+#[test]
+fn river_stops_at_the_first_unloaded_chunk() {
+    let chunkbase = chunkbase([(0, 0)], |x, _| 200.0 - x as f32 * 0.5);
+    let river = water().trace_river(&chunkbase, (10, 64)).unwrap();
+
+    assert_eq!(river.frontier, Some((1, 0)));
+    assert_eq!(river.chunks(), vec![(0, 0)]);
+}